@@ -1,19 +1,61 @@
 use std::borrow::Cow;
+use std::collections::{BTreeMap, HashMap};
 use std::future::Future;
-use std::sync::Arc;
+use std::num::{NonZeroU32, NonZeroUsize};
+use std::pin::pin;
+use std::str::FromStr;
+use std::sync::{Arc, Mutex};
+use std::time::Duration;
 
-use futures_util::stream::{Stream, StreamExt as _};
+use async_stream::{stream, try_stream};
+use futures_util::FutureExt as _;
+use futures_util::stream::{FuturesUnordered, Stream, StreamExt as _};
 use reqwest::StatusCode;
+use serde::de::DeserializeOwned;
 use serde::{Deserialize, Serialize};
-use tracing::{debug, instrument};
+use tokio::sync::OnceCell;
+use tracing::{debug, instrument, warn};
 use url::Url;
 
-use crate::cache::BitlinkCache;
-use crate::cli::Ordering;
+use crate::cache::{BitlinkCache, Cached};
+use crate::cassette::Cassette;
+use crate::cli::{Ordering, TimeUnit, Toggle};
 use crate::config::Config;
-use crate::error::{Error, Result};
+use crate::error::{Error, RawResponse, Result};
+use crate::limits::{Concurrency, Quota, RateLimiter};
+use crate::retry::RetryPolicy;
 
-const VERSION: &str = "v4";
+pub(crate) const VERSION: &str = "v4";
+
+/// Domain of bitlinks created without an explicit one
+const DEFAULT_DOMAIN: &str = "bit.ly";
+
+/// Cache key of the default group GUID of the authenticated user
+const DEFAULT_GROUP_GUID: &str = "user.default_group_guid";
+
+/// How long a cached default group GUID is trusted before it's fetched again
+const DEFAULT_GROUP_GUID_TTL: Duration = Duration::from_secs(24 * 60 * 60);
+
+/// Cache key of the number of groups of the user, set whenever all of them have been cached
+const GROUPS: &str = "user.groups";
+
+/// How long the cached groups are trusted to be all the groups of the user
+const GROUPS_TTL: Duration = Duration::from_secs(24 * 60 * 60);
+
+/// Maximum number of API requests per second, unless configured otherwise (see [`rate_limit`])
+const DEFAULT_RATE_LIMIT: u32 = 10;
+
+/// Endpoint path whose monthly limit applies to creating new bitlinks
+const SHORTEN_LIMIT_PATH: &str = "/v4/shorten";
+
+/// Cache key of the monthly limit of created bitlinks
+const SHORTEN_LIMIT: &str = "user.platform_limits.shorten";
+
+/// How long a cached shorten limit (and its usage count) is trusted before it's fetched again
+///
+/// Bitlinks created in the meantime are added to the cached usage count, but those created by
+/// other clients are not accounted for until it's fetched again.
+const SHORTEN_LIMIT_TTL: Duration = Duration::from_secs(10 * 60);
 
 /// API request to get user info
 ///
@@ -24,8 +66,86 @@ pub struct User {
     pub default_group_guid: String,
 }
 
+/// Group of bitlinks (and users) within an organization
+///
+/// <https://dev.bitly.com/api-reference/#getGroups>
+#[derive(Clone, Debug, Deserialize, Serialize, PartialEq, Eq)]
+pub struct Group {
+    pub guid: String,
+    pub name: String,
+    #[serde(default)]
+    pub organization_guid: Option<String>,
+    #[serde(default)]
+    pub is_active: bool,
+    #[serde(default)]
+    pub role: Option<String>,
+}
+
+impl std::fmt::Display for Group {
+    #[inline]
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        let organization_guid = self.organization_guid.as_deref().unwrap_or_default();
+        write!(f, "{}\t{}\t{organization_guid}", self.guid, self.name)
+    }
+}
+
+#[derive(Debug, Deserialize)]
+struct Groups {
+    groups: Vec<Group>,
+}
+
+/// <https://dev.bitly.com/api-reference/#getOrganizations>
+#[derive(Clone, Debug, Deserialize, Serialize, PartialEq, Eq)]
+pub struct Organization {
+    pub guid: String,
+    pub name: String,
+    #[serde(default)]
+    pub is_active: bool,
+    #[serde(default)]
+    pub tier: Option<String>,
+    #[serde(default)]
+    pub role: Option<String>,
+}
+
+impl std::fmt::Display for Organization {
+    #[inline]
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        let tier = self.tier.as_deref().unwrap_or_default();
+        write!(f, "{}\t{}\t{tier}", self.guid, self.name)
+    }
+}
+
+#[derive(Debug, Deserialize)]
+struct Organizations {
+    organizations: Vec<Organization>,
+}
+
+/// Monthly limit of requests to an API endpoint and the current usage
+///
+/// <https://dev.bitly.com/api-reference/#getPlatformLimits>
+#[derive(Clone, Debug, Deserialize, Serialize, PartialEq, Eq)]
+struct PlatformLimit {
+    endpoint: String,
+    methods: Vec<MethodLimit>,
+}
+
+#[derive(Clone, Debug, Deserialize, Serialize, PartialEq, Eq)]
+struct MethodLimit {
+    name: String,
+    limit: u64,
+    count: u64,
+}
+
+#[derive(Debug, Deserialize)]
+struct PlatformLimits {
+    platform_limits: Vec<PlatformLimit>,
+}
+
 /// API request to create a bitlink
 ///
+/// Plain requests (just `long_url`, `domain` and `group_guid`) are sent to the `shorten` endpoint,
+/// while requests with any of the additional attributes use the full `bitlinks` endpoint.
+///
 /// <https://dev.bitly.com/api-reference/#createBitlink>
 #[derive(Serialize)]
 pub struct Shorten<'a> {
@@ -33,6 +153,71 @@ pub struct Shorten<'a> {
     #[serde(skip_serializing_if = "Option::is_none")]
     pub domain: Option<Cow<'a, str>>,
     pub group_guid: Cow<'a, str>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub title: Option<Cow<'a, str>>,
+    #[serde(skip_serializing_if = "<[_]>::is_empty")]
+    pub tags: Cow<'a, [String]>,
+    #[serde(skip_serializing_if = "<[_]>::is_empty")]
+    pub deeplinks: Cow<'a, [Deeplink]>,
+    /// Custom back-half attached to the bitlink after it's been created
+    #[serde(skip)]
+    pub keyword: Option<Cow<'a, str>>,
+}
+
+impl Shorten<'_> {
+    /// Key identifying requests that would create the same bitlink
+    pub fn key(&self) -> String {
+        let keyword = self.keyword.as_deref().unwrap_or_default();
+        let payload = serde_json::to_string(self).expect("serializable shorten payload");
+        format!("{payload} {keyword}")
+    }
+
+    /// Borrow this request without the custom `keyword`
+    pub fn without_keyword(&self) -> Shorten<'_> {
+        Shorten {
+            long_url: self.long_url.clone(),
+            domain: self.domain.as_deref().map(Cow::Borrowed),
+            group_guid: Cow::Borrowed(&self.group_guid),
+            title: self.title.as_deref().map(Cow::Borrowed),
+            tags: Cow::Borrowed(&self.tags),
+            deeplinks: Cow::Borrowed(&self.deeplinks),
+            keyword: None,
+        }
+    }
+
+    /// Returns `true` if this request sets any attributes beyond the plain `shorten` endpoint
+    #[inline]
+    pub fn is_extended(&self) -> bool {
+        self.title.is_some() || !self.tags.is_empty() || !self.deeplinks.is_empty()
+    }
+
+    #[inline]
+    fn endpoint(&self) -> CreateEndpoint {
+        if self.is_extended() {
+            CreateEndpoint::Bitlinks
+        } else {
+            CreateEndpoint::Shorten
+        }
+    }
+}
+
+/// Endpoint that a [`Shorten`] request is sent to
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+enum CreateEndpoint {
+    /// Plain `shorten` endpoint, which is subject to the monthly quota of created bitlinks
+    Shorten,
+    /// Full `bitlinks` endpoint (with a limit of its own)
+    Bitlinks,
+}
+
+impl CreateEndpoint {
+    #[inline]
+    fn path(self) -> &'static str {
+        match self {
+            Self::Shorten => "shorten",
+            Self::Bitlinks => "bitlinks",
+        }
+    }
 }
 
 impl std::fmt::Debug for Shorten<'_> {
@@ -42,454 +227,4393 @@ impl std::fmt::Debug for Shorten<'_> {
             .field("long_url", &self.long_url.as_str())
             .field("domain", &self.domain)
             .field("group_guid", &self.group_guid.as_ref())
+            .field("title", &self.title)
+            .field("tags", &self.tags)
+            .field("deeplinks", &self.deeplinks)
+            .field("keyword", &self.keyword)
             .finish()
     }
 }
 
-#[derive(Clone, Debug, Deserialize, PartialEq, Eq)]
-pub struct Bitlink {
-    pub link: Url,
-    pub id: String,
-    pub long_url: Url,
+/// Optional attributes of newly created bitlinks
+#[derive(Clone, Debug, Default, Deserialize, Serialize)]
+pub struct BitlinkFields {
+    pub title: Option<String>,
+    pub tags: Vec<String>,
+    pub deeplinks: Vec<Deeplink>,
+    pub keyword: Option<String>,
 }
 
-impl std::fmt::Display for Bitlink {
-    #[inline]
-    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
-        write!(f, "{}", self.link)
-    }
+/// API request to add a custom bitlink (keyword) to an existing bitlink
+///
+/// <https://dev.bitly.com/api-reference/#addCustomBitlink>
+#[derive(Debug, Serialize)]
+pub struct AddCustomBitlink<'a> {
+    pub custom_bitlink: Cow<'a, str>,
+    pub bitlink_id: Cow<'a, str>,
 }
 
-macro_rules! parse_response {
-    ($resp:expr => $ok:ident $(| $oks:ident)* || $err:ident $(| $errs:ident)*) => {{
-        let resp = $resp;
-        match resp.status() {
-            StatusCode::$ok $(| StatusCode::$oks)* => match resp.json().await {
-                Ok(resp) => Ok(resp),
-                Err(err) => panic!("API violation: invalid response {err:?}"),
-            },
-
-            StatusCode::$err $(| StatusCode::$errs)* => match resp.json().await {
-                Ok(resp) => Err(Error::Bitly(resp)),
-                Err(err) => panic!("API violation: invalid error response {err:?}"),
-            },
-
-            code => unreachable!("API violation: unexpected status code '{code}'"),
-        }}
-    };
+/// Request that would be sent to the API (see [`Plan`])
+#[derive(Debug, Serialize)]
+pub struct PlannedRequest {
+    pub method: &'static str,
+    pub url: Url,
+    pub payload: serde_json::Value,
 }
 
-fn api_url(base: &Url, endpoint: &str) -> Url {
-    let mut api_url = base.clone();
-    api_url.set_path(&format!("{VERSION}/{endpoint}"));
-    api_url
+impl PlannedRequest {
+    fn new(method: &'static str, url: Url, payload: &impl Serialize) -> Result<Self> {
+        Ok(Self {
+            method,
+            url,
+            payload: serde_json::to_value(payload)?,
+        })
+    }
 }
 
-struct ClientInner {
-    cfg: Config,
-    http: Option<reqwest::Client>,
-    cache: Option<BitlinkCache>,
+/// How an input would be processed by a mutating command, which is printed under `--dry-run`
+/// instead of sending any request that changes something
+#[derive(Debug, Serialize)]
+pub struct Plan {
+    pub input: String,
+    /// The result that would be served from the local cache (if any)
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub cached: Option<Url>,
+    /// Whether the cached result is stale, i.e. it would be revalidated first (see `--cache-max-age`)
+    #[serde(skip_serializing_if = "std::ops::Not::not")]
+    pub stale: bool,
+    /// Requests that would be sent to the API (in this order)
+    pub requests: Vec<PlannedRequest>,
 }
 
-impl ClientInner {
+impl Plan {
     #[inline]
-    fn api_url(&self, endpoint: &str) -> Url {
-        api_url(&self.cfg.api_url, endpoint)
+    fn cached(input: String, link: Url) -> Self {
+        Self {
+            input,
+            cached: Some(link),
+            stale: false,
+            requests: Vec::new(),
+        }
     }
 
-    #[instrument(level = "debug", skip(self))]
-    async fn fetch_user(&self) -> Result<User> {
-        let Some(ref http) = self.http else {
-            return Err(Error::Offline("user"));
-        };
+    /// Plan to revalidate a stale cached result with given request
+    ///
+    /// NOTE: If the cached result turns out to be outdated, then it'd be created again instead.
+    #[inline]
+    fn stale(input: String, link: Url, revalidate: PlannedRequest) -> Self {
+        Self {
+            input,
+            cached: Some(link),
+            stale: true,
+            requests: vec![revalidate],
+        }
+    }
 
-        let endpoint = self.api_url("user");
+    #[inline]
+    fn requests(input: String, requests: Vec<PlannedRequest>) -> Self {
+        Self {
+            input,
+            cached: None,
+            stale: false,
+            requests,
+        }
+    }
+}
 
-        debug!("fetching user info");
-        let resp = http
-            .get(endpoint)
-            .bearer_auth(self.cfg.api_token())
-            .send()
-            .await?;
+impl std::fmt::Display for Plan {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self.cached {
+            Some(ref link) if self.stale => write!(f, "{}\tstale\t{link}", self.input)?,
+            Some(ref link) => write!(f, "{}\tcached\t{link}", self.input)?,
+            None => write!(f, "{}\trequest", self.input)?,
+        }
 
-        parse_response! { resp =>
-            OK
-            ||
-            FORBIDDEN
-            | GONE
-            | NOT_FOUND
-            | INTERNAL_SERVER_ERROR
-            | SERVICE_UNAVAILABLE
+        for PlannedRequest {
+            method,
+            url,
+            payload,
+        } in &self.requests
+        {
+            write!(f, "\n  {method} {url}")?;
+
+            if !payload.is_null() {
+                write!(f, " {payload}")?;
+            }
+        }
+
+        Ok(())
+    }
+}
+
+/// API response to adding a custom bitlink
+///
+/// <https://dev.bitly.com/api-reference/#addCustomBitlink>
+#[derive(Debug, Deserialize)]
+pub struct CustomBitlink {
+    pub custom_bitlink: BitlinkRef,
+    pub bitlink: Bitlink,
+}
+
+impl From<CustomBitlink> for Bitlink {
+    #[inline]
+    fn from(
+        CustomBitlink {
+            custom_bitlink,
+            bitlink,
+        }: CustomBitlink,
+    ) -> Self {
+        Self {
+            id: custom_bitlink.id(),
+            link: custom_bitlink.0,
+            long_url: bitlink.long_url,
         }
     }
+}
 
-    #[instrument(level = "debug", fields(%long_url), skip_all)]
-    async fn shorten(&self, long_url: Url) -> Result<Bitlink> {
-        debug!("shortening URL");
+/// API request to expand a bitlink
+///
+/// <https://dev.bitly.com/api-reference/#expandBitlink>
+#[derive(Debug, Serialize)]
+pub struct Expand<'a> {
+    pub bitlink_id: Cow<'a, str>,
+}
 
-        let group_guid = match &self.cfg.default_group_guid {
-            Some(group_guid) => Cow::from(group_guid),
-            None => match self.fetch_user().await? {
-                User {
-                    is_active: false, ..
-                } => return Err(Error::UnknownGroupGUID("user is inactive")),
-                User {
-                    default_group_guid, ..
-                } => Cow::Owned(default_group_guid),
-            },
-        };
+/// API request to update an existing bitlink
+///
+/// <https://dev.bitly.com/api-reference/#updateBitlink>
+#[derive(Debug, Serialize)]
+pub struct Update<'a> {
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub title: Option<Cow<'a, str>>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub archived: Option<bool>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub tags: Option<Vec<String>>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub long_url: Option<Cow<'a, Url>>,
+}
 
-        let domain = self.cfg.domain.as_deref().map(Cow::Borrowed);
+/// Changes to apply to existing bitlinks
+#[derive(Clone, Debug, Default)]
+pub struct BitlinkChanges {
+    pub title: Option<String>,
+    pub archived: Option<bool>,
+    pub add_tags: Vec<String>,
+    pub remove_tags: Vec<String>,
+    pub long_url: Option<Url>,
+}
 
-        let payload = Shorten {
-            long_url,
-            domain,
-            group_guid,
-        };
+impl BitlinkChanges {
+    /// Compute new tags from the current ones, preserving their order
+    fn apply_tags(&self, mut tags: Vec<String>) -> Vec<String> {
+        tags.retain(|tag| !self.remove_tags.contains(tag));
 
-        // fast path: check local cache for the bitlink
-        if let Some(ref cache) = self.cache
-            && let Some(bitlink) = cache.get(&payload).await
-        {
-            return Ok(bitlink);
+        for tag in &self.add_tags {
+            if !tags.contains(tag) {
+                tags.push(tag.clone());
+            }
         }
 
-        let Some(ref http) = self.http else {
-            return Err(Error::Offline("shorten"));
-        };
+        tags
+    }
+}
 
-        let endpoint = self.api_url("shorten");
+/// Reference to an existing bitlink
+///
+/// Can be parsed either from a full bitlink URL (e.g., `https://bit.ly/4ePsyXN`) or from a bitlink
+/// ID (e.g., `bit.ly/4ePsyXN`), in which case the `https` scheme is assumed.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct BitlinkRef(Url);
 
-        debug!(?payload, "sending shorten request");
+impl BitlinkRef {
+    /// The bitlink URL (e.g., `https://bit.ly/4ePsyXN`)
+    #[inline]
+    pub fn url(&self) -> &Url {
+        &self.0
+    }
 
-        let resp = http
-            .post(endpoint)
-            .bearer_auth(self.cfg.api_token())
-            .json(&payload)
-            .send()
-            .await?;
+    /// The bitlink ID (e.g., `bit.ly/4ePsyXN`)
+    pub fn id(&self) -> String {
+        let host = self.0.host_str().unwrap_or_default();
+        format!("{host}{}", self.0.path().trim_end_matches('/'))
+    }
+}
 
-        let result = parse_response! { resp =>
-            OK | CREATED
-            ||
-            BAD_REQUEST
-            | FORBIDDEN
-            | GONE
-            | EXPECTATION_FAILED
-            | UNPROCESSABLE_ENTITY
-            | TOO_MANY_REQUESTS
-            | INTERNAL_SERVER_ERROR
-            | SERVICE_UNAVAILABLE
+impl FromStr for BitlinkRef {
+    type Err = url::ParseError;
+
+    fn from_str(s: &str) -> std::result::Result<Self, Self::Err> {
+        let url = match Url::parse(s) {
+            Ok(url) => url,
+            Err(url::ParseError::RelativeUrlWithoutBase) => Url::parse(&format!("https://{s}"))?,
+            Err(error) => return Err(error),
         };
 
-        // if successful then update local cache
-        if let Ok(ref result) = result
-            && let Some(ref cache) = self.cache
-        {
-            cache.set(&payload, result).await;
+        if url.host_str().is_none() {
+            return Err(url::ParseError::EmptyHost);
         }
 
-        result
+        Ok(Self(url))
+    }
+}
+
+impl<'de> Deserialize<'de> for BitlinkRef {
+    fn deserialize<D>(deserializer: D) -> std::result::Result<Self, D::Error>
+    where
+        D: serde::Deserializer<'de>,
+    {
+        let s = Cow::<'de, str>::deserialize(deserializer)?;
+        s.parse().map_err(serde::de::Error::custom)
     }
+}
 
-    #[instrument(level = "debug", skip_all)]
-    fn shorten_all(
-        self: Arc<Self>,
-        urls: impl Stream<Item = Url>,
-    ) -> impl Stream<Item = impl Future<Output = Result<Bitlink>>> {
-        urls.map(move |url| {
-            let client = Arc::clone(&self);
-            async move { client.shorten(url).await }
-        })
+impl std::fmt::Display for BitlinkRef {
+    #[inline]
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(f, "{}", self.0)
     }
 }
 
-pub struct Client {
-    inner: Arc<ClientInner>,
+#[derive(Clone, Debug, Deserialize, PartialEq, Eq)]
+pub struct Bitlink {
+    pub link: Url,
+    pub id: String,
+    pub long_url: Url,
 }
 
-// TODO: handle timeouts, cancellation, API limits (see `GET /v4/user/platform_limits`), etc.
-impl Client {
-    #[instrument(name = "init_client", level = "debug")]
-    pub async fn new(cfg: Config) -> Self {
-        let http = if cfg.offline {
-            debug!("offline mode enabled, skipping HTTP client initialization");
-            None
-        } else {
-            debug!("initializing HTTP client");
-            Some(reqwest::Client::new())
-        };
+impl std::fmt::Display for Bitlink {
+    #[inline]
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(f, "{}", self.link)
+    }
+}
 
-        let cache = BitlinkCache::new(VERSION, cfg.cache_dir.as_ref()).await;
+/// Full bitlink metadata
+///
+/// <https://dev.bitly.com/api-reference/#getBitlink>
+#[derive(Clone, Debug, Deserialize, Serialize, PartialEq, Eq)]
+pub struct BitlinkInfo {
+    pub link: Url,
+    pub id: String,
+    pub long_url: Url,
+    #[serde(default)]
+    pub title: Option<String>,
+    #[serde(default)]
+    pub created_at: Option<String>,
+    #[serde(default)]
+    pub archived: bool,
+    #[serde(default)]
+    pub tags: Vec<String>,
+    #[serde(default)]
+    pub custom_bitlinks: Vec<String>,
+    #[serde(default)]
+    pub deeplinks: Vec<Deeplink>,
+    #[serde(default)]
+    pub references: BTreeMap<String, String>,
+}
 
+impl From<BitlinkInfo> for Bitlink {
+    #[inline]
+    fn from(info: BitlinkInfo) -> Self {
         Self {
-            inner: Arc::new(ClientInner { cfg, http, cache }),
+            link: info.link,
+            id: info.id,
+            long_url: info.long_url,
         }
     }
+}
 
-    #[instrument(level = "debug", skip(self, urls))]
-    pub fn shorten<'a, S>(
-        &self,
-        urls: S,
-        ordering: Ordering,
-    ) -> impl Stream<Item = Result<Bitlink>> + 'a
-    where
-        S: Stream<Item = Url> + Send + 'a,
-    {
-        let client = Arc::clone(&self.inner);
-        let max_concurrent = client.cfg.max_concurrent;
+impl std::fmt::Display for BitlinkInfo {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        writeln!(f, "link:            {}", self.link)?;
+        writeln!(f, "id:              {}", self.id)?;
+        writeln!(f, "long_url:        {}", self.long_url)?;
+        writeln!(
+            f,
+            "title:           {}",
+            self.title.as_deref().unwrap_or("")
+        )?;
+        writeln!(
+            f,
+            "created_at:      {}",
+            self.created_at.as_deref().unwrap_or("")
+        )?;
+        writeln!(f, "archived:        {}", self.archived)?;
+        writeln!(f, "tags:            {}", self.tags.join(", "))?;
+        writeln!(f, "custom_bitlinks: {}", self.custom_bitlinks.join(", "))?;
 
-        match ordering {
-            Ordering::Ordered => client
-                .shorten_all(urls)
-                .buffered(max_concurrent)
-                .left_stream(),
+        write!(f, "deeplinks:")?;
+        for deeplink in &self.deeplinks {
+            write!(f, "\n  - {deeplink}")?;
+        }
 
-            Ordering::Unordered => client
-                .shorten_all(urls)
-                .buffer_unordered(max_concurrent)
-                .right_stream(),
+        write!(f, "\nreferences:")?;
+        for (name, reference) in &self.references {
+            write!(f, "\n  {name}: {reference}")?;
         }
+
+        Ok(())
     }
 }
 
-#[cfg(test)]
-mod tests {
-    use super::*;
-    use rstest::*;
+/// Mobile app deep link attached to a bitlink
+///
+/// <https://dev.bitly.com/api-reference/#createBitlink>
+#[derive(Clone, Debug, Default, Deserialize, Serialize, PartialEq, Eq)]
+pub struct Deeplink {
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub app_id: Option<String>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub app_guid: Option<String>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub app_uri_path: Option<String>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub install_url: Option<String>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub install_type: Option<String>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub os: Option<String>,
+}
 
-    use std::path::PathBuf;
-    use std::sync::atomic::AtomicUsize;
+impl std::fmt::Display for Deeplink {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        let fields = [
+            ("app", self.app_id.as_ref().or(self.app_guid.as_ref())),
+            ("path", self.app_uri_path.as_ref()),
+            ("install_url", self.install_url.as_ref()),
+            ("install_type", self.install_type.as_ref()),
+            ("os", self.os.as_ref()),
+        ];
 
-    use futures_util::stream;
-    use wiremock::matchers::{method, path};
-    use wiremock::{Mock, MockServer, Respond, ResponseTemplate};
+        let mut sep = "";
+        for (name, value) in fields {
+            if let Some(value) = value {
+                write!(f, "{sep}{name}={value}")?;
+                sep = " ";
+            }
+        }
+
+        Ok(())
+    }
+}
+
+/// Filters of bitlinks listed for a group
+///
+/// <https://dev.bitly.com/api-reference/#getBitlinksByGroup>
+#[derive(Clone, Debug, Default)]
+pub struct ListFilter {
+    /// Full-text search query
+    pub query: Option<String>,
+    /// Only bitlinks with all of these tags
+    pub tags: Vec<String>,
+    /// Only bitlinks created after this Unix timestamp
+    pub created_after: Option<u64>,
+    /// Only bitlinks created before this Unix timestamp
+    pub created_before: Option<u64>,
+    pub archived: Toggle,
+    /// Filter by presence of custom bitlinks (keywords)
+    pub custom_bitlink: Toggle,
+    /// Only bitlinks under this domain (filtered locally, not supported by the API)
+    pub domain: Option<String>,
+    /// The number of bitlinks fetched per page
+    pub size: Option<u32>,
+}
+
+impl ListFilter {
+    fn query_pairs(&self) -> Vec<(&'static str, String)> {
+        let mut query = vec![
+            ("archived", self.archived.to_string()),
+            ("custom_bitlink", self.custom_bitlink.to_string()),
+        ];
+
+        if let Some(ref q) = self.query {
+            query.push(("query", q.clone()));
+        }
+
+        if let Some(created_after) = self.created_after {
+            query.push(("created_after", created_after.to_string()));
+        }
+
+        if let Some(created_before) = self.created_before {
+            query.push(("created_before", created_before.to_string()));
+        }
+
+        if let Some(size) = self.size {
+            query.push(("size", size.to_string()));
+        }
+
+        query.extend(self.tags.iter().map(|tag| ("tags", tag.clone())));
+
+        query
+    }
+
+    fn matches(&self, bitlink: &BitlinkInfo) -> bool {
+        self.domain
+            .as_deref()
+            .is_none_or(|domain| bitlink.link.host_str() == Some(domain))
+    }
+}
+
+/// Single page of bitlinks
+///
+/// <https://dev.bitly.com/api-reference/#getBitlinksByGroup>
+#[derive(Debug, Deserialize)]
+struct BitlinksPage {
+    links: Vec<BitlinkInfo>,
+    pagination: Pagination,
+}
+
+#[derive(Debug, Deserialize)]
+struct Pagination {
+    #[serde(default, deserialize_with = "empty_as_none")]
+    next: Option<Url>,
+}
+
+/// Deserialize an optional URL, treating an empty string as `None`
+fn empty_as_none<'de, D>(deserializer: D) -> std::result::Result<Option<Url>, D::Error>
+where
+    D: serde::Deserializer<'de>,
+{
+    match Option::<Cow<'de, str>>::deserialize(deserializer)? {
+        Some(url) if !url.is_empty() => url.parse().map(Some).map_err(serde::de::Error::custom),
+        _ => Ok(None),
+    }
+}
+
+/// Bitlink metrics endpoint
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum Metric {
+    /// <https://dev.bitly.com/api-reference/#getClicksForBitlink>
+    Clicks,
+    /// <https://dev.bitly.com/api-reference/#getClicksSummaryForBitlink>
+    ClicksSummary,
+    /// <https://dev.bitly.com/api-reference/#getMetricsForBitlinkByCountries>
+    Countries,
+    /// <https://dev.bitly.com/api-reference/#getMetricsForBitlinkByCities>
+    Cities,
+    /// <https://dev.bitly.com/api-reference/#getMetricsForBitlinkByReferrers>
+    Referrers,
+    /// <https://dev.bitly.com/api-reference/#getMetricsForBitlinkByReferringDomains>
+    ReferringDomains,
+    /// <https://dev.bitly.com/api-reference/#getMetricsForBitlinkByDevices>
+    Devices,
+}
+
+impl Metric {
+    #[inline]
+    fn endpoint(self) -> &'static str {
+        match self {
+            Self::Clicks => "clicks",
+            Self::ClicksSummary => "clicks/summary",
+            Self::Countries => "countries",
+            Self::Cities => "cities",
+            Self::Referrers => "referrers",
+            Self::ReferringDomains => "referring_domains",
+            Self::Devices => "devices",
+        }
+    }
+}
+
+/// Query parameters shared by all the metrics endpoints
+#[derive(Clone, Debug, Serialize)]
+pub struct MetricsQuery {
+    pub unit: TimeUnit,
+    pub units: i64,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub unit_reference: Option<String>,
+}
+
+/// Metrics of a single bitlink
+#[derive(Clone, Debug, Serialize, PartialEq, Eq)]
+pub struct BitlinkMetrics<T> {
+    pub bitlink: String,
+    #[serde(flatten)]
+    pub metrics: T,
+}
+
+/// Click counts of a bitlink, one per time unit
+///
+/// <https://dev.bitly.com/api-reference/#getClicksForBitlink>
+#[derive(Clone, Debug, Deserialize, Serialize, PartialEq, Eq)]
+pub struct Clicks {
+    pub link_clicks: Vec<LinkClicks>,
+    pub unit: TimeUnit,
+    pub units: i64,
+    #[serde(default)]
+    pub unit_reference: Option<String>,
+}
+
+#[derive(Clone, Debug, Deserialize, Serialize, PartialEq, Eq)]
+pub struct LinkClicks {
+    pub date: String,
+    pub clicks: u64,
+}
+
+impl std::fmt::Display for BitlinkMetrics<Clicks> {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        let mut sep = "";
+        for LinkClicks { date, clicks } in &self.metrics.link_clicks {
+            write!(f, "{sep}{}\t{date}\t{clicks}", self.bitlink)?;
+            sep = "\n";
+        }
+        Ok(())
+    }
+}
+
+/// Total number of clicks of a bitlink
+///
+/// <https://dev.bitly.com/api-reference/#getClicksSummaryForBitlink>
+#[derive(Clone, Debug, Deserialize, Serialize, PartialEq, Eq)]
+pub struct ClicksSummary {
+    pub total_clicks: u64,
+    pub unit: TimeUnit,
+    pub units: i64,
+    #[serde(default)]
+    pub unit_reference: Option<String>,
+}
+
+impl std::fmt::Display for BitlinkMetrics<ClicksSummary> {
+    #[inline]
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(f, "{}\t{}", self.bitlink, self.metrics.total_clicks)
+    }
+}
+
+/// Click counts of a bitlink broken down by some facet (e.g., countries or referrers)
+///
+/// This is the response of all the breakdown endpoints (see [`Metric`]).
+#[derive(Clone, Debug, Deserialize, Serialize, PartialEq, Eq)]
+pub struct Breakdown {
+    pub metrics: Vec<FacetClicks>,
+    #[serde(default)]
+    pub facet: Option<String>,
+    pub unit: TimeUnit,
+    pub units: i64,
+    #[serde(default)]
+    pub unit_reference: Option<String>,
+}
+
+/// Number of clicks for a single value of a facet
+///
+/// City metrics identify the location by `city`, `subregion`, `region` and `country` instead of a
+/// single `value`.
+#[derive(Clone, Debug, Default, Deserialize, Serialize, PartialEq, Eq)]
+pub struct FacetClicks {
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub value: Option<String>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub city: Option<String>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub subregion: Option<String>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub region: Option<String>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub country: Option<String>,
+    pub clicks: u64,
+}
+
+impl FacetClicks {
+    /// Human-readable name of the facet value
+    pub fn label(&self) -> Cow<'_, str> {
+        if let Some(ref value) = self.value {
+            return Cow::Borrowed(value);
+        }
+
+        let location = [&self.city, &self.subregion, &self.region, &self.country]
+            .into_iter()
+            .flatten()
+            .map(String::as_str)
+            .collect::<Vec<_>>();
+
+        Cow::Owned(location.join(", "))
+    }
+}
+
+impl std::fmt::Display for BitlinkMetrics<Breakdown> {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        let mut sep = "";
+        for metric in &self.metrics.metrics {
+            write!(
+                f,
+                "{sep}{}\t{}\t{}",
+                self.bitlink,
+                metric.label(),
+                metric.clicks
+            )?;
+            sep = "\n";
+        }
+        Ok(())
+    }
+}
+
+macro_rules! parse_response {
+    ($resp:expr => $ok:ident $(| $oks:ident)* || $err:ident $(| $errs:ident)*) => {
+        decode_response(
+            $resp,
+            &[StatusCode::$ok $(, StatusCode::$oks)*],
+            &[StatusCode::$err $(, StatusCode::$errs)*],
+        )
+        .await
+    };
+}
+
+/// Decode a response to an API request
+///
+/// Responses with one of the `ok` status codes are decoded as `T`, responses with one of the
+/// (documented) `err` status codes as an [`ErrorResponse`](crate::error::ErrorResponse). Any other
+/// status code or a body that fails to decode is reported as an error as well.
+async fn decode_response<T>(
+    resp: reqwest::Response,
+    ok: &[StatusCode],
+    err: &[StatusCode],
+) -> Result<T>
+where
+    T: DeserializeOwned,
+{
+    let status = resp.status();
+    let headers = resp.headers().clone();
+    let body = resp.bytes().await?;
+
+    let result = if ok.contains(&status) {
+        serde_json::from_slice(&body).map(Ok)
+    } else if err.contains(&status) {
+        serde_json::from_slice(&body).map(|resp| Err(Error::Bitly(resp)))
+    } else {
+        let response = RawResponse::new(status, headers, &body);
+        return Err(Error::UnexpectedStatus(Box::new(response)));
+    };
+
+    result.unwrap_or_else(|source| {
+        let response = RawResponse::new(status, headers, &body);
+        Err(Error::InvalidResponse {
+            response: Box::new(response),
+            source,
+        })
+    })
+}
+
+/// Derive the maximum number of API requests per second from the monthly shorten limit (if known)
+///
+/// The rate is [`DEFAULT_RATE_LIMIT`], unless the remaining quota is lower than that, so that a
+/// burst of requests doesn't overshoot it. At least one request per second is always allowed,
+/// since the limit applies to the requests to all the other endpoints as well.
+fn rate_limit(limit: Option<&MethodLimit>) -> NonZeroU32 {
+    let remaining = limit.map_or(u64::MAX, |limit| limit.limit.saturating_sub(limit.count));
+    let rate = remaining.min(u64::from(DEFAULT_RATE_LIMIT));
+    u32::try_from(rate)
+        .ok()
+        .and_then(NonZeroU32::new)
+        .unwrap_or(NonZeroU32::MIN)
+}
+
+fn api_url(base: &Url, endpoint: &str) -> Url {
+    let mut api_url = base.clone();
+    api_url.set_path(&format!("{VERSION}/{endpoint}"));
+    api_url
+}
+
+struct ClientInner {
+    cfg: Config,
+    http: Option<reqwest::Client>,
+    cache: Option<BitlinkCache>,
+    /// Group GUID resolved once and shared by all the requests
+    group_guid: OnceCell<String>,
+    /// Rate limiter shared by all the requests (unless disabled, see [`rate_limit`])
+    limiter: Option<RateLimiter>,
+    /// Limit of requests in flight, which may adapt to the API responses
+    concurrency: Arc<Concurrency>,
+    /// Remaining monthly quota of created bitlinks (if it could be determined)
+    quota: Option<Quota>,
+    /// Shorten requests in flight by their payload (see [`Shorten::key`])
+    in_flight: Mutex<HashMap<String, Arc<SharedShorten>>>,
+    /// Recorded (or replayed) API interactions (see `--record` and `--replay`)
+    cassette: Option<Cassette>,
+}
+
+/// Outcome of a shorten request shared by all the duplicates in flight
+type SharedShorten = OnceCell<std::result::Result<Bitlink, Arc<Error>>>;
+
+impl ClientInner {
+    #[inline]
+    fn api_url(&self, endpoint: &str) -> Url {
+        api_url(&self.cfg.api_url, endpoint)
+    }
+
+    /// Send given request, retrying transient failures according to the [`RetryPolicy`]
+    ///
+    /// Requests that are not idempotent (e.g., `POST` adding a custom bitlink) are only retried if
+    /// the API could not have processed them, i.e. when rate limited (`429`) or when the connection
+    /// failed. Otherwise a retry of a request that succeeded, but whose response got lost, could
+    /// fail or create a duplicate.
+    #[inline]
+    async fn send(&self, req: reqwest::RequestBuilder) -> Result<reqwest::Response> {
+        self.send_retrying(req, false).await
+    }
+
+    /// Send given request like [`Self::send`], but retry it as if it was idempotent
+    ///
+    /// This is meant for `POST` requests which don't change anything (e.g., `/v4/expand`), or which
+    /// yield the same result when repeated (e.g., `/v4/shorten` of the same long URL).
+    #[inline]
+    async fn send_idempotent(&self, req: reqwest::RequestBuilder) -> Result<reqwest::Response> {
+        self.send_retrying(req, true).await
+    }
+
+    async fn send_retrying(
+        &self,
+        req: reqwest::RequestBuilder,
+        idempotent: bool,
+    ) -> Result<reqwest::Response> {
+        let (http, req) = req.build_split();
+        let req = req?;
+
+        let idempotent = idempotent || req.method().is_idempotent();
+
+        let policy = &self.cfg.retry;
+        let mut attempts = 0;
+
+        loop {
+            attempts += 1;
+
+            if let Some(ref limiter) = self.limiter {
+                limiter.acquire().await;
+            }
+
+            let epoch = self.concurrency.epoch();
+
+            // NOTE: requests with a streaming body cannot be cloned, hence not retried
+            let Some(next) = req.try_clone().filter(|_| policy.can_retry(attempts)) else {
+                let resp = self.execute(&http, req).await;
+                self.adapt_concurrency(epoch, &resp);
+                return resp;
+            };
+
+            let resp = self.execute(&http, next).await;
+            self.adapt_concurrency(epoch, &resp);
+
+            let delay = match resp {
+                Ok(resp) if RetryPolicy::is_retryable(resp.status(), idempotent) => {
+                    let delay = policy.delay(attempts, Some(resp.headers()));
+                    warn!(status = %resp.status(), attempts, ?delay, "retrying request");
+                    delay
+                }
+                Ok(resp) => return Ok(resp),
+                Err(Error::Http(ref error))
+                    if error.is_connect() || (idempotent && error.is_timeout()) =>
+                {
+                    let delay = policy.delay(attempts, None);
+                    warn!(%error, attempts, ?delay, "retrying request");
+                    delay
+                }
+                Err(error) => return Err(error),
+            };
+
+            tokio::time::sleep(delay).await;
+        }
+    }
+
+    /// Execute a single attempt of given request (possibly recorded or replayed by a cassette)
+    #[inline]
+    async fn execute(
+        &self,
+        http: &reqwest::Client,
+        req: reqwest::Request,
+    ) -> Result<reqwest::Response> {
+        match self.cassette {
+            Some(ref cassette) => cassette.execute(http, req).await,
+            None => http.execute(req).await.map_err(Error::from),
+        }
+    }
+
+    /// Adapt the concurrency to the outcome of a request issued in given `epoch`
+    fn adapt_concurrency(&self, epoch: u64, resp: &Result<reqwest::Response>) {
+        match resp {
+            Ok(resp) if resp.status().is_success() => self.concurrency.succeeded(),
+            Ok(resp)
+                if matches!(
+                    resp.status(),
+                    StatusCode::TOO_MANY_REQUESTS | StatusCode::SERVICE_UNAVAILABLE
+                ) =>
+            {
+                self.concurrency.overloaded(epoch);
+            }
+            _ => {}
+        }
+    }
+
+    #[instrument(level = "debug", skip(self))]
+    async fn fetch_user(&self) -> Result<User> {
+        let Some(ref http) = self.http else {
+            return Err(Error::Offline("user"));
+        };
+
+        let endpoint = self.api_url("user");
+
+        debug!("fetching user info");
+        let req = http.get(endpoint).bearer_auth(self.cfg.api_token());
+        let resp = self.send(req).await?;
+
+        parse_response! { resp =>
+            OK
+            ||
+            FORBIDDEN
+            | GONE
+            | NOT_FOUND
+            | INTERNAL_SERVER_ERROR
+            | SERVICE_UNAVAILABLE
+        }
+    }
+
+    #[instrument(level = "debug", skip(self))]
+    async fn fetch_groups(&self, organization_guid: Option<&str>) -> Result<Vec<Group>> {
+        let Some(ref http) = self.http else {
+            return Err(Error::Offline("groups"));
+        };
+
+        let endpoint = self.api_url("groups");
+
+        debug!("fetching groups");
+
+        let mut req = http.get(endpoint).bearer_auth(self.cfg.api_token());
+
+        if let Some(organization_guid) = organization_guid {
+            req = req.query(&[("organization_guid", organization_guid)]);
+        }
+
+        let resp = self.send(req).await?;
+
+        let Groups { groups } = parse_response! { resp =>
+            OK
+            ||
+            BAD_REQUEST
+            | FORBIDDEN
+            | NOT_FOUND
+            | GONE
+            | TOO_MANY_REQUESTS
+            | INTERNAL_SERVER_ERROR
+            | SERVICE_UNAVAILABLE
+        }?;
+
+        // keep the local name-to-GUID mapping up to date
+        if let Some(ref cache) = self.cache {
+            // NOTE: groups of an organization are just a part of all the groups of the user
+            match organization_guid {
+                Some(_) => {
+                    cache.set_groups(&groups).await;
+                }
+                None => {
+                    if cache.replace_groups(&groups).await {
+                        cache.set_value(GROUPS, &groups.len().to_string()).await;
+                    }
+                }
+            }
+        }
+
+        Ok(groups)
+    }
+
+    #[instrument(level = "debug", skip(self))]
+    async fn fetch_organizations(&self) -> Result<Vec<Organization>> {
+        let Some(ref http) = self.http else {
+            return Err(Error::Offline("organizations"));
+        };
+
+        let endpoint = self.api_url("organizations");
+
+        debug!("fetching organizations");
+
+        let req = http.get(endpoint).bearer_auth(self.cfg.api_token());
+        let resp = self.send(req).await?;
+
+        let Organizations { organizations } = parse_response! { resp =>
+            OK
+            ||
+            BAD_REQUEST
+            | FORBIDDEN
+            | NOT_FOUND
+            | GONE
+            | TOO_MANY_REQUESTS
+            | INTERNAL_SERVER_ERROR
+            | SERVICE_UNAVAILABLE
+        }?;
+
+        Ok(organizations)
+    }
+
+    /// Fetch the monthly limit of created bitlinks and its current usage
+    #[instrument(level = "debug", skip(self))]
+    async fn fetch_shorten_limit(&self) -> Result<Option<MethodLimit>> {
+        let Some(ref http) = self.http else {
+            return Err(Error::Offline("platform limits"));
+        };
+
+        let endpoint = self.api_url("user/platform_limits");
+
+        debug!("fetching platform limits");
+
+        let req = http
+            .get(endpoint)
+            .bearer_auth(self.cfg.api_token())
+            .query(&[("path", SHORTEN_LIMIT_PATH)]);
+        let resp = self.send(req).await?;
+
+        let PlatformLimits { platform_limits } = parse_response! { resp =>
+            OK
+            ||
+            FORBIDDEN
+            | NOT_FOUND
+            | GONE
+            | TOO_MANY_REQUESTS
+            | INTERNAL_SERVER_ERROR
+            | SERVICE_UNAVAILABLE
+        }?;
+
+        let limit = platform_limits
+            .into_iter()
+            .filter(|limit| limit.endpoint == SHORTEN_LIMIT_PATH)
+            .flat_map(|limit| limit.methods)
+            .find(|method| method.name.eq_ignore_ascii_case("POST"));
+
+        Ok(limit)
+    }
+
+    /// Get the monthly limit of created bitlinks and its current usage
+    ///
+    /// The limit is cached for [`SHORTEN_LIMIT_TTL`]. If it cannot be determined, then the quota
+    /// is not enforced (and exceeding it surfaces as an API error).
+    async fn shorten_limit(&self) -> Option<MethodLimit> {
+        if let Some(ref cache) = self.cache
+            && let Some(limit) = cache
+                .get_value(SHORTEN_LIMIT, Some(SHORTEN_LIMIT_TTL))
+                .await
+                .and_then(|limit| serde_json::from_str(&limit).ok())
+        {
+            return Some(limit);
+        }
+
+        match self.fetch_shorten_limit().await {
+            Ok(Some(limit)) => {
+                if let (Some(cache), Ok(value)) = (&self.cache, serde_json::to_string(&limit)) {
+                    cache.set_value(SHORTEN_LIMIT, &value).await;
+                }
+                Some(limit)
+            }
+            Ok(None) => None,
+            Err(error) => {
+                warn!(%error, "failed to determine shorten quota");
+                None
+            }
+        }
+    }
+
+    /// Persist the current usage of given shorten quota, so that the next runs account for it
+    ///
+    /// NOTE: The age of the cached limit is kept, so that it's still fetched again once expired.
+    async fn save_quota(&self, quota: &Quota) {
+        let Some(ref cache) = self.cache else {
+            return;
+        };
+
+        let limit = MethodLimit {
+            name: "POST".to_string(),
+            limit: quota.limit(),
+            count: quota.used(),
+        };
+
+        if let Ok(value) = serde_json::to_string(&limit) {
+            cache.replace_value(SHORTEN_LIMIT, &value).await;
+        }
+    }
+
+    /// Resolve a group name to its GUID
+    ///
+    /// Looks the name up in the local cache first and falls back to fetching (and caching) all the
+    /// groups of the user if the name is unknown. The cache is only trusted if it holds all the
+    /// groups of the user fetched within [`GROUPS_TTL`] (or regardless under the offline mode).
+    #[instrument(level = "debug", skip(self))]
+    async fn resolve_group(&self, name: &str) -> Result<String> {
+        let cached = match self.cache {
+            Some(ref cache)
+                if self.http.is_none()
+                    || cache.get_value(GROUPS, Some(GROUPS_TTL)).await.is_some() =>
+            {
+                cache.find_groups(name).await
+            }
+            _ => Vec::new(),
+        };
+
+        let mut groups = if cached.is_empty() && self.http.is_some() {
+            self.fetch_groups(None).await?
+        } else {
+            cached
+        };
+
+        groups.retain(|group| group.name == name);
+
+        match groups.len() {
+            0 => Err(Error::UnknownGroup(name.to_string())),
+            1 => Ok(groups.swap_remove(0).guid),
+            _ => Err(Error::AmbiguousGroup {
+                name: name.to_string(),
+                candidates: groups
+                    .into_iter()
+                    .map(
+                        |Group {
+                             guid,
+                             organization_guid,
+                             ..
+                         }| match organization_guid {
+                            Some(organization_guid) => {
+                                format!("{guid} (organization {organization_guid})")
+                            }
+                            None => guid,
+                        },
+                    )
+                    .collect(),
+            }),
+        }
+    }
+
+    /// Resolve the group GUID to use in requests (see [`Config::default_group_guid`])
+    ///
+    /// The group is resolved just once and shared by all the (concurrent) requests.
+    async fn group_guid(&self) -> Result<&str> {
+        if let Some(ref group_guid) = self.cfg.default_group_guid {
+            return Ok(group_guid);
+        }
+
+        let group_guid = self
+            .group_guid
+            .get_or_try_init(|| async {
+                match self.cfg.default_group {
+                    Some(ref name) => self.resolve_group(name).await,
+                    None => self.default_group_guid().await,
+                }
+            })
+            .await?;
+
+        Ok(group_guid)
+    }
+
+    /// Get the default group GUID of the authenticated user
+    ///
+    /// The GUID is persisted in the local cache and trusted for [`DEFAULT_GROUP_GUID_TTL`], or
+    /// regardless of its age under the offline mode.
+    #[instrument(level = "debug", skip(self))]
+    async fn default_group_guid(&self) -> Result<String> {
+        if let Some(ref cache) = self.cache {
+            let max_age = self.http.as_ref().map(|_| DEFAULT_GROUP_GUID_TTL);
+            if let Some(group_guid) = cache.get_value(DEFAULT_GROUP_GUID, max_age).await {
+                return Ok(group_guid);
+            }
+        }
+
+        let group_guid = match self.fetch_user().await? {
+            User {
+                is_active: false, ..
+            } => return Err(Error::UnknownGroupGUID("user is inactive")),
+            User {
+                default_group_guid, ..
+            } => default_group_guid,
+        };
+
+        if let Some(ref cache) = self.cache {
+            cache.set_value(DEFAULT_GROUP_GUID, &group_guid).await;
+        }
+
+        Ok(group_guid)
+    }
+
+    /// Build the payload of a shorten request for given URL (resolving the group if necessary)
+    async fn shorten_payload<'a>(
+        &'a self,
+        long_url: Url,
+        fields: &'a BitlinkFields,
+    ) -> Result<Shorten<'a>> {
+        let group_guid = Cow::Borrowed(self.group_guid().await?);
+
+        let domain = self.cfg.domain.as_deref().map(Cow::Borrowed);
+
+        Ok(Shorten {
+            long_url,
+            domain,
+            group_guid,
+            title: fields.title.as_deref().map(Cow::Borrowed),
+            tags: Cow::Borrowed(&fields.tags),
+            deeplinks: Cow::Borrowed(&fields.deeplinks),
+            keyword: fields.keyword.as_deref().map(Cow::Borrowed),
+        })
+    }
+
+    #[instrument(level = "debug", fields(%long_url), skip_all)]
+    async fn shorten(&self, long_url: Url, fields: &BitlinkFields) -> Result<Bitlink> {
+        debug!("shortening URL");
+
+        let payload = self.shorten_payload(long_url, fields).await?;
+
+        // fast path: check local cache for the bitlink
+        if let Some(ref cache) = self.cache
+            && let Some(bitlink) = cache.lookup(&payload, self.cfg.cache_max_age()).await
+            && let Some(bitlink) = self.revalidate(cache, bitlink).await
+        {
+            return Ok(bitlink);
+        }
+
+        // duplicate inputs in flight wait for (and share) the outcome of the first one
+        let key = payload.key();
+        let request = {
+            let mut in_flight = self.in_flight.lock().expect("in-flight requests lock");
+            Arc::clone(in_flight.entry(key.clone()).or_default())
+        };
+
+        let result = request
+            .get_or_init(|| async { self.shorten_uncached(&payload).await.map_err(Arc::new) })
+            .await
+            .clone();
+
+        // NOTE: the finished request is forgotten, so that a later duplicate (e.g., a retry after
+        //  an error) is sent anew
+        {
+            let mut in_flight = self.in_flight.lock().expect("in-flight requests lock");
+            if in_flight
+                .get(&key)
+                .is_some_and(|shared| Arc::ptr_eq(shared, &request))
+            {
+                in_flight.remove(&key);
+            }
+        }
+
+        // the last one to get the error takes it over, the others only share it
+        drop(request);
+        result.map_err(|error| Arc::try_unwrap(error).unwrap_or_else(Error::Shared))
+    }
+
+    /// Plan how given URL would be shortened, without sending any request that creates a bitlink
+    #[instrument(level = "debug", fields(%long_url), skip_all)]
+    async fn plan_shorten(&self, long_url: Url, fields: &BitlinkFields) -> Result<Plan> {
+        let input = long_url.to_string();
+        let payload = self.shorten_payload(long_url, fields).await?;
+
+        if let Some(ref cache) = self.cache
+            && let Some(bitlink) = cache.lookup(&payload, self.cfg.cache_max_age()).await
+        {
+            return match bitlink {
+                Cached::Fresh(bitlink) => Ok(Plan::cached(input, bitlink.link)),
+                Cached::Stale(bitlink) => {
+                    let bitlink_id = BitlinkRef(bitlink.link.clone()).id();
+                    let url = self.api_url(&format!("bitlinks/{bitlink_id}"));
+                    let revalidate = PlannedRequest::new("GET", url, &())?;
+                    Ok(Plan::stale(input, bitlink.link, revalidate))
+                }
+            };
+        }
+
+        let create = || {
+            let query = payload.without_keyword();
+            PlannedRequest::new("POST", self.api_url(query.endpoint().path()), &query)
+        };
+
+        let Some(ref keyword) = payload.keyword else {
+            return Ok(Plan::requests(input, vec![create()?]));
+        };
+
+        let bitlink = match self.cache {
+            Some(ref cache) => cache.get(&payload.without_keyword()).await,
+            None => None,
+        };
+
+        // NOTE: the ID of a bitlink that is yet to be created is unknown (hence null)
+        let (mut requests, domain, bitlink_id) = match bitlink {
+            Some(ref bitlink) => (
+                vec![],
+                bitlink.link.host_str().unwrap_or_default(),
+                Some(bitlink.id.as_str()),
+            ),
+            None => (
+                vec![create()?],
+                payload.domain.as_deref().unwrap_or(DEFAULT_DOMAIN),
+                None,
+            ),
+        };
+
+        let custom = serde_json::json!({
+            "custom_bitlink": format!("{domain}/{keyword}"),
+            "bitlink_id": bitlink_id,
+        });
+
+        requests.push(PlannedRequest::new(
+            "POST",
+            self.api_url("custom_bitlinks"),
+            &custom,
+        )?);
+
+        Ok(Plan::requests(input, requests))
+    }
+
+    #[instrument(level = "debug", skip_all)]
+    fn plan_shorten_all<K>(
+        self: Arc<Self>,
+        urls: impl Stream<Item = (K, Url)>,
+        fields: BitlinkFields,
+    ) -> impl Stream<Item = impl Future<Output = (K, Result<Plan>)>> {
+        let fields = Arc::new(fields);
+        urls.map(move |(key, url)| {
+            let client = Arc::clone(&self);
+            let fields = Arc::clone(&fields);
+            async move {
+                let input = url.to_string();
+                let result = client.plan_shorten(url, &fields).await;
+                (key, result.map_err(|error| error.with_input(input)))
+            }
+        })
+    }
+
+    /// Create new bitlink for given payload (with a custom keyword attached, if requested)
+    async fn shorten_uncached(&self, payload: &Shorten<'_>) -> Result<Bitlink> {
+        let Some(ref keyword) = payload.keyword else {
+            return self.create(payload).await;
+        };
+
+        // custom bitlinks are attached to a regular one, which might've been created before
+        let query = payload.without_keyword();
+        let bitlink = match self.cache {
+            Some(ref cache) => match cache.get(&query).await {
+                Some(bitlink) => bitlink,
+                None => self.create(&query).await?,
+            },
+            None => self.create(&query).await?,
+        };
+
+        let custom = self.add_custom_bitlink(&bitlink, keyword).await?;
+
+        if let Some(ref cache) = self.cache {
+            cache.set(payload, &custom).await;
+        }
+
+        Ok(custom)
+    }
+
+    /// Create new bitlink and store it in the local cache
+    async fn create(&self, payload: &Shorten<'_>) -> Result<Bitlink> {
+        let Some(ref http) = self.http else {
+            return Err(Error::Offline("shorten"));
+        };
+
+        let endpoint = payload.endpoint();
+
+        // NOTE: the quota applies just to the shorten endpoint (bitlinks have a limit of their own)
+        let quota = match endpoint {
+            CreateEndpoint::Shorten => self.quota.as_ref(),
+            CreateEndpoint::Bitlinks => None,
+        };
+
+        if let Some(quota) = quota {
+            quota.reserve()?;
+        }
+
+        let endpoint = self.api_url(endpoint.path());
+
+        debug!(?payload, "sending shorten request");
+
+        let req = http
+            .post(endpoint)
+            .bearer_auth(self.cfg.api_token())
+            .json(payload);
+
+        // NOTE: the API returns the existing bitlink of a long URL, so creating it is idempotent
+        let result = async {
+            let resp = self.send_idempotent(req).await?;
+
+            parse_response! { resp =>
+                OK | CREATED
+                ||
+                BAD_REQUEST
+                | PAYMENT_REQUIRED
+                | FORBIDDEN
+                | GONE
+                | EXPECTATION_FAILED
+                | UNPROCESSABLE_ENTITY
+                | TOO_MANY_REQUESTS
+                | INTERNAL_SERVER_ERROR
+                | SERVICE_UNAVAILABLE
+            }
+        }
+        .await;
+
+        // a failed request does not use up the quota
+        if let (Some(quota), Err(_)) = (quota, &result) {
+            quota.release();
+        }
+
+        // if successful then update local cache
+        if let Ok(ref result) = result
+            && let Some(ref cache) = self.cache
+        {
+            cache.set(payload, result).await;
+
+            if let Some(quota) = quota {
+                self.save_quota(quota).await;
+            }
+        }
+
+        result
+    }
+
+    #[instrument(level = "debug", skip(self, bitlink), fields(%bitlink))]
+    async fn add_custom_bitlink(&self, bitlink: &Bitlink, keyword: &str) -> Result<Bitlink> {
+        let Some(ref http) = self.http else {
+            return Err(Error::Offline("custom bitlink"));
+        };
+
+        let domain = bitlink.link.host_str().unwrap_or_default();
+        let custom_bitlink = format!("{domain}/{keyword}");
+
+        let endpoint = self.api_url("custom_bitlinks");
+
+        let payload = AddCustomBitlink {
+            custom_bitlink: Cow::Borrowed(&custom_bitlink),
+            bitlink_id: Cow::Borrowed(&bitlink.id),
+        };
+
+        debug!(?payload, "sending custom bitlink request");
+
+        let req = http
+            .post(endpoint)
+            .bearer_auth(self.cfg.api_token())
+            .json(&payload);
+        let resp = self.send(req).await?;
+
+        let result: Result<CustomBitlink> = parse_response! { resp =>
+            OK | CREATED
+            ||
+            BAD_REQUEST
+            | PAYMENT_REQUIRED
+            | FORBIDDEN
+            | NOT_FOUND
+            | CONFLICT
+            | GONE
+            | EXPECTATION_FAILED
+            | UNPROCESSABLE_ENTITY
+            | TOO_MANY_REQUESTS
+            | INTERNAL_SERVER_ERROR
+            | SERVICE_UNAVAILABLE
+        };
+
+        match result {
+            Ok(custom) => Ok(custom.into()),
+            Err(Error::Bitly(resp)) if resp.is_keyword_taken() => {
+                Err(Error::KeywordTaken(custom_bitlink))
+            }
+            Err(error) => Err(error),
+        }
+    }
+
+    #[instrument(level = "debug", skip_all)]
+    fn shorten_all<K>(
+        self: Arc<Self>,
+        urls: impl Stream<Item = (K, Url)>,
+        fields: BitlinkFields,
+    ) -> impl Stream<Item = impl Future<Output = (K, Result<Bitlink>)>> {
+        let fields = Arc::new(fields);
+        urls.map(move |(key, url)| {
+            let client = Arc::clone(&self);
+            let fields = Arc::clone(&fields);
+            async move {
+                let input = url.to_string();
+                let result = client.shorten(url, &fields).await;
+                (key, result.map_err(|error| error.with_input(input)))
+            }
+        })
+    }
+
+    #[instrument(level = "debug", fields(%bitlink), skip_all)]
+    async fn expand(&self, bitlink: BitlinkRef) -> Result<Bitlink> {
+        debug!("expanding bitlink");
+
+        // fast path: check local cache for a bitlink we've created before
+        if let Some(ref cache) = self.cache
+            && let Some(bitlink) = cache
+                .lookup_by_link(bitlink.url(), self.cfg.cache_max_age())
+                .await
+            && let Some(bitlink) = self.revalidate(cache, bitlink).await
+        {
+            return Ok(bitlink);
+        }
+
+        let Some(ref http) = self.http else {
+            return Err(Error::Offline("expand"));
+        };
+
+        let endpoint = self.api_url("expand");
+
+        let payload = Expand {
+            bitlink_id: Cow::Owned(bitlink.id()),
+        };
+
+        debug!(?payload, "sending expand request");
+
+        let req = http
+            .post(endpoint)
+            .bearer_auth(self.cfg.api_token())
+            .json(&payload);
+        let resp = self.send_idempotent(req).await?;
+
+        parse_response! { resp =>
+            OK
+            ||
+            BAD_REQUEST
+            | FORBIDDEN
+            | NOT_FOUND
+            | GONE
+            | EXPECTATION_FAILED
+            | UNPROCESSABLE_ENTITY
+            | TOO_MANY_REQUESTS
+            | INTERNAL_SERVER_ERROR
+            | SERVICE_UNAVAILABLE
+        }
+    }
+
+    /// Check a stale cached bitlink against the API, dropping it if it's been changed or deleted
+    ///
+    /// Returns the bitlink if it's fresh or still valid. If it can't be revalidated (e.g., under
+    /// the offline mode or when the API is unreachable), the stale bitlink is used anyway.
+    #[instrument(level = "debug", skip_all)]
+    async fn revalidate(&self, cache: &BitlinkCache, bitlink: Cached<Bitlink>) -> Option<Bitlink> {
+        let bitlink = match bitlink {
+            Cached::Fresh(bitlink) => return Some(bitlink),
+            Cached::Stale(bitlink) => bitlink,
+        };
+
+        debug!(link = %bitlink.link, "revalidating cached bitlink");
+
+        match self.info(&BitlinkRef(bitlink.link.clone())).await {
+            Ok(info) if !info.archived && info.long_url == bitlink.long_url => {
+                cache.touch(&bitlink.id).await;
+                Some(bitlink)
+            }
+            Ok(_) => {
+                debug!("cached bitlink has changed");
+                cache.evict(&bitlink.id).await;
+                None
+            }
+            Err(Error::Bitly(ref error)) if error.is_not_found() => {
+                debug!("cached bitlink has been deleted");
+                cache.evict(&bitlink.id).await;
+                None
+            }
+            Err(error) => {
+                warn!(%error, link = %bitlink.link, "using stale cached bitlink");
+                Some(bitlink)
+            }
+        }
+    }
+
+    #[instrument(level = "debug", skip_all)]
+    fn expand_all(
+        self: Arc<Self>,
+        bitlinks: impl Stream<Item = BitlinkRef>,
+    ) -> impl Stream<Item = impl Future<Output = Result<Bitlink>>> {
+        bitlinks.map(move |bitlink| {
+            let client = Arc::clone(&self);
+            async move {
+                let input = bitlink.to_string();
+                let result = client.expand(bitlink).await;
+                result.map_err(|error| error.with_input(input))
+            }
+        })
+    }
+
+    #[instrument(level = "debug", fields(%bitlink), skip_all)]
+    async fn info(&self, bitlink: &BitlinkRef) -> Result<BitlinkInfo> {
+        let Some(ref http) = self.http else {
+            return Err(Error::Offline("info"));
+        };
+
+        let endpoint = self.api_url(&format!("bitlinks/{}", bitlink.id()));
+
+        debug!("fetching bitlink info");
+
+        let req = http.get(endpoint).bearer_auth(self.cfg.api_token());
+        let resp = self.send(req).await?;
+
+        parse_response! { resp =>
+            OK
+            ||
+            BAD_REQUEST
+            | PAYMENT_REQUIRED
+            | FORBIDDEN
+            | NOT_FOUND
+            | GONE
+            | TOO_MANY_REQUESTS
+            | INTERNAL_SERVER_ERROR
+            | SERVICE_UNAVAILABLE
+        }
+    }
+
+    #[instrument(level = "debug", skip_all)]
+    fn info_all(
+        self: Arc<Self>,
+        bitlinks: impl Stream<Item = BitlinkRef>,
+    ) -> impl Stream<Item = impl Future<Output = Result<BitlinkInfo>>> {
+        bitlinks.map(move |bitlink| {
+            let client = Arc::clone(&self);
+            async move {
+                let result = client.info(&bitlink).await;
+                result.map_err(|error| error.with_input(bitlink))
+            }
+        })
+    }
+
+    #[instrument(level = "debug", fields(%bitlink), skip_all)]
+    async fn update(&self, bitlink: BitlinkRef, changes: &BitlinkChanges) -> Result<BitlinkInfo> {
+        let Some(ref http) = self.http else {
+            return Err(Error::Offline("update"));
+        };
+
+        let payload = self.update_payload(&bitlink, changes).await?;
+
+        let endpoint = self.api_url(&format!("bitlinks/{}", bitlink.id()));
+
+        debug!(?payload, "sending update request");
+
+        let req = http
+            .patch(endpoint)
+            .bearer_auth(self.cfg.api_token())
+            .json(&payload);
+        let resp = self.send(req).await?;
+
+        let result: Result<BitlinkInfo> = parse_response! { resp =>
+            OK
+            ||
+            BAD_REQUEST
+            | PAYMENT_REQUIRED
+            | FORBIDDEN
+            | NOT_FOUND
+            | GONE
+            | UNPROCESSABLE_ENTITY
+            | TOO_MANY_REQUESTS
+            | INTERNAL_SERVER_ERROR
+            | SERVICE_UNAVAILABLE
+        };
+
+        // if the destination has changed then the cached entry must follow
+        if let Ok(ref info) = result
+            && payload.long_url.is_some()
+            && let Some(ref cache) = self.cache
+        {
+            cache.update_long_url(&info.id, &info.long_url).await;
+        }
+
+        result
+    }
+
+    /// Build the payload of an update request for given bitlink
+    async fn update_payload<'a>(
+        &self,
+        bitlink: &BitlinkRef,
+        changes: &'a BitlinkChanges,
+    ) -> Result<Update<'a>> {
+        // NOTE: Bitly replaces the whole list of tags, so we need to know the current ones
+        let tags = if changes.add_tags.is_empty() && changes.remove_tags.is_empty() {
+            None
+        } else {
+            let BitlinkInfo { tags, .. } = self.info(bitlink).await?;
+            Some(changes.apply_tags(tags))
+        };
+
+        Ok(Update {
+            title: changes.title.as_deref().map(Cow::Borrowed),
+            archived: changes.archived,
+            tags,
+            long_url: changes.long_url.as_ref().map(Cow::Borrowed),
+        })
+    }
+
+    /// Plan how given bitlink would be updated, without sending the update request
+    #[instrument(level = "debug", fields(%bitlink), skip(self, bitlink))]
+    async fn plan_update(&self, bitlink: BitlinkRef, changes: &BitlinkChanges) -> Result<Plan> {
+        let payload = self.update_payload(&bitlink, changes).await?;
+        let endpoint = self.api_url(&format!("bitlinks/{}", bitlink.id()));
+        let request = PlannedRequest::new("PATCH", endpoint, &payload)?;
+        Ok(Plan::requests(bitlink.to_string(), vec![request]))
+    }
+
+    #[instrument(level = "debug", skip_all)]
+    fn plan_update_all(
+        self: Arc<Self>,
+        bitlinks: impl Stream<Item = BitlinkRef>,
+        changes: BitlinkChanges,
+    ) -> impl Stream<Item = impl Future<Output = Result<Plan>>> {
+        let changes = Arc::new(changes);
+        bitlinks.map(move |bitlink| {
+            let client = Arc::clone(&self);
+            let changes = Arc::clone(&changes);
+            async move {
+                let input = bitlink.to_string();
+                let result = client.plan_update(bitlink, &changes).await;
+                result.map_err(|error| error.with_input(input))
+            }
+        })
+    }
+
+    #[instrument(level = "debug", skip_all)]
+    fn update_all(
+        self: Arc<Self>,
+        bitlinks: impl Stream<Item = BitlinkRef>,
+        changes: BitlinkChanges,
+    ) -> impl Stream<Item = impl Future<Output = Result<BitlinkInfo>>> {
+        let changes = Arc::new(changes);
+        bitlinks.map(move |bitlink| {
+            let client = Arc::clone(&self);
+            let changes = Arc::clone(&changes);
+            async move {
+                let input = bitlink.to_string();
+                let result = client.update(bitlink, &changes).await;
+                result.map_err(|error| error.with_input(input))
+            }
+        })
+    }
+
+    #[instrument(level = "debug", fields(%bitlink), skip(self, bitlink))]
+    async fn metrics<T>(
+        &self,
+        bitlink: BitlinkRef,
+        metric: Metric,
+        query: &MetricsQuery,
+    ) -> Result<BitlinkMetrics<T>>
+    where
+        T: DeserializeOwned,
+    {
+        let Some(ref http) = self.http else {
+            return Err(Error::Offline("metrics"));
+        };
+
+        let bitlink = bitlink.id();
+        let endpoint = self.api_url(&format!("bitlinks/{bitlink}/{}", metric.endpoint()));
+
+        debug!("fetching bitlink metrics");
+
+        let req = http
+            .get(endpoint)
+            .bearer_auth(self.cfg.api_token())
+            .query(query);
+        let resp = self.send(req).await?;
+
+        let metrics = parse_response! { resp =>
+            OK
+            ||
+            BAD_REQUEST
+            | PAYMENT_REQUIRED
+            | FORBIDDEN
+            | NOT_FOUND
+            | GONE
+            | TOO_MANY_REQUESTS
+            | INTERNAL_SERVER_ERROR
+            | SERVICE_UNAVAILABLE
+        }?;
+
+        Ok(BitlinkMetrics { bitlink, metrics })
+    }
+
+    #[instrument(level = "debug", skip_all)]
+    fn metrics_all<T>(
+        self: Arc<Self>,
+        bitlinks: impl Stream<Item = BitlinkRef>,
+        metric: Metric,
+        query: MetricsQuery,
+    ) -> impl Stream<Item = impl Future<Output = Result<BitlinkMetrics<T>>>>
+    where
+        T: DeserializeOwned,
+    {
+        let query = Arc::new(query);
+        bitlinks.map(move |bitlink| {
+            let client = Arc::clone(&self);
+            let query = Arc::clone(&query);
+            async move {
+                let input = bitlink.to_string();
+                let result = client.metrics(bitlink, metric, &query).await;
+                result.map_err(|error| error.with_input(input))
+            }
+        })
+    }
+
+    /// Lazily list bitlinks of the default group, following the pagination
+    #[instrument(level = "debug", skip(self))]
+    fn list(self: Arc<Self>, filter: ListFilter) -> impl Stream<Item = Result<BitlinkInfo>> {
+        try_stream! {
+            let Some(ref http) = self.http else {
+                Err(Error::Offline("list"))?;
+                return;
+            };
+
+            let group_guid = self.group_guid().await?;
+
+            let mut endpoint = self.api_url(&format!("groups/{group_guid}/bitlinks"));
+            let mut query = Some(filter.query_pairs());
+
+            loop {
+                debug!(%endpoint, "fetching page of bitlinks");
+
+                let mut req = http.get(endpoint).bearer_auth(self.cfg.api_token());
+
+                // NOTE: the next page URL already contains all the query parameters
+                if let Some(query) = query.take() {
+                    req = req.query(&query);
+                }
+
+                let resp = self.send(req).await?;
+
+                let page: BitlinksPage = parse_response! { resp =>
+                    OK
+                    ||
+                    BAD_REQUEST
+                    | PAYMENT_REQUIRED
+                    | FORBIDDEN
+                    | NOT_FOUND
+                    | GONE
+                    | TOO_MANY_REQUESTS
+                    | INTERNAL_SERVER_ERROR
+                    | SERVICE_UNAVAILABLE
+                }?;
+
+                for bitlink in page.links {
+                    if filter.matches(&bitlink) {
+                        yield bitlink;
+                    }
+                }
+
+                match page.pagination.next {
+                    Some(next) => endpoint = next,
+                    None => break,
+                }
+            }
+        }
+    }
+}
+
+/// Run given stream of requests concurrently (up to the `concurrency` limit in flight) and yield
+/// their results either in the input order or as soon as they complete, depending on the
+/// `ordering`.
+///
+/// The limit is checked before each request is started, so it may change over time (see
+/// [`Concurrency::adaptive`]). Like with [`StreamExt::buffered`], in the ordered mode the results
+/// waiting for their predecessors count towards the limit as well.
+fn buffer<'a, S, F, T>(
+    requests: S,
+    ordering: Ordering,
+    concurrency: Arc<Concurrency>,
+) -> impl Stream<Item = T> + 'a
+where
+    S: Stream<Item = F> + 'a,
+    F: Future<Output = T> + 'a,
+    T: 'a,
+{
+    stream! {
+        let mut requests = pin!(requests.fuse());
+        let mut in_flight = FuturesUnordered::new();
+        let mut completed = BTreeMap::new();
+        let mut started = 0usize;
+        let mut next = 0usize;
+        let mut exhausted = false;
+
+        loop {
+            let limit = concurrency.limit();
+            let can_start = !exhausted && in_flight.len() + completed.len() < limit;
+
+            // NOTE: completed requests take precedence to free up their slots as soon as possible
+            let (seq, result) = tokio::select! {
+                biased;
+                Some(completed) = in_flight.next(), if !in_flight.is_empty() => completed,
+                request = requests.next(), if can_start => {
+                    match request {
+                        Some(request) => {
+                            let seq = started;
+                            started += 1;
+                            in_flight.push(request.map(move |result| (seq, result)));
+                            debug!(concurrency = limit, in_flight = in_flight.len(), "request started");
+                        }
+                        None => exhausted = true,
+                    }
+                    continue;
+                }
+                else => break,
+            };
+
+            match ordering {
+                Ordering::Ordered => {
+                    completed.insert(seq, result);
+                    while let Some(result) = completed.remove(&next) {
+                        next += 1;
+                        yield result;
+                    }
+                }
+                Ordering::Unordered => yield result,
+            }
+        }
+    }
+}
+
+pub struct Client {
+    inner: Arc<ClientInner>,
+}
+
+impl Client {
+    /// Initialize the client, failing if the HTTP client cannot be built (e.g., its TLS backend)
+    #[instrument(name = "init_client", level = "debug")]
+    pub async fn new(cfg: Config) -> Result<Self> {
+        let http = if cfg.offline {
+            debug!("offline mode enabled, skipping HTTP client initialization");
+            None
+        } else {
+            debug!("initializing HTTP client");
+
+            let mut http = reqwest::Client::builder();
+
+            if let Some(timeout) = cfg.request_timeout() {
+                http = http.timeout(timeout);
+            }
+
+            if let Some(timeout) = cfg.connect_timeout() {
+                http = http.connect_timeout(timeout);
+            }
+
+            Some(http.build()?)
+        };
+
+        let cache = BitlinkCache::new(VERSION, cfg.cache_dir.as_ref()).await;
+
+        let max_concurrent = NonZeroUsize::new(cfg.max_concurrent).unwrap_or(NonZeroUsize::MIN);
+        let concurrency = if cfg.adaptive_concurrency {
+            let ceiling = NonZeroUsize::new(cfg.max_concurrent_ceiling).unwrap_or(max_concurrent);
+            Concurrency::adaptive(max_concurrent, ceiling)
+        } else {
+            Concurrency::fixed(max_concurrent)
+        };
+
+        let cassette = cfg.cassette.clone().map(Cassette::new);
+
+        let mut inner = ClientInner {
+            cfg,
+            http,
+            cache,
+            group_guid: OnceCell::new(),
+            limiter: None,
+            concurrency: Arc::new(concurrency),
+            quota: None,
+            in_flight: Mutex::default(),
+            cassette,
+        };
+
+        // NOTE: the platform limits determine both the quota and (unless configured) the rate
+        let limit = match inner.http {
+            Some(_) => inner.shorten_limit().await,
+            None => None,
+        };
+
+        let rate = match inner.cfg.rate_limit {
+            Some(rate) => NonZeroU32::new(rate),
+            None => Some(rate_limit(limit.as_ref())),
+        };
+
+        debug!(?rate, "rate limit of API requests");
+
+        inner.limiter = rate.map(RateLimiter::new);
+        inner.quota = limit.map(|MethodLimit { limit, count, .. }| Quota::new(limit, count));
+
+        Ok(Self {
+            inner: Arc::new(inner),
+        })
+    }
+
+    /// Shorten given URLs, passing the key of each URL along with its result
+    ///
+    /// The key identifies the input of each result (e.g., to journal it, see `--job`), which is
+    /// necessary in the unordered mode.
+    #[instrument(level = "debug", skip(self, urls))]
+    pub fn shorten_keyed<'a, K, S>(
+        &self,
+        urls: S,
+        fields: BitlinkFields,
+        ordering: Ordering,
+    ) -> impl Stream<Item = (K, Result<Bitlink>)> + 'a
+    where
+        K: 'a,
+        S: Stream<Item = (K, Url)> + Send + 'a,
+    {
+        let client = Arc::clone(&self.inner);
+        let concurrency = Arc::clone(&client.concurrency);
+        buffer(client.shorten_all(urls, fields), ordering, concurrency)
+    }
+
+    /// Plan how given URLs would be shortened (see [`Plan`]), without creating any bitlinks
+    #[instrument(level = "debug", skip(self, urls))]
+    pub fn plan_shorten<'a, K, S>(
+        &self,
+        urls: S,
+        fields: BitlinkFields,
+        ordering: Ordering,
+    ) -> impl Stream<Item = (K, Result<Plan>)> + 'a
+    where
+        K: 'a,
+        S: Stream<Item = (K, Url)> + Send + 'a,
+    {
+        let client = Arc::clone(&self.inner);
+        let concurrency = Arc::clone(&client.concurrency);
+        buffer(client.plan_shorten_all(urls, fields), ordering, concurrency)
+    }
+
+    #[instrument(level = "debug", skip(self, bitlinks))]
+    pub fn expand<'a, S>(
+        &self,
+        bitlinks: S,
+        ordering: Ordering,
+    ) -> impl Stream<Item = Result<Bitlink>> + 'a
+    where
+        S: Stream<Item = BitlinkRef> + Send + 'a,
+    {
+        let client = Arc::clone(&self.inner);
+        let concurrency = Arc::clone(&client.concurrency);
+        buffer(client.expand_all(bitlinks), ordering, concurrency)
+    }
+
+    #[instrument(level = "debug", skip(self, bitlinks))]
+    pub fn info<'a, S>(
+        &self,
+        bitlinks: S,
+        ordering: Ordering,
+    ) -> impl Stream<Item = Result<BitlinkInfo>> + 'a
+    where
+        S: Stream<Item = BitlinkRef> + Send + 'a,
+    {
+        let client = Arc::clone(&self.inner);
+        let concurrency = Arc::clone(&client.concurrency);
+        buffer(client.info_all(bitlinks), ordering, concurrency)
+    }
+
+    #[instrument(level = "debug", skip(self, bitlinks))]
+    pub fn update<'a, S>(
+        &self,
+        bitlinks: S,
+        changes: BitlinkChanges,
+        ordering: Ordering,
+    ) -> impl Stream<Item = Result<BitlinkInfo>> + 'a
+    where
+        S: Stream<Item = BitlinkRef> + Send + 'a,
+    {
+        let client = Arc::clone(&self.inner);
+        let concurrency = Arc::clone(&client.concurrency);
+        let requests = client.update_all(bitlinks, changes);
+        buffer(requests, ordering, concurrency)
+    }
+
+    /// Plan how given bitlinks would be updated (see [`Plan`]), without changing them
+    #[instrument(level = "debug", skip(self, bitlinks))]
+    pub fn plan_update<'a, S>(
+        &self,
+        bitlinks: S,
+        changes: BitlinkChanges,
+        ordering: Ordering,
+    ) -> impl Stream<Item = Result<Plan>> + 'a
+    where
+        S: Stream<Item = BitlinkRef> + Send + 'a,
+    {
+        let client = Arc::clone(&self.inner);
+        let concurrency = Arc::clone(&client.concurrency);
+        let requests = client.plan_update_all(bitlinks, changes);
+        buffer(requests, ordering, concurrency)
+    }
+
+    /// Lazily list bitlinks of the default group matching given `filter`
+    ///
+    /// Pages of bitlinks are fetched on demand as the stream is consumed.
+    #[inline]
+    pub fn list(&self, filter: ListFilter) -> impl Stream<Item = Result<BitlinkInfo>> + 'static {
+        Arc::clone(&self.inner).list(filter)
+    }
+
+    /// Fetch groups of the user, optionally only those of given organization
+    #[inline]
+    pub async fn groups(&self, organization_guid: Option<&str>) -> Result<Vec<Group>> {
+        self.inner.fetch_groups(organization_guid).await
+    }
+
+    /// Fetch organizations of the user
+    #[inline]
+    pub async fn organizations(&self) -> Result<Vec<Organization>> {
+        self.inner.fetch_organizations().await
+    }
+
+    /// Fetch given `metric` for each of the bitlinks
+    ///
+    /// Note that the type of the metrics `T` must correspond to the response of the `metric`
+    /// endpoint (e.g., [`Clicks`] for [`Metric::Clicks`]).
+    #[instrument(level = "debug", skip(self, bitlinks))]
+    pub fn metrics<'a, S, T>(
+        &self,
+        bitlinks: S,
+        metric: Metric,
+        query: MetricsQuery,
+        ordering: Ordering,
+    ) -> impl Stream<Item = Result<BitlinkMetrics<T>>> + 'a
+    where
+        S: Stream<Item = BitlinkRef> + Send + 'a,
+        T: DeserializeOwned + 'a,
+    {
+        let client = Arc::clone(&self.inner);
+        let concurrency = Arc::clone(&client.concurrency);
+        let requests = client.metrics_all(bitlinks, metric, query);
+        buffer(requests, ordering, concurrency)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use rstest::*;
+
+    use crate::cassette::CassetteMode;
+    use crate::error::Diagnostic;
+
+    use std::path::PathBuf;
+    use std::sync::atomic::AtomicUsize;
+
+    use futures_util::stream;
+    use wiremock::matchers::{
+        body_json, body_partial_json, method, path, query_param, query_param_is_missing,
+    };
+    use wiremock::{Mock, MockServer, Respond, ResponseTemplate};
+
+    struct LinkResponder {
+        resp_num: AtomicUsize,
+        responses: Vec<String>,
+    }
+
+    impl LinkResponder {
+        fn new(ordering: Ordering, responses: impl IntoIterator<Item = impl Into<String>>) -> Self {
+            let mut responses = responses.into_iter().map(Into::<String>::into).collect();
+            Self {
+                resp_num: AtomicUsize::new(0),
+                responses: match ordering {
+                    Ordering::Ordered => responses,
+                    Ordering::Unordered => {
+                        responses.reverse();
+                        responses
+                    }
+                },
+            }
+        }
+    }
+
+    impl Respond for LinkResponder {
+        fn respond(&self, _request: &wiremock::Request) -> ResponseTemplate {
+            let i = self
+                .resp_num
+                .fetch_add(1, std::sync::atomic::Ordering::Relaxed);
+
+            let body = self.responses[i].as_bytes();
+
+            ResponseTemplate::new(StatusCode::OK).set_body_raw(body, "application/json")
+        }
+    }
+
+    struct ShortenTest {
+        urls: Vec<Url>,
+        responder: LinkResponder,
+        expected: Vec<Bitlink>,
+    }
+
+    struct ServerConfig {
+        server: MockServer,
+        config: Config,
+    }
+
+    // NOTE: starts mock server on a random local port
+    #[fixture]
+    async fn server() -> MockServer {
+        MockServer::start().await
+    }
+
+    // NOTE: disables caching
+    #[fixture]
+    fn config() -> Config {
+        Config {
+            api_url: Url::parse("https://api-ssl.bitly.com").unwrap(),
+            api_token: "secret-token".into(),
+            domain: Some("test.domain".to_string()),
+            default_group_guid: Some("test-group-guid".to_string()),
+            default_group: None,
+            cache_dir: Some(PathBuf::new()),
+            cache_max_age_s: 0,
+            offline: false,
+            max_concurrent: 4,
+            adaptive_concurrency: false,
+            max_concurrent_ceiling: 64,
+            rate_limit: Some(0),
+            request_timeout_ms: 5_000,
+            connect_timeout_ms: 5_000,
+            retry: RetryPolicy {
+                base_delay_ms: 1,
+                max_delay_ms: 10,
+                jitter: false,
+                ..RetryPolicy::default()
+            },
+            cassette: None,
+        }
+    }
+
+    #[fixture]
+    async fn server_config(#[future(awt)] server: MockServer, mut config: Config) -> ServerConfig {
+        config.with_api_url(server.uri().parse().expect("valid mock API URL"));
+
+        // NOTE: mounted with low priority so that tests can override it
+        Mock::given(method("GET"))
+            .and(path("v4/user/platform_limits"))
+            .respond_with(platform_limits(1_000, 0))
+            .with_priority(u8::MAX)
+            .mount(&server)
+            .await;
+
+        ServerConfig { server, config }
+    }
+
+    fn platform_limits(limit: u64, count: u64) -> ResponseTemplate {
+        ResponseTemplate::new(StatusCode::OK).set_body_json(serde_json::json!({
+            "platform_limits": [{
+                "endpoint": "/v4/shorten",
+                "methods": [{ "name": "POST", "limit": limit, "count": count }]
+            }]
+        }))
+    }
+
+    #[fixture]
+    fn urls() -> Vec<Url> {
+        vec![
+            Url::parse("https://example.com").unwrap(),
+            Url::parse("http://example.com").unwrap(),
+        ]
+    }
+
+    #[fixture]
+    fn shorten_test(
+        #[default(Ordering::Ordered)] ordering: Ordering,
+        urls: Vec<Url>,
+    ) -> ShortenTest {
+        ShortenTest {
+            urls,
+            responder: LinkResponder::new(
+                ordering,
+                [
+                    r#"{
+                      "created_at": "2024-08-07T08:48:48+0000",
+                      "id": "1",
+                      "link": "https://test.domain/4ePsyXN",
+                      "custom_bitlinks": [],
+                      "long_url": "https://example.com",
+                      "archived": false,
+                      "tags": [],
+                      "deeplinks": [],
+                      "references": {
+                        "group": "https://api-ssl.bitly.com/v4/groups/test-group-guid"
+                      }
+                    }"#,
+                    r#"{
+                      "created_at": "2024-08-07T08:48:49+0000",
+                      "id": "2",
+                      "link": "https://test.domain/3WA1XXp",
+                      "custom_bitlinks": [],
+                      "long_url": "http://example.com",
+                      "archived": false,
+                      "tags": [],
+                      "deeplinks": [],
+                      "references": {
+                        "group": "https://api-ssl.bitly.com/v4/groups/test-group-guid"
+                      }
+                    }"#,
+                ],
+            ),
+            expected: vec![
+                Bitlink {
+                    link: "https://test.domain/4ePsyXN".parse().unwrap(),
+                    id: "1".to_string(),
+                    long_url: "https://example.com".parse().unwrap(),
+                },
+                Bitlink {
+                    link: "https://test.domain/3WA1XXp".parse().unwrap(),
+                    id: "2".to_string(),
+                    long_url: "http://example.com".parse().unwrap(),
+                },
+            ],
+        }
+    }
+
+    async fn test_shorten(
+        config: Config,
+        urls: Vec<Url>,
+        ordering: Ordering,
+    ) -> Vec<Result<Bitlink>> {
+        // TODO: parametrize client by cache to be able to mock it for tests
+        let client = Client::new(config).await.expect("initialized client");
+        client
+            .shorten_keyed(
+                stream::iter(urls).map(|url| ((), url)),
+                BitlinkFields::default(),
+                ordering,
+            )
+            .map(|((), result)| result)
+            .collect::<Vec<_>>()
+            .await
+    }
+
+    #[rstest]
+    #[tokio::test]
+    async fn shorten_urls_ordered(
+        #[future(awt)] server_config: ServerConfig,
+        #[from(shorten_test)] ShortenTest {
+            urls,
+            responder,
+            expected,
+        }: ShortenTest,
+    ) {
+        let ServerConfig { server, config } = server_config;
+
+        Mock::given(method("POST"))
+            .and(path("v4/shorten"))
+            .respond_with(responder)
+            .mount(&server)
+            .await;
+
+        let results = test_shorten(config, urls, Ordering::Ordered).await;
+
+        match results.into_iter().collect::<Result<Vec<_>>>() {
+            Ok(actual) => assert_eq!(expected, actual),
+            Err(error) => panic!("encountered API/client error: {error:?}"),
+        }
+    }
+
+    #[rstest]
+    #[tokio::test]
+    async fn shorten_urls_unordered(
+        #[future(awt)] server_config: ServerConfig,
+        #[from(shorten_test)]
+        #[with(Ordering::Unordered)]
+        ShortenTest {
+            urls,
+            responder,
+            expected,
+        }: ShortenTest,
+    ) {
+        let ServerConfig { server, config } = server_config;
+
+        Mock::given(method("POST"))
+            .and(path("v4/shorten"))
+            .respond_with(responder)
+            .mount(&server)
+            .await;
+
+        let results = test_shorten(config, urls, Ordering::Unordered).await;
+
+        match results.into_iter().collect::<Result<Vec<_>>>() {
+            Ok(mut actual) => {
+                actual.sort_by_cached_key(|link| link.id.parse::<u8>().expect("use u8 IDs"));
+                assert_eq!(expected, actual)
+            }
+            Err(error) => panic!("encountered API/client error: {error:?}"),
+        }
+    }
+
+    #[rstest]
+    #[tokio::test]
+    async fn shorten_with_fields(#[future(awt)] server_config: ServerConfig) {
+        let ServerConfig { server, config } = server_config;
+
+        let created = ResponseTemplate::new(StatusCode::CREATED).set_body_raw(
+            r#"{
+              "created_at": "2024-08-07T08:48:48+0000",
+              "id": "test.domain/4ePsyXN",
+              "link": "https://test.domain/4ePsyXN",
+              "title": "Launch",
+              "long_url": "https://example.com",
+              "tags": ["campaign"]
+            }"#,
+            "application/json",
+        );
+
+        // NOTE: the (used up) shorten quota does not apply to bitlinks created with extra fields
+        Mock::given(method("GET"))
+            .and(path("v4/user/platform_limits"))
+            .respond_with(platform_limits(10, 10))
+            .expect(1)
+            .mount(&server)
+            .await;
+
+        Mock::given(method("POST"))
+            .and(path("v4/bitlinks"))
+            .and(body_json(serde_json::json!({
+                "long_url": "https://example.com/",
+                "domain": "test.domain",
+                "group_guid": "test-group-guid",
+                "title": "Launch",
+                "tags": ["campaign"],
+            })))
+            .respond_with(created)
+            .expect(1)
+            .mount(&server)
+            .await;
+
+        let fields = BitlinkFields {
+            title: Some("Launch".to_string()),
+            tags: vec!["campaign".to_string()],
+            ..Default::default()
+        };
+
+        let urls = stream::iter([Url::parse("https://example.com").unwrap()]);
+
+        let client = Client::new(config).await.expect("initialized client");
+        let results = client
+            .shorten_keyed(urls.map(|url| ((), url)), fields, Ordering::Ordered)
+            .map(|((), result)| result)
+            .collect::<Vec<_>>()
+            .await;
+
+        let expected = vec![Bitlink {
+            link: "https://test.domain/4ePsyXN".parse().unwrap(),
+            id: "test.domain/4ePsyXN".to_string(),
+            long_url: "https://example.com".parse().unwrap(),
+        }];
+
+        match results.into_iter().collect::<Result<Vec<_>>>() {
+            Ok(actual) => assert_eq!(expected, actual),
+            Err(error) => panic!("encountered API/client error: {error:?}"),
+        }
+    }
+
+    #[fixture]
+    fn keyword_fields() -> BitlinkFields {
+        BitlinkFields {
+            keyword: Some("launch".to_string()),
+            ..Default::default()
+        }
+    }
+
+    #[rstest]
+    #[tokio::test]
+    async fn shorten_with_keyword(
+        #[future(awt)] server_config: ServerConfig,
+        #[from(keyword_fields)] fields: BitlinkFields,
+    ) {
+        let ServerConfig { server, config } = server_config;
+
+        let created = ResponseTemplate::new(StatusCode::CREATED).set_body_raw(
+            r#"{
+              "id": "test.domain/4ePsyXN",
+              "link": "https://test.domain/4ePsyXN",
+              "long_url": "https://example.com"
+            }"#,
+            "application/json",
+        );
+
+        let custom = ResponseTemplate::new(StatusCode::OK).set_body_raw(
+            r#"{
+              "custom_bitlink": "test.domain/launch",
+              "bitlink": {
+                "id": "test.domain/4ePsyXN",
+                "link": "https://test.domain/4ePsyXN",
+                "long_url": "https://example.com"
+              },
+              "bitlink_history": []
+            }"#,
+            "application/json",
+        );
+
+        Mock::given(method("POST"))
+            .and(path("v4/shorten"))
+            .respond_with(created)
+            .expect(1)
+            .mount(&server)
+            .await;
+
+        Mock::given(method("POST"))
+            .and(path("v4/custom_bitlinks"))
+            .and(body_json(serde_json::json!({
+                "custom_bitlink": "test.domain/launch",
+                "bitlink_id": "test.domain/4ePsyXN",
+            })))
+            .respond_with(custom)
+            .expect(1)
+            .mount(&server)
+            .await;
+
+        let urls = stream::iter([Url::parse("https://example.com").unwrap()]);
+
+        let client = Client::new(config).await.expect("initialized client");
+        let results = client
+            .shorten_keyed(urls.map(|url| ((), url)), fields, Ordering::Ordered)
+            .map(|((), result)| result)
+            .collect::<Vec<_>>()
+            .await;
+
+        let expected = vec![Bitlink {
+            link: "https://test.domain/launch".parse().unwrap(),
+            id: "test.domain/launch".to_string(),
+            long_url: "https://example.com".parse().unwrap(),
+        }];
+
+        match results.into_iter().collect::<Result<Vec<_>>>() {
+            Ok(actual) => assert_eq!(expected, actual),
+            Err(error) => panic!("encountered API/client error: {error:?}"),
+        }
+    }
+
+    #[rstest]
+    #[tokio::test]
+    async fn shorten_with_taken_keyword(
+        #[future(awt)] server_config: ServerConfig,
+        #[from(keyword_fields)] fields: BitlinkFields,
+    ) {
+        let ServerConfig { server, config } = server_config;
+
+        let created = ResponseTemplate::new(StatusCode::CREATED).set_body_raw(
+            r#"{
+              "id": "test.domain/4ePsyXN",
+              "link": "https://test.domain/4ePsyXN",
+              "long_url": "https://example.com"
+            }"#,
+            "application/json",
+        );
+
+        let taken = ResponseTemplate::new(StatusCode::CONFLICT).set_body_raw(
+            r#"{"message": "ALREADY_EXISTS", "resource": "custom_bitlinks"}"#,
+            "application/json",
+        );
+
+        Mock::given(method("POST"))
+            .and(path("v4/shorten"))
+            .respond_with(created)
+            .mount(&server)
+            .await;
+
+        Mock::given(method("POST"))
+            .and(path("v4/custom_bitlinks"))
+            .respond_with(taken)
+            .mount(&server)
+            .await;
+
+        let urls = stream::iter([Url::parse("https://example.com").unwrap()]);
+
+        let client = Client::new(config).await.expect("initialized client");
+        let results = client
+            .shorten_keyed(urls.map(|url| ((), url)), fields, Ordering::Ordered)
+            .map(|((), result)| result)
+            .collect::<Vec<_>>()
+            .await;
+
+        match results
+            .into_iter()
+            .collect::<Result<Vec<_>>>()
+            .map_err(Error::without_input)
+        {
+            Err(Error::KeywordTaken(custom)) => assert_eq!("test.domain/launch", custom),
+            result => panic!("expected taken keyword error, got: {result:?}"),
+        }
+    }
+
+    #[rstest]
+    #[tokio::test]
+    async fn shorten_auth_error(#[future(awt)] server_config: ServerConfig, urls: Vec<Url>) {
+        let ServerConfig { server, config } = server_config;
+
+        let forbidden = ResponseTemplate::new(StatusCode::FORBIDDEN)
+            .set_body_raw(r#"{"message": "FORBIDDEN"}"#, "application/json");
+
+        Mock::given(method("POST"))
+            .and(path("v4/shorten"))
+            .respond_with(forbidden)
+            .mount(&server)
+            .await;
+
+        let results = test_shorten(config, urls, Ordering::Ordered).await;
+
+        match results
+            .into_iter()
+            .collect::<Result<Vec<_>>>()
+            .map_err(Error::without_input)
+        {
+            Ok(links) => panic!("expected API error (FORBIDDEN), got: {links:?}"),
+            Err(Error::Bitly(resp)) => assert_eq!("FORBIDDEN", resp.message),
+            Err(error) => panic!("expected API error (FORBIDDEN), got: {error:?}"),
+        }
+    }
+
+    #[rstest]
+    #[tokio::test]
+    async fn shorten_invalid_long_url(#[future(awt)] server_config: ServerConfig) {
+        let ServerConfig { server, config } = server_config;
+
+        let invalid = ResponseTemplate::new(StatusCode::BAD_REQUEST).set_body_raw(
+            r#"{
+              "message": "INVALID_ARG_LONG_URL",
+              "resource": "bitlinks",
+              "description": "The value provided is invalid.",
+              "errors": [{"field": "long_url", "error_code": "invalid"}]
+            }"#,
+            "application/json",
+        );
+
+        Mock::given(method("POST"))
+            .and(path("v4/shorten"))
+            .respond_with(invalid)
+            .mount(&server)
+            .await;
+
+        let urls = vec![Url::parse("ftp://example.com").unwrap()];
+        let results = test_shorten(config, urls, Ordering::Ordered).await;
+
+        match results.into_iter().collect::<Result<Vec<_>>>() {
+            Ok(links) => panic!("expected rejected long URL, got: {links:?}"),
+            Err(Error::Rejected {
+                input,
+                diagnostic: Diagnostic::InvalidLongUrl,
+                source,
+            }) => {
+                assert_eq!("ftp://example.com/", input);
+                assert_eq!("INVALID_ARG_LONG_URL", source.message);
+            }
+            Err(error) => panic!("expected rejected long URL, got: {error:?}"),
+        }
+    }
+
+    /// Respond to a shorten request with a bitlink derived from the path of its long URL
+    const RESPONSE_DELAY: Duration = Duration::from_millis(50);
+
+    fn respond_with_link(request: &wiremock::Request) -> ResponseTemplate {
+        let payload: serde_json::Value = request.body_json().expect("shorten payload");
+        let long_url = payload["long_url"].as_str().expect("long URL");
+        let id = long_url.trim_start_matches("https://example.com/");
+        ResponseTemplate::new(StatusCode::OK)
+            .set_body_json(serde_json::json!({
+                "id": format!("test.domain/{id}"),
+                "link": format!("https://test.domain/{id}"),
+                "long_url": long_url,
+            }))
+            .set_delay(RESPONSE_DELAY)
+    }
+
+    #[rstest]
+    #[case(Ordering::Ordered)]
+    #[case(Ordering::Unordered)]
+    #[tokio::test]
+    async fn shorten_adaptive_concurrency(
+        #[future(awt)] server_config: ServerConfig,
+        #[case] ordering: Ordering,
+    ) {
+        let ServerConfig { server, mut config } = server_config;
+
+        config.max_concurrent = 2;
+        config.adaptive_concurrency = true;
+        config.max_concurrent_ceiling = 4;
+
+        let too_many_requests = ResponseTemplate::new(StatusCode::TOO_MANY_REQUESTS)
+            .insert_header("Retry-After", "0")
+            .set_body_raw(r#"{"message": "RATE_LIMIT_EXCEEDED"}"#, "application/json");
+
+        Mock::given(method("POST"))
+            .and(path("v4/shorten"))
+            .respond_with(too_many_requests)
+            .up_to_n_times(2)
+            .with_priority(1)
+            .mount(&server)
+            .await;
+
+        let arrivals = Arc::new(Mutex::new(Vec::new()));
+
+        Mock::given(method("POST"))
+            .and(path("v4/shorten"))
+            .respond_with({
+                let arrivals = Arc::clone(&arrivals);
+                move |request: &wiremock::Request| {
+                    arrivals
+                        .lock()
+                        .expect("request arrivals")
+                        .push(std::time::Instant::now());
+                    respond_with_link(request)
+                }
+            })
+            .mount(&server)
+            .await;
+
+        let urls = (0..12)
+            .map(|id| Url::parse(&format!("https://example.com/{id}")).unwrap())
+            .collect::<Vec<_>>();
+
+        let client = Client::new(config).await.expect("initialized client");
+
+        assert_eq!(2, client.inner.concurrency.limit(), "initial limit");
+
+        let results = client
+            .shorten_keyed(
+                stream::iter(urls.clone()).map(|url| ((), url)),
+                BitlinkFields::default(),
+                ordering,
+            )
+            .map(|((), result)| result)
+            .collect::<Vec<_>>()
+            .await;
+
+        // NOTE: backs off to a single request in flight and then grows with each success
+        assert_eq!(
+            4,
+            client.inner.concurrency.limit(),
+            "grown up to the ceiling"
+        );
+
+        // NOTE: a request has been in flight at least during the response delay at the server
+        let arrivals = arrivals.lock().expect("request arrivals");
+        let peak = arrivals
+            .iter()
+            .map(|&at| {
+                arrivals
+                    .iter()
+                    .filter(|&&other| other <= at && at < other + RESPONSE_DELAY)
+                    .count()
+            })
+            .max();
+
+        assert!(
+            matches!(peak, Some(peak) if (1..=4).contains(&peak)),
+            "peak of requests in flight {peak:?} exceeds the ceiling"
+        );
+
+        let mut actual = results
+            .into_iter()
+            .map(|result| result.expect("shortened URL").long_url)
+            .collect::<Vec<_>>();
+
+        let mut expected = urls;
+
+        if let Ordering::Unordered = ordering {
+            actual.sort();
+            expected.sort();
+        }
+
+        assert_eq!(expected, actual);
+    }
+
+    /// Make all the bitlinks cached in given directory older by given number of seconds
+    async fn backdate_cache(cache_dir: &std::path::Path, secs: i64) {
+        let db = cache_dir.join(format!("{VERSION}.db"));
+        let pool = sqlx::SqlitePool::connect(&format!("sqlite:{}", db.display()))
+            .await
+            .expect("cache database");
+        sqlx::query("UPDATE shorten SET cached_at = cached_at - $1")
+            .bind(secs)
+            .execute(&pool)
+            .await
+            .expect("backdate entries");
+        pool.close().await;
+    }
+
+    #[rstest]
+    #[tokio::test]
+    async fn plan_stale_bitlinks(#[future(awt)] server_config: ServerConfig) {
+        let ServerConfig { server, mut config } = server_config;
+
+        let cache_dir = tempfile::tempdir().expect("temp cache dir");
+        config.cache_dir = Some(cache_dir.path().to_path_buf());
+        config.cache_max_age_s = 60 * 60;
+
+        // NOTE: the plan doesn't revalidate the stale bitlink, it just lists the request
+        Mock::given(method("POST"))
+            .and(path("v4/shorten"))
+            .respond_with(respond_with_link)
+            .expect(1)
+            .mount(&server)
+            .await;
+
+        let url = Url::parse("https://example.com/cached").unwrap();
+
+        let client = Client::new(config).await.expect("initialized client");
+
+        let created = client
+            .shorten_keyed(
+                stream::iter([url.clone()]).map(|url| ((), url)),
+                BitlinkFields::default(),
+                Ordering::Ordered,
+            )
+            .map(|((), result)| result)
+            .collect::<Vec<_>>()
+            .await;
+        assert!(created.iter().all(Result::is_ok), "{created:?}");
+
+        backdate_cache(cache_dir.path(), 7200).await;
+
+        let urls = stream::iter([url.clone()]).map(|url| ((), url));
+        let plans = client
+            .plan_shorten(urls, BitlinkFields::default(), Ordering::Ordered)
+            .map(|((), plan)| plan.expect("planned shorten"))
+            .collect::<Vec<_>>()
+            .await;
+
+        let [plan] = plans.as_slice() else {
+            panic!("expected a single plan, got: {plans:?}");
+        };
+
+        assert!(plan.stale, "{plan:?}");
+        assert_eq!(
+            Some("https://test.domain/cached"),
+            plan.cached.as_ref().map(Url::as_str)
+        );
+
+        let [request] = plan.requests.as_slice() else {
+            panic!("expected a single request, got: {plan:?}");
+        };
+        assert_eq!("GET", request.method);
+        assert_eq!("/v4/bitlinks/test.domain/cached", request.url.path());
+
+        assert_eq!(
+            format!(
+                "{url}\tstale\thttps://test.domain/cached\n  GET {}",
+                request.url
+            ),
+            plan.to_string()
+        );
+    }
+
+    #[rstest]
+    #[tokio::test]
+    async fn plan_shorten_without_creating_bitlinks(#[future(awt)] server_config: ServerConfig) {
+        let ServerConfig { server, mut config } = server_config;
+
+        let cache_dir = tempfile::tempdir().expect("temp cache dir");
+        config.cache_dir = Some(cache_dir.path().to_path_buf());
+
+        // NOTE: only the bitlink created before planning hits the API
+        Mock::given(method("POST"))
+            .and(path("v4/shorten"))
+            .respond_with(respond_with_link)
+            .expect(1)
+            .mount(&server)
+            .await;
+
+        let cached = Url::parse("https://example.com/cached").unwrap();
+        let fresh = Url::parse("https://example.com/fresh").unwrap();
+
+        let client = Client::new(config).await.expect("initialized client");
+
+        let created = client
+            .shorten_keyed(
+                stream::iter([cached.clone()]).map(|url| ((), url)),
+                BitlinkFields::default(),
+                Ordering::Ordered,
+            )
+            .map(|((), result)| result)
+            .collect::<Vec<_>>()
+            .await;
+        assert!(created.iter().all(Result::is_ok), "{created:?}");
+
+        let urls = stream::iter([cached.clone(), fresh.clone()]).map(|url| ((), url));
+        let plans = client
+            .plan_shorten(urls, BitlinkFields::default(), Ordering::Ordered)
+            .map(|((), plan)| plan.expect("planned shorten"))
+            .collect::<Vec<_>>()
+            .await;
+
+        let [cached_plan, fresh_plan] = plans.as_slice() else {
+            panic!("expected two plans, got: {plans:?}");
+        };
+
+        assert_eq!(cached.as_str(), cached_plan.input);
+        assert_eq!(
+            Some("https://test.domain/cached"),
+            cached_plan.cached.as_ref().map(Url::as_str)
+        );
+        assert!(cached_plan.requests.is_empty());
+
+        assert_eq!(None, fresh_plan.cached);
+        let [request] = fresh_plan.requests.as_slice() else {
+            panic!("expected a single request, got: {fresh_plan:?}");
+        };
+        assert_eq!("POST", request.method);
+        assert_eq!("/v4/shorten", request.url.path());
+        assert_eq!(
+            serde_json::json!({
+                "long_url": "https://example.com/fresh",
+                "domain": "test.domain",
+                "group_guid": "test-group-guid",
+            }),
+            request.payload
+        );
+    }
+
+    #[rstest]
+    #[tokio::test]
+    async fn replay_recorded_shorten(#[future(awt)] server_config: ServerConfig, urls: Vec<Url>) {
+        let ServerConfig { server, mut config } = server_config;
+
+        Mock::given(method("POST"))
+            .and(path("v4/shorten"))
+            .respond_with(respond_with_link)
+            .expect(2)
+            .mount(&server)
+            .await;
+
+        let cassette = tempfile::tempdir().expect("temp cassette dir");
+        let dir = cassette.path().to_path_buf();
+
+        config.cassette = Some(CassetteMode::Record(dir.clone()));
+        let client = Client::new(config).await.expect("initialized client");
+
+        let recorded = client
+            .shorten_keyed(
+                stream::iter(urls.clone()).map(|url| ((), url)),
+                BitlinkFields::default(),
+                Ordering::Ordered,
+            )
+            .map(|((), result)| result)
+            .collect::<Vec<_>>()
+            .await
+            .into_iter()
+            .collect::<Result<Vec<_>>>()
+            .expect("recorded bitlinks");
+
+        let ClientInner { mut cfg, .. } = Arc::into_inner(client.inner).expect("unique client");
+
+        // NOTE: the replay must not touch the API, which is gone by now
+        server.verify().await;
+        drop(server);
+
+        cfg.cassette = Some(CassetteMode::Replay(dir));
+        let client = Client::new(cfg).await.expect("initialized client");
+
+        let replayed = client
+            .shorten_keyed(
+                stream::iter(urls).map(|url| ((), url)),
+                BitlinkFields::default(),
+                Ordering::Ordered,
+            )
+            .map(|((), result)| result)
+            .collect::<Vec<_>>()
+            .await
+            .into_iter()
+            .collect::<Result<Vec<_>>>()
+            .expect("replayed bitlinks");
+
+        assert_eq!(recorded, replayed);
+    }
+
+    #[rstest]
+    #[case(Ordering::Ordered)]
+    #[case(Ordering::Unordered)]
+    #[tokio::test]
+    async fn shorten_duplicate_urls(
+        #[future(awt)] server_config: ServerConfig,
+        #[case] ordering: Ordering,
+    ) {
+        let ServerConfig { server, mut config } = server_config;
+
+        // NOTE: all the duplicates must be in flight at once (the cache is disabled)
+        config.max_concurrent = 8;
+
+        Mock::given(method("POST"))
+            .and(path("v4/shorten"))
+            .respond_with(respond_with_link)
+            .expect(2)
+            .mount(&server)
+            .await;
+
+        let urls = ["a", "b", "a", "a", "b"]
+            .into_iter()
+            .map(|id| Url::parse(&format!("https://example.com/{id}")).unwrap())
+            .collect::<Vec<_>>();
+
+        let results = test_shorten(config, urls.clone(), ordering).await;
+
+        let mut actual = results
+            .into_iter()
+            .map(|result| result.expect("shortened URL").long_url)
+            .collect::<Vec<_>>();
+
+        let mut expected = urls;
+
+        if let Ordering::Unordered = ordering {
+            actual.sort();
+            expected.sort();
+        }
+
+        assert_eq!(expected, actual);
+    }
+
+    #[rstest]
+    #[tokio::test]
+    async fn shorten_duplicate_urls_rejected(#[future(awt)] server_config: ServerConfig) {
+        let ServerConfig { server, mut config } = server_config;
+
+        // NOTE: all the duplicates must be in flight at once (the cache is disabled)
+        config.max_concurrent = 8;
+
+        let invalid = ResponseTemplate::new(StatusCode::BAD_REQUEST)
+            .set_body_raw(r#"{"message": "INVALID_ARG_LONG_URL"}"#, "application/json")
+            .set_delay(RESPONSE_DELAY);
+
+        // one request for the duplicates in flight and another one for the repeated batch
+        Mock::given(method("POST"))
+            .and(path("v4/shorten"))
+            .respond_with(invalid)
+            .expect(2)
+            .mount(&server)
+            .await;
+
+        let client = Client::new(config).await.expect("initialized client");
+        let url = Url::parse("ftp://example.com").unwrap();
+
+        for _ in 0..2 {
+            let results = client
+                .shorten_keyed(
+                    stream::iter(vec![url.clone(); 3]).map(|url| ((), url)),
+                    BitlinkFields::default(),
+                    Ordering::Unordered,
+                )
+                .map(|((), result)| result)
+                .collect::<Vec<_>>()
+                .await;
+
+            assert_eq!(3, results.len());
+
+            for result in results {
+                match result {
+                    Ok(link) => panic!("expected rejected long URL, got: {link:?}"),
+                    Err(Error::Rejected {
+                        input,
+                        diagnostic: Diagnostic::InvalidLongUrl,
+                        ..
+                    }) => assert_eq!("ftp://example.com/", input),
+                    Err(error) => panic!("expected rejected long URL, got: {error:?}"),
+                }
+            }
+        }
+    }
+
+    #[rstest]
+    #[tokio::test]
+    async fn shorten_retry_transient_errors(
+        #[future(awt)] server_config: ServerConfig,
+        #[from(shorten_test)] ShortenTest {
+            urls,
+            responder,
+            expected,
+        }: ShortenTest,
+    ) {
+        let ServerConfig { server, mut config } = server_config;
+
+        // NOTE: the responder replies in order, so the requests (and retries) must be sequential
+        config.max_concurrent = 1;
+
+        let too_many_requests = ResponseTemplate::new(StatusCode::TOO_MANY_REQUESTS)
+            .insert_header("Retry-After", "0")
+            .set_body_raw(r#"{"message": "RATE_LIMIT_EXCEEDED"}"#, "application/json");
+
+        // NOTE: mocks are matched in the order they were mounted in
+        Mock::given(method("POST"))
+            .and(path("v4/shorten"))
+            .respond_with(too_many_requests)
+            .up_to_n_times(2)
+            .expect(2)
+            .mount(&server)
+            .await;
+
+        Mock::given(method("POST"))
+            .and(path("v4/shorten"))
+            .respond_with(responder)
+            .mount(&server)
+            .await;
+
+        let results = test_shorten(config, urls, Ordering::Ordered).await;
+
+        match results.into_iter().collect::<Result<Vec<_>>>() {
+            Ok(actual) => assert_eq!(expected, actual),
+            Err(error) => panic!("expected transient errors to be retried, got: {error:?}"),
+        }
+    }
+
+    #[rstest]
+    #[tokio::test]
+    async fn shorten_retries_exhausted(#[future(awt)] server_config: ServerConfig) {
+        let ServerConfig { server, config } = server_config;
+
+        let too_many_requests = ResponseTemplate::new(StatusCode::TOO_MANY_REQUESTS)
+            .insert_header("Retry-After", "0")
+            .set_body_raw(r#"{"message": "RATE_LIMIT_EXCEEDED"}"#, "application/json");
+
+        Mock::given(method("POST"))
+            .and(path("v4/shorten"))
+            .respond_with(too_many_requests)
+            .expect(u64::from(config.retry.max_attempts.get()))
+            .mount(&server)
+            .await;
+
+        let urls = vec![Url::parse("https://example.com").unwrap()];
+        let results = test_shorten(config, urls, Ordering::Ordered).await;
+
+        match results
+            .into_iter()
+            .collect::<Result<Vec<_>>>()
+            .map_err(Error::without_input)
+        {
+            Ok(links) => panic!("expected API error (RATE_LIMIT_EXCEEDED), got: {links:?}"),
+            Err(Error::Bitly(resp)) => assert_eq!("RATE_LIMIT_EXCEEDED", resp.message),
+            Err(error) => panic!("expected API error (RATE_LIMIT_EXCEEDED), got: {error:?}"),
+        }
+    }
+
+    #[rstest]
+    #[tokio::test]
+    async fn shorten_retry_server_errors(#[future(awt)] server_config: ServerConfig) {
+        let ServerConfig { server, config } = server_config;
+
+        let unavailable = ResponseTemplate::new(StatusCode::SERVICE_UNAVAILABLE).set_body_raw(
+            r#"{"message": "TEMPORARILY_UNAVAILABLE"}"#,
+            "application/json",
+        );
+
+        let created = ResponseTemplate::new(StatusCode::OK).set_body_raw(
+            r#"{
+              "id": "test.domain/4ePsyXN",
+              "link": "https://test.domain/4ePsyXN",
+              "long_url": "https://example.com"
+            }"#,
+            "application/json",
+        );
+
+        // NOTE: shortening the same URL again yields the same bitlink, so it's safe to retry
+        Mock::given(method("POST"))
+            .and(path("v4/shorten"))
+            .respond_with(unavailable)
+            .up_to_n_times(2)
+            .expect(2)
+            .mount(&server)
+            .await;
+
+        Mock::given(method("POST"))
+            .and(path("v4/shorten"))
+            .respond_with(created)
+            .expect(1)
+            .mount(&server)
+            .await;
+
+        let urls = vec![Url::parse("https://example.com").unwrap()];
+        let results = test_shorten(config, urls, Ordering::Ordered).await;
+
+        let expected = vec![Bitlink {
+            link: "https://test.domain/4ePsyXN".parse().unwrap(),
+            id: "test.domain/4ePsyXN".to_string(),
+            long_url: "https://example.com".parse().unwrap(),
+        }];
+
+        match results.into_iter().collect::<Result<Vec<_>>>() {
+            Ok(actual) => assert_eq!(expected, actual),
+            Err(error) => panic!("expected transient errors to be retried, got: {error:?}"),
+        }
+    }
+
+    #[rstest]
+    #[tokio::test]
+    async fn custom_bitlink_server_error_not_retried(
+        #[future(awt)] server_config: ServerConfig,
+        #[from(keyword_fields)] fields: BitlinkFields,
+    ) {
+        let ServerConfig { server, config } = server_config;
+
+        let created = ResponseTemplate::new(StatusCode::CREATED).set_body_raw(
+            r#"{
+              "id": "test.domain/4ePsyXN",
+              "link": "https://test.domain/4ePsyXN",
+              "long_url": "https://example.com"
+            }"#,
+            "application/json",
+        );
+
+        let unavailable = ResponseTemplate::new(StatusCode::SERVICE_UNAVAILABLE).set_body_raw(
+            r#"{"message": "TEMPORARILY_UNAVAILABLE"}"#,
+            "application/json",
+        );
+
+        Mock::given(method("POST"))
+            .and(path("v4/shorten"))
+            .respond_with(created)
+            .expect(1)
+            .mount(&server)
+            .await;
+
+        // NOTE: the keyword might have been added anyway, so adding it again is not safe
+        Mock::given(method("POST"))
+            .and(path("v4/custom_bitlinks"))
+            .respond_with(unavailable)
+            .expect(1)
+            .mount(&server)
+            .await;
+
+        let urls = stream::iter([Url::parse("https://example.com").unwrap()]);
+
+        let client = Client::new(config).await.expect("initialized client");
+        let results = client
+            .shorten_keyed(urls.map(|url| ((), url)), fields, Ordering::Ordered)
+            .map(|((), result)| result)
+            .collect::<Vec<_>>()
+            .await;
+
+        match results
+            .into_iter()
+            .collect::<Result<Vec<_>>>()
+            .map_err(Error::without_input)
+        {
+            Err(Error::Bitly(resp)) => assert_eq!("TEMPORARILY_UNAVAILABLE", resp.message),
+            result => panic!("expected API error (SERVICE_UNAVAILABLE), got: {result:?}"),
+        }
+    }
+
+    #[rstest]
+    #[tokio::test]
+    async fn shorten_request_timeout(#[future(awt)] server_config: ServerConfig) {
+        let ServerConfig { server, mut config } = server_config;
+
+        config.request_timeout_ms = 50;
+        config.retry.max_attempts = NonZeroU32::MIN;
+
+        let hung = ResponseTemplate::new(StatusCode::OK).set_delay(Duration::from_secs(5));
+
+        Mock::given(method("POST"))
+            .and(path("v4/shorten"))
+            .respond_with(hung)
+            .expect(1)
+            .mount(&server)
+            .await;
+
+        let urls = vec![Url::parse("https://example.com").unwrap()];
+        let results = test_shorten(config, urls, Ordering::Ordered).await;
+
+        match results
+            .into_iter()
+            .collect::<Result<Vec<_>>>()
+            .map_err(Error::without_input)
+        {
+            Ok(links) => panic!("expected request to time out, got: {links:?}"),
+            Err(Error::Http(error)) => assert!(error.is_timeout(), "{error:?}"),
+            Err(error) => panic!("expected request to time out, got: {error:?}"),
+        }
+    }
+
+    #[rstest]
+    #[case(None, 10)]
+    #[case(Some((1_000, 0)), 10)]
+    #[case(Some((1_000, 997)), 3)]
+    #[case(Some((1_000, 1_000)), 1)]
+    #[case(Some((1_000, 1_200)), 1)]
+    fn rate_limit_from_shorten_limit(#[case] limit: Option<(u64, u64)>, #[case] expected: u32) {
+        let limit = limit.map(|(limit, count)| MethodLimit {
+            name: "POST".to_string(),
+            limit,
+            count,
+        });
+        assert_eq!(expected, rate_limit(limit.as_ref()).get());
+    }
+
+    #[rstest]
+    #[case::derived(None, true)]
+    #[case::disabled(Some(0), false)]
+    #[tokio::test]
+    async fn platform_limits_on_setup(
+        #[future(awt)] server_config: ServerConfig,
+        #[case] rate_limit: Option<u32>,
+        #[case] limited: bool,
+    ) {
+        let ServerConfig { server, mut config } = server_config;
+
+        config.rate_limit = rate_limit;
+
+        Mock::given(method("GET"))
+            .and(path("v4/user/platform_limits"))
+            .and(query_param("path", "/v4/shorten"))
+            .respond_with(platform_limits(100, 97))
+            .expect(1)
+            .mount(&server)
+            .await;
+
+        let client = Client::new(config).await.expect("initialized client");
+
+        match client.inner.quota {
+            Some(ref quota) => assert_eq!((100, 97), (quota.limit(), quota.used())),
+            None => panic!("expected the quota to be determined on setup"),
+        }
+
+        assert_eq!(limited, client.inner.limiter.is_some());
+    }
+
+    #[rstest]
+    #[tokio::test]
+    async fn shorten_quota_exceeded(
+        #[future(awt)] server_config: ServerConfig,
+        #[from(shorten_test)] ShortenTest {
+            urls,
+            responder,
+            expected,
+        }: ShortenTest,
+    ) {
+        let ServerConfig { server, mut config } = server_config;
+
+        // NOTE: the responder replies in order, so the requests must be sequential
+        config.max_concurrent = 1;
+
+        Mock::given(method("GET"))
+            .and(path("v4/user/platform_limits"))
+            .and(query_param("path", "/v4/shorten"))
+            .respond_with(platform_limits(10, 9))
+            .expect(1)
+            .mount(&server)
+            .await;
+
+        Mock::given(method("POST"))
+            .and(path("v4/shorten"))
+            .respond_with(responder)
+            .expect(1)
+            .mount(&server)
+            .await;
+
+        let mut results = test_shorten(config, urls, Ordering::Ordered)
+            .await
+            .into_iter();
+
+        match results.next() {
+            Some(Ok(actual)) => assert_eq!(expected[0], actual),
+            result => panic!("expected first bitlink to fit the quota, got: {result:?}"),
+        }
+
+        match results.next().map(|r| r.map_err(Error::without_input)) {
+            Some(Err(Error::QuotaExceeded {
+                limit: 10,
+                used: 10,
+                remaining: 0,
+            })) => {}
+            result => panic!("expected quota to be exceeded, got: {result:?}"),
+        }
+    }
+
+    #[rstest]
+    #[tokio::test]
+    async fn shorten_quota_released_on_error(
+        #[future(awt)] server_config: ServerConfig,
+        #[from(shorten_test)] ShortenTest {
+            urls,
+            responder,
+            expected,
+        }: ShortenTest,
+    ) {
+        let ServerConfig { server, mut config } = server_config;
+
+        // NOTE: the responder replies in order, so the requests must be sequential
+        config.max_concurrent = 1;
+
+        Mock::given(method("GET"))
+            .and(path("v4/user/platform_limits"))
+            .and(query_param("path", "/v4/shorten"))
+            .respond_with(platform_limits(10, 9))
+            .expect(1)
+            .mount(&server)
+            .await;
+
+        let bad_request = ResponseTemplate::new(StatusCode::BAD_REQUEST)
+            .set_body_raw(r#"{"message": "INVALID_ARG_LONG_URL"}"#, "application/json");
+
+        Mock::given(method("POST"))
+            .and(path("v4/shorten"))
+            .and(body_partial_json(
+                serde_json::json!({ "long_url": urls[0].as_str() }),
+            ))
+            .respond_with(bad_request)
+            .expect(1)
+            .mount(&server)
+            .await;
+
+        Mock::given(method("POST"))
+            .and(path("v4/shorten"))
+            .respond_with(responder)
+            .expect(1)
+            .mount(&server)
+            .await;
+
+        let mut results = test_shorten(config, urls, Ordering::Ordered)
+            .await
+            .into_iter();
+
+        match results.next().map(|r| r.map_err(Error::without_input)) {
+            Some(Err(Error::Rejected { .. })) => {}
+            result => panic!("expected first request to fail, got: {result:?}"),
+        }
+
+        // NOTE: the responder replies in order, regardless of the long URL
+        match results.next() {
+            Some(Ok(actual)) => assert_eq!(expected[0].link, actual.link),
+            result => panic!("expected released quota to be used, got: {result:?}"),
+        }
+    }
+
+    #[rstest]
+    #[tokio::test]
+    async fn shorten_quota_persisted(
+        #[future(awt)] server_config: ServerConfig,
+        #[from(config)] mut next: Config,
+        #[from(shorten_test)] ShortenTest {
+            urls,
+            responder,
+            expected,
+        }: ShortenTest,
+    ) {
+        let ServerConfig { server, mut config } = server_config;
+
+        let cache_dir = tempfile::tempdir().expect("temp cache dir");
+        config.cache_dir = Some(cache_dir.path().to_path_buf());
+
+        next.with_api_url(config.api_url.clone());
+        next.cache_dir.clone_from(&config.cache_dir);
+
+        // NOTE: the cached limit is used by the next run, so it's fetched just once
+        Mock::given(method("GET"))
+            .and(path("v4/user/platform_limits"))
+            .and(query_param("path", "/v4/shorten"))
+            .respond_with(platform_limits(10, 9))
+            .expect(1)
+            .mount(&server)
+            .await;
+
+        Mock::given(method("POST"))
+            .and(path("v4/shorten"))
+            .respond_with(responder)
+            .expect(1)
+            .mount(&server)
+            .await;
+
+        let results = test_shorten(config, urls[..1].to_vec(), Ordering::Ordered).await;
+
+        match results.into_iter().collect::<Result<Vec<_>>>() {
+            Ok(actual) => assert_eq!(expected[..1], actual),
+            Err(error) => panic!("expected bitlink to fit the quota, got: {error:?}"),
+        }
+
+        let results = test_shorten(next, urls[1..].to_vec(), Ordering::Ordered).await;
+
+        match results
+            .into_iter()
+            .collect::<Result<Vec<_>>>()
+            .map_err(Error::without_input)
+        {
+            Err(Error::QuotaExceeded {
+                limit: 10,
+                used: 10,
+                remaining: 0,
+            }) => {}
+            result => panic!("expected quota used by the previous run, got: {result:?}"),
+        }
+    }
+
+    #[rstest]
+    #[tokio::test]
+    async fn expand_bitlinks(#[future(awt)] server_config: ServerConfig) {
+        let ServerConfig { server, config } = server_config;
+
+        let expanded = ResponseTemplate::new(StatusCode::OK).set_body_raw(
+            r#"{
+              "created_at": "2024-08-07T08:48:48+0000",
+              "link": "https://test.domain/4ePsyXN",
+              "id": "test.domain/4ePsyXN",
+              "long_url": "https://example.com"
+            }"#,
+            "application/json",
+        );
+
+        Mock::given(method("POST"))
+            .and(path("v4/expand"))
+            .and(body_json(
+                serde_json::json!({ "bitlink_id": "test.domain/4ePsyXN" }),
+            ))
+            .respond_with(expanded)
+            .expect(1)
+            .mount(&server)
+            .await;
+
+        let bitlinks = vec!["test.domain/4ePsyXN".parse().expect("valid bitlink ID")];
+
+        let client = Client::new(config).await.expect("initialized client");
+        let results = client
+            .expand(stream::iter(bitlinks), Ordering::Ordered)
+            .collect::<Vec<_>>()
+            .await;
+
+        let expected = vec![Bitlink {
+            link: "https://test.domain/4ePsyXN".parse().unwrap(),
+            id: "test.domain/4ePsyXN".to_string(),
+            long_url: "https://example.com".parse().unwrap(),
+        }];
+
+        match results.into_iter().collect::<Result<Vec<_>>>() {
+            Ok(actual) => assert_eq!(expected, actual),
+            Err(error) => panic!("encountered API/client error: {error:?}"),
+        }
+    }
+
+    #[rstest]
+    #[tokio::test]
+    async fn expand_retry_server_errors(#[future(awt)] server_config: ServerConfig) {
+        let ServerConfig { server, config } = server_config;
+
+        let unavailable = ResponseTemplate::new(StatusCode::SERVICE_UNAVAILABLE).set_body_raw(
+            r#"{"message": "TEMPORARILY_UNAVAILABLE"}"#,
+            "application/json",
+        );
+
+        let expanded = ResponseTemplate::new(StatusCode::OK).set_body_raw(
+            r#"{
+              "link": "https://test.domain/4ePsyXN",
+              "id": "test.domain/4ePsyXN",
+              "long_url": "https://example.com"
+            }"#,
+            "application/json",
+        );
+
+        // NOTE: expanding doesn't change anything, so it's retried despite being a POST
+        Mock::given(method("POST"))
+            .and(path("v4/expand"))
+            .respond_with(unavailable)
+            .up_to_n_times(2)
+            .expect(2)
+            .mount(&server)
+            .await;
+
+        Mock::given(method("POST"))
+            .and(path("v4/expand"))
+            .respond_with(expanded)
+            .expect(1)
+            .mount(&server)
+            .await;
+
+        let bitlinks = vec!["test.domain/4ePsyXN".parse().expect("valid bitlink ID")];
+
+        let client = Client::new(config).await.expect("initialized client");
+        let results = client
+            .expand(stream::iter(bitlinks), Ordering::Ordered)
+            .collect::<Vec<_>>()
+            .await;
+
+        let expected = vec![Bitlink {
+            link: "https://test.domain/4ePsyXN".parse().unwrap(),
+            id: "test.domain/4ePsyXN".to_string(),
+            long_url: "https://example.com".parse().unwrap(),
+        }];
+
+        match results.into_iter().collect::<Result<Vec<_>>>() {
+            Ok(actual) => assert_eq!(expected, actual),
+            Err(error) => panic!("expected transient errors to be retried, got: {error:?}"),
+        }
+    }
+
+    #[rstest]
+    #[case::unauthorized(StatusCode::UNAUTHORIZED, "text/plain", "Unauthorized", false)]
+    #[case::bad_gateway(
+        StatusCode::BAD_GATEWAY,
+        "text/html",
+        "<html>Bad Gateway</html>",
+        false
+    )]
+    #[case::malformed_ok(StatusCode::OK, "application/json", r#"{"link": 42}"#, true)]
+    #[case::malformed_error(StatusCode::NOT_FOUND, "text/html", "<html>Not Found</html>", true)]
+    #[tokio::test]
+    async fn expand_unexpected_response(
+        #[future(awt)] server_config: ServerConfig,
+        #[case] status: StatusCode,
+        #[case] content_type: &str,
+        #[case] body: &str,
+        #[case] invalid: bool,
+    ) {
+        let ServerConfig { server, config } = server_config;
+
+        let resp = ResponseTemplate::new(status).set_body_raw(body, content_type);
+
+        Mock::given(method("POST"))
+            .and(path("v4/expand"))
+            .respond_with(resp)
+            .mount(&server)
+            .await;
+
+        let bitlinks = vec!["test.domain/4ePsyXN".parse().expect("valid bitlink ID")];
+
+        let client = Client::new(config).await.expect("initialized client");
+        let results = client
+            .expand(stream::iter(bitlinks), Ordering::Ordered)
+            .collect::<Vec<_>>()
+            .await;
+
+        let response = match results
+            .into_iter()
+            .collect::<Result<Vec<_>>>()
+            .map_err(Error::without_input)
+        {
+            Ok(links) => panic!("expected unexpected response error, got: {links:?}"),
+            Err(Error::UnexpectedStatus(response)) if !invalid => response,
+            Err(Error::InvalidResponse { response, .. }) if invalid => response,
+            Err(error) => panic!("expected unexpected response error, got: {error:?}"),
+        };
+
+        assert_eq!(status, response.status);
+        assert_eq!(body, response.body);
+        assert_eq!(
+            Some(content_type),
+            response
+                .headers
+                .get("content-type")
+                .and_then(|value| value.to_str().ok())
+        );
+    }
+
+    #[rstest]
+    #[tokio::test]
+    async fn expand_offline_from_cache(mut config: Config) {
+        let cache_dir = tempfile::tempdir().expect("temp cache dir");
+
+        let bitlink = Bitlink {
+            link: "https://test.domain/4ePsyXN".parse().unwrap(),
+            id: "test.domain/4ePsyXN".to_string(),
+            long_url: "https://example.com".parse().unwrap(),
+        };
+
+        let cache = BitlinkCache::new(VERSION, Some(cache_dir.path()))
+            .await
+            .expect("cache enabled");
+
+        let payload = Shorten {
+            long_url: bitlink.long_url.clone(),
+            domain: config.domain.as_deref().map(Cow::Borrowed),
+            group_guid: Cow::Borrowed("test-group-guid"),
+            title: None,
+            tags: Cow::Borrowed(&[]),
+            deeplinks: Cow::Borrowed(&[]),
+            keyword: None,
+        };
+
+        cache.set(&payload, &bitlink).await;
+
+        config.cache_dir = Some(cache_dir.path().to_path_buf());
+        config.offline = true;
+
+        let bitlinks = vec![
+            "https://test.domain/4ePsyXN"
+                .parse()
+                .expect("valid bitlink"),
+            "https://test.domain/unknown"
+                .parse()
+                .expect("valid bitlink"),
+        ];
+
+        let client = Client::new(config).await.expect("initialized client");
+        let mut results = client
+            .expand(stream::iter(bitlinks), Ordering::Ordered)
+            .collect::<Vec<_>>()
+            .await
+            .into_iter();
+
+        match results.next() {
+            Some(Ok(actual)) => assert_eq!(bitlink, actual),
+            result => panic!("expected cached bitlink, got: {result:?}"),
+        }
+
+        match results.next().map(|r| r.map_err(Error::without_input)) {
+            Some(Err(Error::Offline(_))) => {}
+            result => panic!("expected offline error, got: {result:?}"),
+        }
+    }
+
+    #[rstest]
+    #[tokio::test]
+    async fn revalidate_stale_bitlinks(
+        #[future(awt)] server_config: ServerConfig,
+        #[from(config)] mut offline: Config,
+    ) {
+        let ServerConfig { server, mut config } = server_config;
+
+        let cache_dir = tempfile::tempdir().expect("temp cache dir");
+        config.cache_dir = Some(cache_dir.path().to_path_buf());
+        config.cache_max_age_s = 60 * 60;
+
+        offline.cache_dir.clone_from(&config.cache_dir);
+        offline.cache_max_age_s = config.cache_max_age_s;
+        offline.offline = true;
+
+        let valid = Bitlink {
+            link: "https://test.domain/valid".parse().unwrap(),
+            id: "test.domain/valid".to_string(),
+            long_url: "https://example.com/".parse().unwrap(),
+        };
+
+        let deleted = Bitlink {
+            link: "https://test.domain/deleted".parse().unwrap(),
+            id: "test.domain/deleted".to_string(),
+            long_url: "https://example.org/".parse().unwrap(),
+        };
+
+        let cache = BitlinkCache::new(VERSION, Some(cache_dir.path()))
+            .await
+            .expect("cache enabled");
+
+        for bitlink in [&valid, &deleted] {
+            let payload = Shorten {
+                long_url: bitlink.long_url.clone(),
+                domain: config.domain.as_deref().map(Cow::Borrowed),
+                group_guid: Cow::Borrowed("test-group-guid"),
+                title: None,
+                tags: Cow::Borrowed(&[]),
+                deeplinks: Cow::Borrowed(&[]),
+                keyword: None,
+            };
+            cache.set(&payload, bitlink).await;
+        }
+
+        // NOTE: backdate the entries, so that they are stale
+        backdate_cache(cache_dir.path(), 7200).await;
+
+        let info = ResponseTemplate::new(StatusCode::OK).set_body_raw(
+            r#"{
+              "id": "test.domain/valid",
+              "link": "https://test.domain/valid",
+              "long_url": "https://example.com/"
+            }"#,
+            "application/json",
+        );
+
+        Mock::given(method("GET"))
+            .and(path("v4/bitlinks/test.domain/valid"))
+            .respond_with(info)
+            .expect(1)
+            .mount(&server)
+            .await;
+
+        let not_found = ResponseTemplate::new(StatusCode::NOT_FOUND)
+            .set_body_raw(r#"{"message": "NOT_FOUND"}"#, "application/json");
+
+        Mock::given(method("GET"))
+            .and(path("v4/bitlinks/test.domain/deleted"))
+            .respond_with(not_found)
+            .expect(1)
+            .mount(&server)
+            .await;
+
+        let recreated = ResponseTemplate::new(StatusCode::OK).set_body_raw(
+            r#"{
+              "id": "test.domain/recreated",
+              "link": "https://test.domain/recreated",
+              "long_url": "https://example.org/"
+            }"#,
+            "application/json",
+        );
+
+        Mock::given(method("POST"))
+            .and(path("v4/shorten"))
+            .respond_with(recreated)
+            .expect(1)
+            .mount(&server)
+            .await;
+
+        let urls = vec![valid.long_url.clone(), deleted.long_url.clone()];
+
+        let expected = vec![
+            valid.clone(),
+            Bitlink {
+                link: "https://test.domain/recreated".parse().unwrap(),
+                id: "test.domain/recreated".to_string(),
+                long_url: deleted.long_url.clone(),
+            },
+        ];
+
+        let client = Client::new(config).await.expect("initialized client");
+
+        // NOTE: revalidated entries are fresh, so the second round sends no more requests
+        for _ in 0..2 {
+            let results = client
+                .shorten_keyed(
+                    stream::iter(urls.clone()).map(|url| ((), url)),
+                    BitlinkFields::default(),
+                    Ordering::Ordered,
+                )
+                .map(|((), result)| result)
+                .collect::<Vec<_>>()
+                .await;
+
+            match results.into_iter().collect::<Result<Vec<_>>>() {
+                Ok(actual) => assert_eq!(expected, actual),
+                Err(error) => panic!("encountered API/client error: {error:?}"),
+            }
+        }
+
+        // stale entries are still served if they can't be revalidated
+        backdate_cache(cache_dir.path(), 7200).await;
+
+        let results = test_shorten(offline, urls, Ordering::Ordered).await;
+
+        match results.into_iter().collect::<Result<Vec<_>>>() {
+            Ok(actual) => assert_eq!(expected, actual),
+            Err(error) => panic!("expected stale results, got: {error:?}"),
+        }
+    }
+
+    #[rstest]
+    #[tokio::test]
+    async fn bitlink_info(#[future(awt)] server_config: ServerConfig) {
+        let ServerConfig { server, config } = server_config;
+
+        let info = ResponseTemplate::new(StatusCode::OK).set_body_raw(
+            r#"{
+              "created_at": "2024-08-07T08:48:48+0000",
+              "id": "test.domain/4ePsyXN",
+              "link": "https://test.domain/4ePsyXN",
+              "title": "Example Domain",
+              "custom_bitlinks": ["https://test.domain/example"],
+              "long_url": "https://example.com",
+              "archived": true,
+              "tags": ["campaign"],
+              "deeplinks": [{
+                "app_guid": "test-app-guid",
+                "app_uri_path": "/example",
+                "install_url": "https://example.com/install",
+                "install_type": "promote_install",
+                "os": "ios"
+              }],
+              "references": {
+                "group": "https://api-ssl.bitly.com/v4/groups/test-group-guid"
+              }
+            }"#,
+            "application/json",
+        );
+
+        Mock::given(method("GET"))
+            .and(path("v4/bitlinks/test.domain/4ePsyXN"))
+            .respond_with(info)
+            .expect(1)
+            .mount(&server)
+            .await;
+
+        let bitlinks = vec!["https://test.domain/4ePsyXN".parse().unwrap()];
+
+        let client = Client::new(config).await.expect("initialized client");
+        let results = client
+            .info(stream::iter(bitlinks), Ordering::Ordered)
+            .collect::<Vec<_>>()
+            .await;
+
+        let expected = vec![BitlinkInfo {
+            link: "https://test.domain/4ePsyXN".parse().unwrap(),
+            id: "test.domain/4ePsyXN".to_string(),
+            long_url: "https://example.com".parse().unwrap(),
+            title: Some("Example Domain".to_string()),
+            created_at: Some("2024-08-07T08:48:48+0000".to_string()),
+            archived: true,
+            tags: vec!["campaign".to_string()],
+            custom_bitlinks: vec!["https://test.domain/example".to_string()],
+            deeplinks: vec![Deeplink {
+                app_guid: Some("test-app-guid".to_string()),
+                app_uri_path: Some("/example".to_string()),
+                install_url: Some("https://example.com/install".to_string()),
+                install_type: Some("promote_install".to_string()),
+                os: Some("ios".to_string()),
+                ..Default::default()
+            }],
+            references: BTreeMap::from([(
+                "group".to_string(),
+                "https://api-ssl.bitly.com/v4/groups/test-group-guid".to_string(),
+            )]),
+        }];
+
+        match results.into_iter().collect::<Result<Vec<_>>>() {
+            Ok(actual) => assert_eq!(expected, actual),
+            Err(error) => panic!("encountered API/client error: {error:?}"),
+        }
+    }
+
+    #[rstest]
+    #[tokio::test]
+    async fn update_bitlinks(#[future(awt)] server_config: ServerConfig) {
+        let ServerConfig { server, mut config } = server_config;
+
+        let cache_dir = tempfile::tempdir().expect("temp cache dir");
+        config.cache_dir = Some(cache_dir.path().to_path_buf());
+
+        let bitlink = Bitlink {
+            link: "https://test.domain/4ePsyXN".parse().unwrap(),
+            id: "test.domain/4ePsyXN".to_string(),
+            long_url: "https://example.com".parse().unwrap(),
+        };
+
+        let payload = Shorten {
+            long_url: bitlink.long_url.clone(),
+            domain: config.domain.as_deref().map(Cow::Borrowed),
+            group_guid: Cow::Borrowed("test-group-guid"),
+            title: None,
+            tags: Cow::Borrowed(&[]),
+            deeplinks: Cow::Borrowed(&[]),
+            keyword: None,
+        };
+
+        BitlinkCache::new(VERSION, Some(cache_dir.path()))
+            .await
+            .expect("cache enabled")
+            .set(&payload, &bitlink)
+            .await;
+
+        let current = ResponseTemplate::new(StatusCode::OK).set_body_raw(
+            r#"{
+              "id": "test.domain/4ePsyXN",
+              "link": "https://test.domain/4ePsyXN",
+              "long_url": "https://example.com",
+              "tags": ["a", "b"]
+            }"#,
+            "application/json",
+        );
+
+        let updated = ResponseTemplate::new(StatusCode::OK).set_body_raw(
+            r#"{
+              "id": "test.domain/4ePsyXN",
+              "link": "https://test.domain/4ePsyXN",
+              "long_url": "https://example.com/new",
+              "tags": ["b", "c"]
+            }"#,
+            "application/json",
+        );
+
+        Mock::given(method("GET"))
+            .and(path("v4/bitlinks/test.domain/4ePsyXN"))
+            .respond_with(current)
+            .expect(1)
+            .mount(&server)
+            .await;
+
+        Mock::given(method("PATCH"))
+            .and(path("v4/bitlinks/test.domain/4ePsyXN"))
+            .and(body_json(serde_json::json!({
+                "tags": ["b", "c"],
+                "long_url": "https://example.com/new",
+            })))
+            .respond_with(updated)
+            .expect(1)
+            .mount(&server)
+            .await;
+
+        let changes = BitlinkChanges {
+            add_tags: vec!["c".to_string()],
+            remove_tags: vec!["a".to_string()],
+            long_url: Some("https://example.com/new".parse().unwrap()),
+            ..Default::default()
+        };
+
+        let bitlinks = stream::iter([BitlinkRef(bitlink.link.clone())]);
+
+        let client = Client::new(config).await.expect("initialized client");
+        let results = client
+            .update(bitlinks, changes, Ordering::Ordered)
+            .collect::<Vec<_>>()
+            .await;
+
+        match results.into_iter().collect::<Result<Vec<_>>>() {
+            Ok(actual) => {
+                assert_eq!(1, actual.len());
+                assert_eq!(vec!["b".to_string(), "c".to_string()], actual[0].tags);
+            }
+            Err(error) => panic!("encountered API/client error: {error:?}"),
+        }
+
+        let cached = client
+            .inner
+            .cache
+            .as_ref()
+            .expect("cache enabled")
+            .get_by_link(&bitlink.link)
+            .await
+            .expect("cached bitlink");
+
+        assert_eq!("https://example.com/new", cached.long_url.as_str());
+    }
+
+    #[fixture]
+    fn metrics_query() -> MetricsQuery {
+        MetricsQuery {
+            unit: TimeUnit::Week,
+            units: 2,
+            unit_reference: None,
+        }
+    }
+
+    #[rstest]
+    #[tokio::test]
+    async fn bitlink_clicks(
+        #[future(awt)] server_config: ServerConfig,
+        metrics_query: MetricsQuery,
+    ) {
+        let ServerConfig { server, config } = server_config;
+
+        let clicks = ResponseTemplate::new(StatusCode::OK).set_body_raw(
+            r#"{
+              "link_clicks": [
+                {"date": "2024-08-05T00:00:00+0000", "clicks": 3},
+                {"date": "2024-07-29T00:00:00+0000", "clicks": 5}
+              ],
+              "units": 2,
+              "unit": "week",
+              "unit_reference": "2024-08-07T08:48:48+0000"
+            }"#,
+            "application/json",
+        );
+
+        Mock::given(method("GET"))
+            .and(path("v4/bitlinks/test.domain/4ePsyXN/clicks"))
+            .and(query_param("unit", "week"))
+            .and(query_param("units", "2"))
+            .respond_with(clicks)
+            .expect(1)
+            .mount(&server)
+            .await;
+
+        let bitlinks = stream::iter(["test.domain/4ePsyXN".parse().unwrap()]);
+
+        let client = Client::new(config).await.expect("initialized client");
+        let results = client
+            .metrics(bitlinks, Metric::Clicks, metrics_query, Ordering::Ordered)
+            .collect::<Vec<_>>()
+            .await;
+
+        let expected = vec![BitlinkMetrics {
+            bitlink: "test.domain/4ePsyXN".to_string(),
+            metrics: Clicks {
+                link_clicks: vec![
+                    LinkClicks {
+                        date: "2024-08-05T00:00:00+0000".to_string(),
+                        clicks: 3,
+                    },
+                    LinkClicks {
+                        date: "2024-07-29T00:00:00+0000".to_string(),
+                        clicks: 5,
+                    },
+                ],
+                unit: TimeUnit::Week,
+                units: 2,
+                unit_reference: Some("2024-08-07T08:48:48+0000".to_string()),
+            },
+        }];
 
-    struct LinkResponder {
-        resp_num: AtomicUsize,
-        responses: Vec<String>,
+        match results.into_iter().collect::<Result<Vec<_>>>() {
+            Ok(actual) => assert_eq!(expected, actual),
+            Err(error) => panic!("encountered API/client error: {error:?}"),
+        }
     }
 
-    impl LinkResponder {
-        fn new(ordering: Ordering, responses: impl IntoIterator<Item = impl Into<String>>) -> Self {
-            let mut responses = responses.into_iter().map(Into::<String>::into).collect();
-            Self {
-                resp_num: AtomicUsize::new(0),
-                responses: match ordering {
-                    Ordering::Ordered => responses,
-                    Ordering::Unordered => {
-                        responses.reverse();
-                        responses
-                    }
-                },
+    #[rstest]
+    #[tokio::test]
+    async fn bitlink_clicks_summary(
+        #[future(awt)] server_config: ServerConfig,
+        metrics_query: MetricsQuery,
+    ) {
+        let ServerConfig { server, config } = server_config;
+
+        let summary = ResponseTemplate::new(StatusCode::OK).set_body_raw(
+            r#"{"total_clicks": 8, "units": 2, "unit": "week"}"#,
+            "application/json",
+        );
+
+        Mock::given(method("GET"))
+            .and(path("v4/bitlinks/test.domain/4ePsyXN/clicks/summary"))
+            .respond_with(summary)
+            .expect(1)
+            .mount(&server)
+            .await;
+
+        let bitlinks = stream::iter(["test.domain/4ePsyXN".parse().unwrap()]);
+
+        let client = Client::new(config).await.expect("initialized client");
+        let results = client
+            .metrics::<_, ClicksSummary>(
+                bitlinks,
+                Metric::ClicksSummary,
+                metrics_query,
+                Ordering::Ordered,
+            )
+            .collect::<Vec<_>>()
+            .await;
+
+        match results.into_iter().collect::<Result<Vec<_>>>() {
+            Ok(actual) => {
+                let totals = actual.iter().map(ToString::to_string).collect::<Vec<_>>();
+                assert_eq!(vec!["test.domain/4ePsyXN\t8".to_string()], totals);
             }
+            Err(error) => panic!("encountered API/client error: {error:?}"),
         }
     }
 
-    impl Respond for LinkResponder {
-        fn respond(&self, _request: &wiremock::Request) -> ResponseTemplate {
-            let i = self
-                .resp_num
-                .fetch_add(1, std::sync::atomic::Ordering::Relaxed);
+    #[rstest]
+    #[case::countries(
+        Metric::Countries,
+        "countries",
+        r#"{"value": "CZ", "clicks": 3}"#,
+        "test.domain/4ePsyXN\tCZ\t3"
+    )]
+    #[case::cities(
+        Metric::Cities,
+        "cities",
+        r#"{"city": "Prague", "region": "Prague", "country": "CZ", "clicks": 2}"#,
+        "test.domain/4ePsyXN\tPrague, Prague, CZ\t2"
+    )]
+    #[case::referring_domains(
+        Metric::ReferringDomains,
+        "referring_domains",
+        r#"{"value": "example.com", "clicks": 1}"#,
+        "test.domain/4ePsyXN\texample.com\t1"
+    )]
+    #[tokio::test]
+    async fn bitlink_breakdown(
+        #[future(awt)] server_config: ServerConfig,
+        metrics_query: MetricsQuery,
+        #[case] metric: Metric,
+        #[case] endpoint: &str,
+        #[case] item: &str,
+        #[case] expected: &str,
+    ) {
+        let ServerConfig { server, config } = server_config;
 
-            let body = self.responses[i].as_bytes();
+        let breakdown = ResponseTemplate::new(StatusCode::OK).set_body_raw(
+            format!(
+                r#"{{"metrics": [{item}], "units": 2, "unit": "week", "facet": "{endpoint}"}}"#
+            ),
+            "application/json",
+        );
 
-            ResponseTemplate::new(StatusCode::OK).set_body_raw(body, "application/json")
+        Mock::given(method("GET"))
+            .and(path(format!("v4/bitlinks/test.domain/4ePsyXN/{endpoint}")))
+            .respond_with(breakdown)
+            .expect(1)
+            .mount(&server)
+            .await;
+
+        let bitlinks = stream::iter(["test.domain/4ePsyXN".parse().unwrap()]);
+
+        let client = Client::new(config).await.expect("initialized client");
+        let results = client
+            .metrics::<_, Breakdown>(bitlinks, metric, metrics_query, Ordering::Ordered)
+            .collect::<Vec<_>>()
+            .await;
+
+        match results.into_iter().collect::<Result<Vec<_>>>() {
+            Ok(actual) => {
+                let actual = actual.iter().map(ToString::to_string).collect::<Vec<_>>();
+                assert_eq!(vec![expected.to_string()], actual);
+            }
+            Err(error) => panic!("encountered API/client error: {error:?}"),
         }
     }
 
-    struct ShortenTest {
-        urls: Vec<Url>,
-        responder: LinkResponder,
-        expected: Vec<Bitlink>,
-    }
+    #[rstest]
+    #[tokio::test]
+    async fn list_bitlinks(#[future(awt)] server_config: ServerConfig) {
+        let ServerConfig { server, config } = server_config;
 
-    struct ServerConfig {
-        server: MockServer,
-        config: Config,
-    }
+        let first = ResponseTemplate::new(StatusCode::OK).set_body_raw(
+            format!(
+                r#"{{
+                  "links": [
+                    {{"id": "test.domain/a", "link": "https://test.domain/a", "long_url": "https://example.com/a"}},
+                    {{"id": "bit.ly/b", "link": "https://bit.ly/b", "long_url": "https://example.com/b"}}
+                  ],
+                  "pagination": {{
+                    "next": "{}/v4/groups/test-group-guid/bitlinks?tags=x&search_after=b",
+                    "size": 2
+                  }}
+                }}"#,
+                server.uri()
+            ),
+            "application/json",
+        );
 
-    // NOTE: starts mock server on a random local port
-    #[fixture]
-    async fn server() -> MockServer {
-        MockServer::start().await
-    }
+        let second = ResponseTemplate::new(StatusCode::OK).set_body_raw(
+            r#"{
+              "links": [
+                {"id": "test.domain/c", "link": "https://test.domain/c", "long_url": "https://example.com/c"}
+              ],
+              "pagination": {"next": "", "size": 2}
+            }"#,
+            "application/json",
+        );
 
-    // NOTE: disables caching
-    #[fixture]
-    fn config() -> Config {
-        Config {
-            api_url: Url::parse("https://api-ssl.bitly.com").unwrap(),
-            api_token: "secret-token".into(),
+        Mock::given(method("GET"))
+            .and(path("v4/groups/test-group-guid/bitlinks"))
+            .and(query_param("tags", "x"))
+            .and(query_param("archived", "off"))
+            .and(query_param_is_missing("search_after"))
+            .respond_with(first)
+            .expect(1)
+            .mount(&server)
+            .await;
+
+        Mock::given(method("GET"))
+            .and(path("v4/groups/test-group-guid/bitlinks"))
+            .and(query_param("search_after", "b"))
+            .respond_with(second)
+            .expect(1)
+            .mount(&server)
+            .await;
+
+        let filter = ListFilter {
+            tags: vec!["x".to_string()],
+            archived: Toggle::Off,
             domain: Some("test.domain".to_string()),
-            default_group_guid: Some("test-group-guid".to_string()),
-            cache_dir: Some(PathBuf::new()),
-            offline: false,
-            max_concurrent: 4,
+            ..Default::default()
+        };
+
+        let client = Client::new(config).await.expect("initialized client");
+        let results = client.list(filter).collect::<Vec<_>>().await;
+
+        match results.into_iter().collect::<Result<Vec<_>>>() {
+            Ok(actual) => {
+                let ids = actual.into_iter().map(|link| link.id).collect::<Vec<_>>();
+                assert_eq!(vec!["test.domain/a", "test.domain/c"], ids);
+            }
+            Err(error) => panic!("encountered API/client error: {error:?}"),
         }
     }
 
     #[fixture]
-    async fn server_config(#[future(awt)] server: MockServer, mut config: Config) -> ServerConfig {
-        config.with_api_url(server.uri().parse().expect("valid mock API URL"));
-        ServerConfig { server, config }
+    fn groups_response() -> ResponseTemplate {
+        ResponseTemplate::new(StatusCode::OK).set_body_raw(
+            r#"{
+              "groups": [
+                {"guid": "g1", "name": "marketing", "organization_guid": "o1", "is_active": true},
+                {"guid": "g2", "name": "support", "organization_guid": "o1", "is_active": true},
+                {"guid": "g3", "name": "support", "organization_guid": "o2", "is_active": true}
+              ]
+            }"#,
+            "application/json",
+        )
     }
 
-    #[fixture]
-    fn urls() -> Vec<Url> {
-        vec![
-            Url::parse("https://example.com").unwrap(),
-            Url::parse("http://example.com").unwrap(),
-        ]
-    }
+    #[rstest]
+    #[tokio::test]
+    async fn resolve_group_by_name(
+        #[future(awt)] server_config: ServerConfig,
+        groups_response: ResponseTemplate,
+    ) {
+        let ServerConfig { server, mut config } = server_config;
 
-    #[fixture]
-    fn shorten_test(
-        #[default(Ordering::Ordered)] ordering: Ordering,
-        urls: Vec<Url>,
-    ) -> ShortenTest {
-        ShortenTest {
-            urls,
-            responder: LinkResponder::new(
-                ordering,
-                [
-                    r#"{
-                      "created_at": "2024-08-07T08:48:48+0000",
-                      "id": "1",
-                      "link": "https://test.domain/4ePsyXN",
-                      "custom_bitlinks": [],
-                      "long_url": "https://example.com",
-                      "archived": false,
-                      "tags": [],
-                      "deeplinks": [],
-                      "references": {
-                        "group": "https://api-ssl.bitly.com/v4/groups/test-group-guid"
-                      }
-                    }"#,
-                    r#"{
-                      "created_at": "2024-08-07T08:48:49+0000",
-                      "id": "2",
-                      "link": "https://test.domain/3WA1XXp",
-                      "custom_bitlinks": [],
-                      "long_url": "http://example.com",
-                      "archived": false,
-                      "tags": [],
-                      "deeplinks": [],
-                      "references": {
-                        "group": "https://api-ssl.bitly.com/v4/groups/test-group-guid"
-                      }
-                    }"#,
-                ],
-            ),
-            expected: vec![
-                Bitlink {
-                    link: "https://test.domain/4ePsyXN".parse().unwrap(),
-                    id: "1".to_string(),
-                    long_url: "https://example.com".parse().unwrap(),
-                },
-                Bitlink {
-                    link: "https://test.domain/3WA1XXp".parse().unwrap(),
-                    id: "2".to_string(),
-                    long_url: "http://example.com".parse().unwrap(),
-                },
-            ],
-        }
-    }
+        let cache_dir = tempfile::tempdir().expect("temp cache dir");
+        config.cache_dir = Some(cache_dir.path().to_path_buf());
+        config.default_group_guid = None;
+        config.default_group = Some("marketing".to_string());
 
-    async fn test_shorten(
-        config: Config,
-        urls: Vec<Url>,
-        ordering: Ordering,
-    ) -> Vec<Result<Bitlink>> {
-        // TODO: parametrize client by cache to be able to mock it for tests
-        let client = Client::new(config).await;
-        client
-            .shorten(stream::iter(urls), ordering)
-            .collect::<Vec<_>>()
-            .await
+        Mock::given(method("GET"))
+            .and(path("v4/groups"))
+            .respond_with(groups_response)
+            .expect(1)
+            .mount(&server)
+            .await;
+
+        let client = Client::new(config).await.expect("initialized client");
+
+        // NOTE: the group is resolved just once
+        for _ in 0..2 {
+            match client.inner.group_guid().await {
+                Ok(guid) => assert_eq!("g1", guid),
+                Err(error) => panic!("expected group to be resolved, got: {error:?}"),
+            }
+        }
     }
 
     #[rstest]
     #[tokio::test]
-    async fn shorten_urls_ordered(
+    async fn resolve_group_after_partial_groups(
         #[future(awt)] server_config: ServerConfig,
-        #[from(shorten_test)] ShortenTest {
-            urls,
-            responder,
-            expected,
-        }: ShortenTest,
+        #[from(config)] mut next: Config,
+        groups_response: ResponseTemplate,
     ) {
-        let ServerConfig { server, config } = server_config;
+        let ServerConfig { server, mut config } = server_config;
 
-        Mock::given(method("POST"))
-            .and(path("v4/shorten"))
-            .respond_with(responder)
+        let cache_dir = tempfile::tempdir().expect("temp cache dir");
+        config.cache_dir = Some(cache_dir.path().to_path_buf());
+        config.default_group_guid = None;
+        config.default_group = Some("support".to_string());
+
+        next.with_api_url(config.api_url.clone());
+        next.cache_dir.clone_from(&config.cache_dir);
+        next.default_group_guid = None;
+        next.default_group = Some("marketing".to_string());
+
+        let partial = ResponseTemplate::new(StatusCode::OK).set_body_raw(
+            r#"{
+              "groups": [
+                {"guid": "g3", "name": "support", "organization_guid": "o2", "is_active": true}
+              ]
+            }"#,
+            "application/json",
+        );
+
+        Mock::given(method("GET"))
+            .and(path("v4/groups"))
+            .and(query_param("organization_guid", "o2"))
+            .respond_with(partial)
+            .expect(1)
             .mount(&server)
             .await;
 
-        let results = test_shorten(config, urls, Ordering::Ordered).await;
+        // NOTE: all the groups are fetched just once, the next run trusts the cached ones
+        Mock::given(method("GET"))
+            .and(path("v4/groups"))
+            .and(query_param_is_missing("organization_guid"))
+            .respond_with(groups_response)
+            .expect(1)
+            .mount(&server)
+            .await;
 
-        match results.into_iter().collect::<Result<Vec<_>>>() {
-            Ok(actual) => assert_eq!(expected, actual),
-            Err(error) => panic!("encountered API/client error: {error:?}"),
+        let client = Client::new(config).await.expect("initialized client");
+
+        match client.groups(Some("o2")).await {
+            Ok(groups) => assert_eq!(1, groups.len(), "{groups:?}"),
+            Err(error) => panic!("expected groups of an organization, got: {error:?}"),
+        }
+
+        // the cached groups of an organization are not all the groups with the name
+        match client.inner.group_guid().await {
+            Err(Error::AmbiguousGroup { name, candidates }) => {
+                assert_eq!("support", name);
+                assert_eq!(
+                    vec!["g2 (organization o1)", "g3 (organization o2)"],
+                    candidates
+                );
+            }
+            result => panic!("expected ambiguous group, got: {result:?}"),
+        }
+
+        let client = Client::new(next).await.expect("initialized client");
+
+        match client.inner.group_guid().await {
+            Ok(guid) => assert_eq!("g1", guid),
+            Err(error) => panic!("expected group to be resolved, got: {error:?}"),
         }
     }
 
     #[rstest]
     #[tokio::test]
-    async fn shorten_urls_unordered(
+    async fn resolve_default_group_once(
         #[future(awt)] server_config: ServerConfig,
-        #[from(shorten_test)]
-        #[with(Ordering::Unordered)]
-        ShortenTest {
+        #[from(shorten_test)] ShortenTest {
             urls,
             responder,
             expected,
         }: ShortenTest,
     ) {
-        let ServerConfig { server, config } = server_config;
+        let ServerConfig { server, mut config } = server_config;
+
+        let cache_dir = tempfile::tempdir().expect("temp cache dir");
+        config.cache_dir = Some(cache_dir.path().to_path_buf());
+        config.default_group_guid = None;
+
+        // NOTE: the responder replies in order, so the requests must be sequential
+        config.max_concurrent = 1;
+
+        let user = ResponseTemplate::new(StatusCode::OK).set_body_raw(
+            r#"{"is_active": true, "default_group_guid": "test-group-guid"}"#,
+            "application/json",
+        );
+
+        Mock::given(method("GET"))
+            .and(path("v4/user"))
+            .respond_with(user)
+            .expect(1)
+            .mount(&server)
+            .await;
 
         Mock::given(method("POST"))
             .and(path("v4/shorten"))
+            .and(body_partial_json(
+                serde_json::json!({ "group_guid": "test-group-guid" }),
+            ))
             .respond_with(responder)
             .mount(&server)
             .await;
 
-        let results = test_shorten(config, urls, Ordering::Unordered).await;
+        let results = test_shorten(config, urls.clone(), Ordering::Ordered).await;
 
         match results.into_iter().collect::<Result<Vec<_>>>() {
-            Ok(mut actual) => {
-                actual.sort_by_cached_key(|link| link.id.parse::<u8>().expect("use u8 IDs"));
-                assert_eq!(expected, actual)
-            }
+            Ok(actual) => assert_eq!(expected, actual),
             Err(error) => panic!("encountered API/client error: {error:?}"),
         }
+
+        // the resolved group has been persisted, so the offline mode can use it
+        let offline = Config {
+            api_url: Url::parse("https://api-ssl.bitly.com").unwrap(),
+            api_token: "secret-token".into(),
+            domain: Some("test.domain".to_string()),
+            default_group_guid: None,
+            default_group: None,
+            cache_dir: Some(cache_dir.path().to_path_buf()),
+            cache_max_age_s: 0,
+            offline: true,
+            max_concurrent: 4,
+            adaptive_concurrency: false,
+            max_concurrent_ceiling: 64,
+            rate_limit: Some(0),
+            request_timeout_ms: 5_000,
+            connect_timeout_ms: 5_000,
+            retry: RetryPolicy::default(),
+            cassette: None,
+        };
+
+        let results = test_shorten(offline, urls, Ordering::Ordered).await;
+
+        match results.into_iter().collect::<Result<Vec<_>>>() {
+            Ok(actual) => assert_eq!(expected, actual),
+            Err(error) => panic!("expected cached results, got: {error:?}"),
+        }
     }
 
     #[rstest]
     #[tokio::test]
-    async fn shorten_auth_error(#[future(awt)] server_config: ServerConfig, urls: Vec<Url>) {
-        let ServerConfig { server, config } = server_config;
+    async fn resolve_ambiguous_group(
+        #[future(awt)] server_config: ServerConfig,
+        groups_response: ResponseTemplate,
+    ) {
+        let ServerConfig { server, mut config } = server_config;
 
-        let forbidden = ResponseTemplate::new(StatusCode::FORBIDDEN)
-            .set_body_raw(r#"{"message": "FORBIDDEN"}"#, "application/json");
+        config.default_group_guid = None;
+        config.default_group = Some("support".to_string());
 
-        Mock::given(method("POST"))
-            .and(path("v4/shorten"))
-            .respond_with(forbidden)
+        Mock::given(method("GET"))
+            .and(path("v4/groups"))
+            .respond_with(groups_response)
             .mount(&server)
             .await;
 
-        let results = test_shorten(config, urls, Ordering::Ordered).await;
+        let client = Client::new(config).await.expect("initialized client");
 
-        match results.into_iter().collect::<Result<Vec<_>>>() {
-            Ok(links) => panic!("expected API error (FORBIDDEN), got: {links:?}"),
-            Err(Error::Bitly(resp)) => assert_eq!("FORBIDDEN", resp.message),
-            Err(error) => panic!("expected API error (FORBIDDEN), got: {error:?}"),
+        match client.inner.group_guid().await {
+            Err(Error::AmbiguousGroup { name, candidates }) => {
+                assert_eq!("support", name);
+                assert_eq!(
+                    vec!["g2 (organization o1)", "g3 (organization o2)"],
+                    candidates
+                );
+            }
+            result => panic!("expected ambiguous group error, got: {result:?}"),
         }
     }
 
-    // TODO: test with caching enabled and --offline
+    // TODO: test shorten with caching enabled and --offline
+
+    #[rstest]
+    #[case::url("https://bit.ly/4ePsyXN", "bit.ly/4ePsyXN")]
+    #[case::id("bit.ly/4ePsyXN", "bit.ly/4ePsyXN")]
+    #[case::trailing_slash("https://bit.ly/4ePsyXN/", "bit.ly/4ePsyXN")]
+    fn parse_bitlink_ref(#[case] input: &str, #[case] expected_id: &str) {
+        let bitlink = input.parse::<BitlinkRef>().expect("valid bitlink");
+        assert_eq!(expected_id, bitlink.id());
+    }
 
     #[rstest]
     #[case::shorten(
@@ -1,21 +1,34 @@
-use std::path::Path;
+use std::collections::BTreeMap;
+use std::path::{Path, PathBuf};
 use std::str::FromStr as _;
+use std::time::{Duration, SystemTime, UNIX_EPOCH};
 
+use serde::{Deserialize, Serialize};
 use sqlx::prelude::*;
 use sqlx::sqlite::{SqliteConnectOptions, SqlitePool, SqliteRow};
 use tracing::{debug, error, instrument};
+use url::Url;
 
-use crate::api::{Bitlink, Shorten};
+use crate::api::{Bitlink, Deeplink, Group, Shorten};
+use crate::cli::ConflictPolicy;
 use crate::config::APP;
+use crate::error::{Error, Result};
+use crate::migrations::{self, Migration};
 
 #[derive(Debug)]
 pub struct BitlinkCache {
     pool: SqlitePool,
+    /// Path to the database file
+    path: PathBuf,
 }
 
 impl BitlinkCache {
-    #[instrument(name = "init_cache", level = "debug", skip(cache_dir))]
-    pub async fn new(name: &str, cache_dir: Option<impl AsRef<Path>>) -> Option<Self> {
+    /// Open the database in given cache directory without migrating its schema
+    ///
+    /// If the `cache_dir` is unspecified, the XDG cache directory is used. An empty path disables
+    /// the cache.
+    #[instrument(level = "debug", skip(cache_dir))]
+    pub async fn connect(name: &str, cache_dir: Option<impl AsRef<Path>>) -> Option<Self> {
         let cache_dir = match cache_dir {
             Some(dir) if dir.as_ref().as_os_str().is_empty() => return None,
             Some(cache_dir) => std::path::absolute(cache_dir).ok()?,
@@ -34,8 +47,8 @@ impl BitlinkCache {
             return None;
         }
 
-        let path = cache_dir.join(format!("{name}.db"));
-        let path = path.to_string_lossy();
+        let db = cache_dir.join(format!("{name}.db"));
+        let path = db.to_string_lossy();
 
         let Ok(ops) = SqliteConnectOptions::from_str(&format!("sqlite:{path}")) else {
             error!(?path, "invalid database path");
@@ -54,56 +67,170 @@ impl BitlinkCache {
             }
         };
 
-        debug!("setting up database tables");
-
-        let res = sqlx::query(
-            r#"
-            CREATE TABLE IF NOT EXISTS shorten (
-              id TEXT NOT NULL UNIQUE,
-              link TEXT NOT NULL,
-              long_url TEXT NOT NULL,
-              domain TEXT,
-              group_guid TEXT NOT NULL
-            );
+        Some(Self { pool, path: db })
+    }
 
-            CREATE UNIQUE INDEX IF NOT EXISTS ix_shorten
-            ON shorten (group_guid, domain, long_url);
-            "#,
-        )
-        .execute(&pool)
-        .await;
+    /// Open the database in given cache directory and apply all the pending schema migrations
+    ///
+    /// Fails (with an error logged) if the database has been created by a newer version.
+    #[instrument(name = "init_cache", level = "debug", skip(cache_dir))]
+    pub async fn new(name: &str, cache_dir: Option<impl AsRef<Path>>) -> Option<Self> {
+        let cache = Self::connect(name, cache_dir).await?;
 
-        if let Err(error) = res {
-            error!(%error, "failed to set up database");
-            return None;
+        match cache.migrate().await {
+            Ok(applied) => {
+                debug!(n = applied.len(), "applied database migrations");
+                Some(cache)
+            }
+            Err(error) => {
+                error!(%error, "failed to set up database");
+                None
+            }
         }
+    }
 
-        Some(Self { pool })
+    /// Current schema version of the database (see [`migrations::schema_version`])
+    #[inline]
+    pub async fn schema_version(&self) -> sqlx::Result<u32> {
+        migrations::schema_version(&self.pool).await
     }
 
-    #[instrument(level = "debug", skip(self))]
+    /// Migrations of the database schema that have not been applied yet
+    #[inline]
+    pub async fn pending_migrations(&self) -> Result<&'static [Migration]> {
+        migrations::pending(&self.pool).await
+    }
+
+    /// Apply all the pending migrations of the database schema, returning the applied ones
+    #[inline]
+    pub async fn migrate(&self) -> Result<&'static [Migration]> {
+        migrations::migrate(&self.pool).await
+    }
+
+    #[inline]
     pub async fn get(&self, query: &Shorten<'_>) -> Option<Bitlink> {
+        self.lookup(query, None).await.map(Cached::into_inner)
+    }
+
+    /// Get the bitlink cached for given request, which is stale if it's older than `max_age`
+    #[instrument(level = "debug", skip(self))]
+    pub async fn lookup(
+        &self,
+        query: &Shorten<'_>,
+        max_age: Option<Duration>,
+    ) -> Option<Cached<Bitlink>> {
         debug!("checking local cache");
 
-        let res = sqlx::query_as(
+        let res = sqlx::query(
             r#"
-            SELECT id, link, long_url
+            SELECT id, link, long_url, cached_at
             FROM shorten
-            WHERE group_guid = $1 AND domain = $2 AND long_url = $3
+            WHERE group_guid = $1 AND domain IS $2 AND long_url = $3 AND options = $4
             LIMIT 1
             "#,
         )
         .bind(query.group_guid.as_ref())
         .bind(query.domain.as_ref())
         .bind(query.long_url.as_str())
+        .bind(options_key(query))
+        .fetch_optional(&self.pool)
+        .await;
+
+        cached(res, max_age)
+    }
+
+    /// Reverse lookup of a bitlink by its short `link`
+    #[cfg(test)]
+    #[inline]
+    pub async fn get_by_link(&self, link: &Url) -> Option<Bitlink> {
+        self.lookup_by_link(link, None)
+            .await
+            .map(Cached::into_inner)
+    }
+
+    /// Reverse lookup of a bitlink by its short `link`, which is stale if it's older than `max_age`
+    #[instrument(level = "debug", skip(self), fields(%link))]
+    pub async fn lookup_by_link(
+        &self,
+        link: &Url,
+        max_age: Option<Duration>,
+    ) -> Option<Cached<Bitlink>> {
+        debug!("checking local cache");
+
+        let res = sqlx::query(
+            r#"
+            SELECT id, link, long_url, cached_at
+            FROM shorten
+            WHERE link = $1
+            LIMIT 1
+            "#,
+        )
+        .bind(link.as_str())
         .fetch_optional(&self.pool)
         .await;
 
+        cached(res, max_age)
+    }
+
+    /// Mark the cached bitlink with given `id` as fresh (e.g., after it's been revalidated)
+    ///
+    /// Note that the same bitlink might be cached for several requests (e.g., with different tags).
+    #[instrument(level = "debug", skip(self), ret)]
+    pub async fn touch(&self, id: &str) -> bool {
+        let res = sqlx::query("UPDATE shorten SET cached_at = $2 WHERE id = $1")
+            .bind(id)
+            .bind(unix_now())
+            .execute(&self.pool)
+            .await;
+
         match res {
-            Ok(link) => link,
+            Ok(res) => res.rows_affected() >= 1,
             Err(error) => {
-                error!(%error, "failed to access local cache");
-                None
+                error!(%error, "failed to update local cache");
+                false
+            }
+        }
+    }
+
+    /// Drop all the entries of the bitlink with given `id` (e.g., after it's been deleted)
+    #[instrument(level = "debug", skip(self), ret)]
+    pub async fn evict(&self, id: &str) -> bool {
+        let res = sqlx::query("DELETE FROM shorten WHERE id = $1")
+            .bind(id)
+            .execute(&self.pool)
+            .await;
+
+        match res {
+            Ok(res) => res.rows_affected() >= 1,
+            Err(error) => {
+                error!(%error, "failed to update local cache");
+                false
+            }
+        }
+    }
+
+    /// Point the cached bitlink with given `id` (all its entries) to a new `long_url`
+    ///
+    /// Any other entry that would conflict with the updated one (i.e., a different bitlink for the
+    /// same `long_url` under the same key) is replaced.
+    #[instrument(level = "debug", skip(self), fields(%long_url), ret)]
+    pub async fn update_long_url(&self, id: &str, long_url: &Url) -> bool {
+        debug!("updating long URL in local cache");
+
+        let res = sqlx::query(
+            "UPDATE OR REPLACE shorten SET long_url = $2, cached_at = $3 WHERE id = $1",
+        )
+        .bind(id)
+        .bind(long_url.as_str())
+        .bind(unix_now())
+        .execute(&self.pool)
+        .await;
+
+        match res {
+            Ok(res) => res.rows_affected() >= 1,
+            Err(error) => {
+                error!(%error, "failed to update local cache");
+                false
             }
         }
     }
@@ -114,8 +241,8 @@ impl BitlinkCache {
 
         let res = sqlx::query(
             r#"
-            INSERT INTO shorten (id, link, long_url, domain, group_guid) VALUES
-            ($1, $2, $3, $4, $5)
+            INSERT INTO shorten (id, link, long_url, domain, group_guid, options, cached_at)
+            VALUES ($1, $2, $3, $4, $5, $6, $7)
             "#,
         )
         .bind(&link.id)
@@ -123,6 +250,8 @@ impl BitlinkCache {
         .bind(query.long_url.as_str())
         .bind(query.domain.as_ref())
         .bind(query.group_guid.as_ref())
+        .bind(options_key(query))
+        .bind(unix_now())
         .execute(&self.pool)
         .await;
 
@@ -136,6 +265,627 @@ impl BitlinkCache {
     }
 }
 
+impl BitlinkCache {
+    /// Get a stored value unless it's older than `max_age` (if specified)
+    #[instrument(level = "debug", skip(self))]
+    pub async fn get_value(&self, key: &str, max_age: Option<Duration>) -> Option<String> {
+        debug!("checking local cache");
+
+        let res =
+            sqlx::query_as::<_, (String, i64)>("SELECT value, updated_at FROM kv WHERE key = $1")
+                .bind(key)
+                .fetch_optional(&self.pool)
+                .await;
+
+        match res {
+            Ok(Some((value, updated_at))) => match max_age {
+                Some(max_age)
+                    if unix_now().saturating_sub(updated_at) > max_age.as_secs() as i64 =>
+                {
+                    debug!(updated_at, "cached value expired");
+                    None
+                }
+                _ => Some(value),
+            },
+            Ok(None) => None,
+            Err(error) => {
+                error!(%error, "failed to access local cache");
+                None
+            }
+        }
+    }
+
+    /// Store a value under given key, replacing any previous one
+    #[instrument(level = "debug", skip(self), ret)]
+    pub async fn set_value(&self, key: &str, value: &str) -> bool {
+        debug!("updating local cache");
+
+        let res =
+            sqlx::query("INSERT OR REPLACE INTO kv (key, value, updated_at) VALUES ($1, $2, $3)")
+                .bind(key)
+                .bind(value)
+                .bind(unix_now())
+                .execute(&self.pool)
+                .await;
+
+        match res {
+            Ok(res) => res.rows_affected() == 1,
+            Err(error) => {
+                error!(%error, "failed to update local cache");
+                false
+            }
+        }
+    }
+
+    /// Replace the value stored under given key, keeping its age (see [`Self::get_value`])
+    ///
+    /// Returns `false` if there's no value stored under the key.
+    #[instrument(level = "debug", skip(self), ret)]
+    pub async fn replace_value(&self, key: &str, value: &str) -> bool {
+        debug!("updating local cache");
+
+        let res = sqlx::query("UPDATE kv SET value = $2 WHERE key = $1")
+            .bind(key)
+            .bind(value)
+            .execute(&self.pool)
+            .await;
+
+        match res {
+            Ok(res) => res.rows_affected() == 1,
+            Err(error) => {
+                error!(%error, "failed to update local cache");
+                false
+            }
+        }
+    }
+
+    /// Register new job with given (serialized) spec, returns `false` if the job already exists
+    #[instrument(level = "debug", skip(self, spec))]
+    pub async fn create_job(&self, name: &str, spec: &str) -> sqlx::Result<bool> {
+        let res =
+            sqlx::query("INSERT OR IGNORE INTO jobs (name, spec, created_at) VALUES ($1, $2, $3)")
+                .bind(name)
+                .bind(spec)
+                .bind(unix_now())
+                .execute(&self.pool)
+                .await?;
+
+        Ok(res.rows_affected() == 1)
+    }
+
+    /// Get the (serialized) spec of given job
+    #[instrument(level = "debug", skip(self))]
+    pub async fn get_job(&self, name: &str) -> sqlx::Result<Option<String>> {
+        sqlx::query_scalar("SELECT spec FROM jobs WHERE name = $1")
+            .bind(name)
+            .fetch_optional(&self.pool)
+            .await
+    }
+
+    /// Get inputs (by their sequence number) that have been acknowledged as processed by a job,
+    /// together with their outputs (if they succeeded)
+    #[instrument(level = "debug", skip(self))]
+    pub async fn acknowledged(
+        &self,
+        job: &str,
+    ) -> sqlx::Result<BTreeMap<u64, (String, Option<String>)>> {
+        let rows: Vec<(i64, String, Option<String>)> =
+            sqlx::query_as("SELECT seq, input, output FROM journal WHERE job = $1 ORDER BY seq")
+                .bind(job)
+                .fetch_all(&self.pool)
+                .await?;
+
+        Ok(rows
+            .into_iter()
+            .map(|(seq, input, output)| (seq as u64, (input, output)))
+            .collect())
+    }
+
+    /// Record the outcome of processing an input of a job
+    #[instrument(level = "debug", skip(self, output, error))]
+    pub async fn acknowledge(
+        &self,
+        job: &str,
+        seq: u64,
+        input: &str,
+        output: Option<&str>,
+        error: Option<&str>,
+    ) -> sqlx::Result<()> {
+        sqlx::query(
+            r#"
+            INSERT OR REPLACE INTO journal (job, seq, input, output, error)
+            VALUES ($1, $2, $3, $4, $5)
+            "#,
+        )
+        .bind(job)
+        .bind(seq as i64)
+        .bind(input)
+        .bind(output)
+        .bind(error)
+        .execute(&self.pool)
+        .await?;
+
+        Ok(())
+    }
+
+    /// Find all the groups with given name
+    #[instrument(level = "debug", skip(self))]
+    pub async fn find_groups(&self, name: &str) -> Vec<Group> {
+        debug!("looking up groups in local cache");
+
+        let res = sqlx::query_as(
+            r#"
+            SELECT guid, name, organization_guid, is_active, role
+            FROM groups
+            WHERE name = $1
+            ORDER BY guid
+            "#,
+        )
+        .bind(name)
+        .fetch_all(&self.pool)
+        .await;
+
+        match res {
+            Ok(groups) => groups,
+            Err(error) => {
+                error!(%error, "failed to access local cache");
+                Vec::new()
+            }
+        }
+    }
+
+    /// Insert or update given groups
+    #[inline]
+    pub async fn set_groups(&self, groups: &[Group]) -> bool {
+        self.store_groups(groups, false).await
+    }
+
+    /// Replace all the cached groups with given ones (e.g., all the groups of the user)
+    #[inline]
+    pub async fn replace_groups(&self, groups: &[Group]) -> bool {
+        self.store_groups(groups, true).await
+    }
+
+    #[instrument(level = "debug", skip(self, groups), fields(n = groups.len()), ret)]
+    async fn store_groups(&self, groups: &[Group], replace: bool) -> bool {
+        debug!("updating groups in local cache");
+
+        let res = async {
+            let mut tx = self.pool.begin().await?;
+
+            if replace {
+                sqlx::query("DELETE FROM groups").execute(&mut *tx).await?;
+            }
+
+            for group in groups {
+                sqlx::query(
+                    r#"
+                    INSERT OR REPLACE INTO groups (guid, name, organization_guid, is_active, role)
+                    VALUES ($1, $2, $3, $4, $5)
+                    "#,
+                )
+                .bind(&group.guid)
+                .bind(&group.name)
+                .bind(&group.organization_guid)
+                .bind(group.is_active)
+                .bind(&group.role)
+                .execute(&mut *tx)
+                .await?;
+            }
+
+            tx.commit().await
+        }
+        .await;
+
+        match res {
+            Ok(()) => true,
+            Err(error) => {
+                error!(%error, "failed to update local cache");
+                false
+            }
+        }
+    }
+}
+
+/// Cached bitlink along with the key it's been cached under
+#[derive(Clone, Debug, Deserialize, Serialize, PartialEq, Eq)]
+pub struct CacheEntry {
+    pub id: String,
+    pub link: Url,
+    pub long_url: Url,
+    pub domain: Option<String>,
+    pub group_guid: String,
+}
+
+impl std::fmt::Display for CacheEntry {
+    #[inline]
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(f, "{}\t{}", self.link, self.long_url)
+    }
+}
+
+/// Filter of cached bitlinks (see [`BitlinkCache::entries`])
+#[derive(Debug, Default)]
+pub struct CacheFilter {
+    /// Only entries under this domain
+    pub domain: Option<String>,
+    /// Only entries of this group
+    pub group_guid: Option<String>,
+    /// Only entries of groups with this name (as cached by `bitcli groups list`)
+    pub group: Option<String>,
+    /// Only entries whose long URL or link matches this glob pattern (e.g., `*example.com*`)
+    pub pattern: Option<String>,
+    /// Maximum number of entries
+    pub limit: Option<usize>,
+}
+
+/// Number of imported entries by how they've been handled (see [`BitlinkCache::import`])
+#[derive(Debug, Default, PartialEq, Eq)]
+pub struct ImportSummary {
+    /// New entries
+    pub imported: usize,
+    /// Entries that replaced conflicting cached ones
+    pub replaced: usize,
+    /// Entries equal to or conflicting with cached ones, which have been kept
+    pub skipped: usize,
+}
+
+/// Summary of the contents of the local database
+#[derive(Debug, Serialize)]
+pub struct CacheStats {
+    pub path: PathBuf,
+    /// Size of the database files (in bytes)
+    pub size: u64,
+    pub bitlinks: u64,
+    pub groups: u64,
+    pub values: u64,
+    pub jobs: u64,
+    /// Number of cached bitlinks per domain (`None` being the account's default domain)
+    pub domains: Vec<DomainStats>,
+}
+
+#[derive(Debug, Serialize)]
+pub struct DomainStats {
+    pub domain: Option<String>,
+    pub bitlinks: u64,
+}
+
+impl std::fmt::Display for CacheStats {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        writeln!(f, "path: {}", self.path.display())?;
+        writeln!(f, "size: {} B", self.size)?;
+        writeln!(f, "bitlinks: {}", self.bitlinks)?;
+
+        for DomainStats { domain, bitlinks } in &self.domains {
+            let domain = domain.as_deref().unwrap_or("(default)");
+            writeln!(f, "  {domain}: {bitlinks}")?;
+        }
+
+        writeln!(f, "groups: {}", self.groups)?;
+        writeln!(f, "values: {}", self.values)?;
+        write!(f, "jobs: {}", self.jobs)
+    }
+}
+
+impl BitlinkCache {
+    /// List cached bitlinks matching given filter (ordered by their links)
+    #[instrument(level = "debug", skip(self))]
+    pub async fn entries(&self, filter: &CacheFilter) -> sqlx::Result<Vec<CacheEntry>> {
+        sqlx::query_as(
+            r#"
+            SELECT id, link, long_url, domain, group_guid
+            FROM shorten
+            WHERE ($1 IS NULL OR domain IS $1)
+              AND ($2 IS NULL OR group_guid = $2)
+              AND ($3 IS NULL OR group_guid IN (SELECT guid FROM groups WHERE name = $3))
+              AND ($4 IS NULL OR long_url GLOB $4 OR link GLOB $4)
+            ORDER BY link
+            LIMIT $5
+            "#,
+        )
+        .bind(filter.domain.as_deref())
+        .bind(filter.group_guid.as_deref())
+        .bind(filter.group.as_deref())
+        .bind(filter.pattern.as_deref())
+        .bind(filter.limit.map_or(-1, |limit| limit as i64))
+        .fetch_all(&self.pool)
+        .await
+    }
+
+    /// Collect row counts and the on-disk size of the database
+    #[instrument(level = "debug", skip(self))]
+    pub async fn stats(&self) -> sqlx::Result<CacheStats> {
+        let (bitlinks, groups, values, jobs): (i64, i64, i64, i64) = sqlx::query_as(
+            r#"
+            SELECT
+              (SELECT COUNT(*) FROM shorten),
+              (SELECT COUNT(*) FROM groups),
+              (SELECT COUNT(*) FROM kv),
+              (SELECT COUNT(*) FROM jobs)
+            "#,
+        )
+        .fetch_one(&self.pool)
+        .await?;
+
+        let domains: Vec<(Option<String>, i64)> = sqlx::query_as(
+            r#"
+            SELECT domain, COUNT(*) AS bitlinks
+            FROM shorten
+            GROUP BY domain
+            ORDER BY bitlinks DESC, domain
+            "#,
+        )
+        .fetch_all(&self.pool)
+        .await?;
+
+        let domains = domains
+            .into_iter()
+            .map(|(domain, bitlinks)| DomainStats {
+                domain,
+                bitlinks: bitlinks as u64,
+            })
+            .collect();
+
+        Ok(CacheStats {
+            path: self.path.clone(),
+            size: self.size(),
+            bitlinks: bitlinks as u64,
+            groups: groups as u64,
+            values: values as u64,
+            jobs: jobs as u64,
+            domains,
+        })
+    }
+
+    /// Remove cached bitlinks with given link or long URL, returning the removed entries
+    #[instrument(level = "debug", skip(self), fields(%url))]
+    pub async fn remove(&self, url: &Url) -> sqlx::Result<Vec<CacheEntry>> {
+        sqlx::query_as(
+            r#"
+            DELETE FROM shorten
+            WHERE link = $1 OR long_url = $1
+            RETURNING id, link, long_url, domain, group_guid
+            "#,
+        )
+        .bind(url.as_str())
+        .fetch_all(&self.pool)
+        .await
+    }
+
+    /// Remove all the cached bitlinks, groups and values, returning the number of removed bitlinks
+    ///
+    /// Note that jobs and their journals are kept, so that they can still be resumed.
+    #[instrument(level = "debug", skip(self))]
+    pub async fn clear(&self) -> sqlx::Result<u64> {
+        let mut tx = self.pool.begin().await?;
+
+        let res = sqlx::query("DELETE FROM shorten").execute(&mut *tx).await?;
+
+        sqlx::query("DELETE FROM groups; DELETE FROM kv;")
+            .execute(&mut *tx)
+            .await?;
+
+        tx.commit().await?;
+
+        Ok(res.rows_affected())
+    }
+
+    /// Rebuild the database file to reclaim unused space, returning its size before and after
+    #[instrument(level = "debug", skip(self))]
+    pub async fn vacuum(&self) -> sqlx::Result<(u64, u64)> {
+        let before = self.size();
+
+        sqlx::query("VACUUM").execute(&self.pool).await?;
+
+        Ok((before, self.size()))
+    }
+
+    /// Get all the cached bitlinks of plain shorten requests (in the order they were cached)
+    ///
+    /// Bitlinks created with a title, tags, deeplinks or a keyword are not included, since these
+    /// are cached under a key which is not part of a [`CacheEntry`].
+    #[instrument(level = "debug", skip(self))]
+    pub async fn export(&self) -> sqlx::Result<Vec<CacheEntry>> {
+        sqlx::query_as(
+            r#"
+            SELECT id, link, long_url, domain, group_guid
+            FROM shorten
+            WHERE options = ''
+            ORDER BY rowid
+            "#,
+        )
+        .fetch_all(&self.pool)
+        .await
+    }
+
+    /// Import given entries as cached bitlinks of plain shorten requests
+    ///
+    /// An entry conflicts with cached ones that have the same `id`, or the same key (i.e., the
+    /// group, domain and long URL of the `ix_shorten` index). Entries equal to the cached ones
+    /// are always skipped, other conflicts are resolved by given `policy`. The import is atomic,
+    /// so a conflict under [`ConflictPolicy::Fail`] leaves the cache unchanged.
+    #[instrument(level = "debug", skip(self, entries), fields(n = entries.len()))]
+    pub async fn import(
+        &self,
+        entries: &[CacheEntry],
+        policy: ConflictPolicy,
+    ) -> Result<ImportSummary> {
+        let mut summary = ImportSummary::default();
+
+        let mut tx = self.pool.begin().await?;
+
+        for entry in entries {
+            // NOTE: NULL domains are distinct in the unique index, hence the explicit lookup
+            let conflicts: Vec<CacheEntry> = sqlx::query_as(
+                r#"
+                SELECT id, link, long_url, domain, group_guid
+                FROM shorten
+                WHERE options = ''
+                  AND (id = $1 OR (group_guid = $2 AND domain IS $3 AND long_url = $4))
+                "#,
+            )
+            .bind(&entry.id)
+            .bind(&entry.group_guid)
+            .bind(entry.domain.as_deref())
+            .bind(entry.long_url.as_str())
+            .fetch_all(&mut *tx)
+            .await?;
+
+            match conflicts.as_slice() {
+                [] => summary.imported += 1,
+                [cached] if cached == entry => {
+                    summary.skipped += 1;
+                    continue;
+                }
+                [cached, ..] => match policy {
+                    ConflictPolicy::Skip => {
+                        summary.skipped += 1;
+                        continue;
+                    }
+                    ConflictPolicy::Overwrite => {
+                        for cached in &conflicts {
+                            sqlx::query("DELETE FROM shorten WHERE id = $1 AND options = ''")
+                                .bind(&cached.id)
+                                .execute(&mut *tx)
+                                .await?;
+                        }
+                        summary.replaced += 1;
+                    }
+                    ConflictPolicy::Fail => {
+                        return Err(Error::ImportConflict {
+                            link: entry.link.to_string(),
+                            long_url: entry.long_url.to_string(),
+                            cached: cached.link.to_string(),
+                        });
+                    }
+                },
+            }
+
+            sqlx::query(
+                r#"
+                INSERT INTO shorten (id, link, long_url, domain, group_guid, options, cached_at)
+                VALUES ($1, $2, $3, $4, $5, '', $6)
+                "#,
+            )
+            .bind(&entry.id)
+            .bind(entry.link.as_str())
+            .bind(entry.long_url.as_str())
+            .bind(entry.domain.as_deref())
+            .bind(&entry.group_guid)
+            .bind(unix_now())
+            .execute(&mut *tx)
+            .await?;
+        }
+
+        tx.commit().await?;
+
+        Ok(summary)
+    }
+
+    /// Size (in bytes) of the database file, including its write-ahead log (if any)
+    fn size(&self) -> u64 {
+        let mut wal = self.path.clone().into_os_string();
+        wal.push("-wal");
+
+        [self.path.as_os_str(), wal.as_os_str()]
+            .into_iter()
+            .filter_map(|path| std::fs::metadata(path).ok())
+            .map(|metadata| metadata.len())
+            .sum()
+    }
+}
+
+/// Cached item, which is _stale_ if it's older than some max-age (and should be revalidated)
+#[derive(Debug, PartialEq, Eq)]
+pub enum Cached<T> {
+    Fresh(T),
+    Stale(T),
+}
+
+impl<T> Cached<T> {
+    #[inline]
+    pub fn into_inner(self) -> T {
+        match self {
+            Self::Fresh(item) | Self::Stale(item) => item,
+        }
+    }
+}
+
+/// Decode a cached bitlink (if found), checking its `cached_at` against given `max_age`
+fn cached(
+    res: sqlx::Result<Option<SqliteRow>>,
+    max_age: Option<Duration>,
+) -> Option<Cached<Bitlink>> {
+    let res = res.and_then(|row| {
+        row.map(|row| {
+            Ok((
+                Bitlink::from_row(&row)?,
+                row.try_get::<i64, _>("cached_at")?,
+            ))
+        })
+        .transpose()
+    });
+
+    match res {
+        Ok(Some((bitlink, cached_at))) => match max_age {
+            Some(max_age) if unix_now().saturating_sub(cached_at) > max_age.as_secs() as i64 => {
+                debug!(cached_at, "cached bitlink is stale");
+                Some(Cached::Stale(bitlink))
+            }
+            _ => Some(Cached::Fresh(bitlink)),
+        },
+        Ok(None) => None,
+        Err(error) => {
+            error!(%error, "failed to access local cache");
+            None
+        }
+    }
+}
+
+/// Current time as a Unix timestamp (in seconds)
+#[inline]
+fn unix_now() -> i64 {
+    SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .map_or(0, |now| now.as_secs() as i64)
+}
+
+/// Canonical representation of the optional attributes of a shorten request
+///
+/// This is part of the cache key, so that e.g. a tagged request never resolves to an untagged
+/// bitlink. Plain requests map to an empty string.
+fn options_key(query: &Shorten<'_>) -> String {
+    #[derive(Serialize)]
+    struct Options<'a> {
+        #[serde(skip_serializing_if = "Option::is_none")]
+        title: Option<&'a str>,
+        #[serde(skip_serializing_if = "Vec::is_empty")]
+        tags: Vec<&'a str>,
+        #[serde(skip_serializing_if = "<[_]>::is_empty")]
+        deeplinks: &'a [Deeplink],
+        #[serde(skip_serializing_if = "Option::is_none")]
+        keyword: Option<&'a str>,
+    }
+
+    if !query.is_extended() && query.keyword.is_none() {
+        return String::new();
+    }
+
+    // NOTE: tags form a set, so their order (or repetition) should not matter
+    let mut tags = query.tags.iter().map(String::as_str).collect::<Vec<_>>();
+    tags.sort_unstable();
+    tags.dedup();
+
+    let options = Options {
+        title: query.title.as_deref(),
+        tags,
+        deeplinks: &query.deeplinks,
+        keyword: query.keyword.as_deref(),
+    };
+
+    serde_json::to_string(&options).unwrap_or_default()
+}
+
 impl FromRow<'_, SqliteRow> for Bitlink {
     fn from_row(row: &SqliteRow) -> sqlx::Result<Self> {
         Ok(Self {
@@ -146,6 +896,30 @@ impl FromRow<'_, SqliteRow> for Bitlink {
     }
 }
 
+impl FromRow<'_, SqliteRow> for CacheEntry {
+    fn from_row(row: &SqliteRow) -> sqlx::Result<Self> {
+        Ok(Self {
+            id: row.try_get("id")?,
+            link: row.try_from::<&str, _, _>("link")?,
+            long_url: row.try_from::<&str, _, _>("long_url")?,
+            domain: row.try_get("domain")?,
+            group_guid: row.try_get("group_guid")?,
+        })
+    }
+}
+
+impl FromRow<'_, SqliteRow> for Group {
+    fn from_row(row: &SqliteRow) -> sqlx::Result<Self> {
+        Ok(Self {
+            guid: row.try_get("guid")?,
+            name: row.try_get("name")?,
+            organization_guid: row.try_get("organization_guid")?,
+            is_active: row.try_get("is_active")?,
+            role: row.try_get("role")?,
+        })
+    }
+}
+
 trait RowExt: Row {
     fn try_from<'r, T, I, R>(&'r self, index: I) -> sqlx::Result<R>
     where
@@ -188,6 +962,10 @@ mod tests {
             long_url: "https://example.com".parse().unwrap(),
             domain: Some(Cow::Borrowed("bit.ly")),
             group_guid: Cow::Borrowed("test-group-guid"),
+            title: None,
+            tags: Cow::Borrowed(&[]),
+            deeplinks: Cow::Borrowed(&[]),
+            keyword: None,
         }
     }
 
@@ -253,6 +1031,474 @@ mod tests {
         assert_eq!(Some(link), cached);
     }
 
+    #[rstest]
+    #[tokio::test]
+    async fn options_are_part_of_key(
+        #[future(awt)] cache: BitlinkCache,
+        shorten: Shorten<'static>,
+        link: Bitlink,
+    ) {
+        cache.set(&shorten, &link).await;
+
+        let tags = ["b".to_string(), "a".to_string()];
+        let tagged = Shorten {
+            tags: Cow::Borrowed(&tags),
+            ..shorten
+        };
+
+        let cached = cache.get(&tagged).await;
+        assert!(
+            cached.is_none(),
+            "tagged request hit untagged link {cached:?}"
+        );
+
+        // NOTE: Bitly returns the same bitlink for the same long URL regardless of the options
+        let set = cache.set(&tagged, &link).await;
+        assert!(
+            set,
+            "cache set should succeed for a request with different options"
+        );
+
+        let tags = ["a".to_string(), "b".to_string(), "a".to_string()];
+        let reordered = Shorten {
+            tags: Cow::Borrowed(&tags),
+            ..tagged
+        };
+
+        assert_eq!(Some(&link), cache.get(&reordered).await.as_ref());
+
+        let entries = || async {
+            sqlx::query_scalar::<_, i64>("SELECT COUNT(*) FROM shorten WHERE id = $1")
+                .bind(&link.id)
+                .fetch_one(&cache.pool)
+                .await
+                .expect("count entries")
+        };
+
+        assert_eq!(2, entries().await);
+
+        assert!(cache.touch(&link.id).await);
+        assert!(cache.evict(&link.id).await);
+        assert_eq!(0, entries().await);
+    }
+
+    #[rstest]
+    #[tokio::test]
+    async fn get_by_link_reverse_lookup(
+        #[future(awt)] cache: BitlinkCache,
+        shorten: Shorten<'static>,
+        link: Bitlink,
+    ) {
+        cache.set(&shorten, &link).await;
+
+        let cached = cache.get_by_link(&link.link).await;
+        assert_eq!(Some(&link), cached.as_ref());
+
+        let other = "https://bit.ly/other".parse().unwrap();
+        let cached = cache.get_by_link(&other).await;
+        assert!(
+            cached.is_none(),
+            "expected no entry for {other}, got {cached:?}"
+        );
+    }
+
+    #[rstest]
+    #[tokio::test]
+    async fn update_long_url(
+        #[future(awt)] cache: BitlinkCache,
+        shorten: Shorten<'static>,
+        link: Bitlink,
+    ) {
+        cache.set(&shorten, &link).await;
+
+        let long_url: Url = "https://example.com/new".parse().unwrap();
+        assert!(cache.update_long_url(&link.id, &long_url).await);
+
+        assert!(cache.get(&shorten).await.is_none(), "stale entry");
+
+        let moved = Shorten {
+            long_url: long_url.clone(),
+            ..shorten
+        };
+
+        let expected = Bitlink { long_url, ..link };
+        assert_eq!(Some(expected), cache.get(&moved).await);
+    }
+
+    #[rstest]
+    #[tokio::test]
+    async fn stale_entries(
+        #[future(awt)] cache: BitlinkCache,
+        shorten: Shorten<'static>,
+        link: Bitlink,
+    ) {
+        let hour = Some(Duration::from_secs(60 * 60));
+
+        assert!(cache.lookup(&shorten, hour).await.is_none());
+        assert!(cache.set(&shorten, &link).await);
+
+        let expected = Some(Cached::Fresh(link.clone()));
+        assert_eq!(expected, cache.lookup(&shorten, hour).await);
+        assert_eq!(expected, cache.lookup_by_link(&link.link, hour).await);
+
+        sqlx::query("UPDATE shorten SET cached_at = cached_at - 7200 WHERE id = $1")
+            .bind(&link.id)
+            .execute(&cache.pool)
+            .await
+            .expect("backdate entry");
+
+        let expected = Some(Cached::Stale(link.clone()));
+        assert_eq!(expected, cache.lookup(&shorten, hour).await);
+        assert_eq!(expected, cache.lookup_by_link(&link.link, hour).await);
+
+        // NOTE: without a max-age, entries never go stale
+        let expected = Some(Cached::Fresh(link.clone()));
+        assert_eq!(expected, cache.lookup(&shorten, None).await);
+
+        assert!(cache.touch(&link.id).await);
+        assert_eq!(expected, cache.lookup(&shorten, hour).await);
+
+        assert!(cache.evict(&link.id).await);
+        assert!(cache.lookup(&shorten, None).await.is_none());
+        assert!(!cache.evict(&link.id).await, "already evicted");
+    }
+
+    #[rstest]
+    #[tokio::test]
+    async fn upgrade_legacy_schema(cache_dir: TempDir, shorten: Shorten<'static>, link: Bitlink) {
+        let path = cache_dir.path().join("test-legacy.db");
+        let ops = SqliteConnectOptions::new()
+            .filename(&path)
+            .create_if_missing(true);
+
+        let pool = SqlitePool::connect_with(ops)
+            .await
+            .expect("legacy database");
+
+        sqlx::query(
+            r#"
+            CREATE TABLE shorten (
+              id TEXT NOT NULL UNIQUE,
+              link TEXT NOT NULL,
+              long_url TEXT NOT NULL,
+              domain TEXT,
+              group_guid TEXT NOT NULL
+            );
+
+            CREATE UNIQUE INDEX ix_shorten
+            ON shorten (group_guid, domain, long_url);
+
+            INSERT INTO shorten (id, link, long_url, domain, group_guid) VALUES
+            ('some-bitlink-id', 'https://bit.ly/4ePsyXN', 'https://example.com/', 'bit.ly', 'test-group-guid');
+            "#,
+        )
+        .execute(&pool)
+        .await
+        .expect("legacy schema");
+
+        pool.close().await;
+
+        let Some(cache) = BitlinkCache::new("test-legacy", Some(cache_dir.path())).await else {
+            panic!("failed to open legacy cache");
+        };
+
+        // NOTE: the age of legacy entries starts with the migration
+        let hour = Some(Duration::from_secs(60 * 60));
+        assert_eq!(
+            Some(Cached::Fresh(link.clone())),
+            cache.lookup(&shorten, hour).await
+        );
+
+        let tags = ["campaign".to_string()];
+        let tagged = Shorten {
+            tags: Cow::Borrowed(&tags),
+            ..shorten
+        };
+
+        assert!(cache.set(&tagged, &link).await, "upgraded key");
+        assert_eq!(migrations::LATEST, cache.schema_version().await.unwrap());
+    }
+
+    #[rstest]
+    #[tokio::test]
+    async fn migrate_schema(cache_dir: TempDir) {
+        let Some(cache) = BitlinkCache::connect("test-migrate", Some(cache_dir.path())).await
+        else {
+            panic!("failed to connect to database");
+        };
+
+        assert_eq!(0, cache.schema_version().await.unwrap());
+        let pending = cache.pending_migrations().await.unwrap();
+        assert_eq!(migrations::MIGRATIONS.len(), pending.len());
+
+        let applied = cache.migrate().await.unwrap();
+        assert_eq!(pending.len(), applied.len());
+
+        assert_eq!(migrations::LATEST, cache.schema_version().await.unwrap());
+        assert!(cache.pending_migrations().await.unwrap().is_empty());
+        assert!(cache.migrate().await.unwrap().is_empty(), "idempotent");
+
+        // NOTE: pretend the database has been migrated by a newer version
+        sqlx::query("UPDATE metadata SET value = $1 WHERE key = 'schema_version'")
+            .bind((migrations::LATEST + 1).to_string())
+            .execute(&cache.pool)
+            .await
+            .expect("newer schema version");
+
+        match cache.pending_migrations().await {
+            Err(Error::UnsupportedSchema { version, supported }) => {
+                assert_eq!(migrations::LATEST + 1, version);
+                assert_eq!(migrations::LATEST, supported);
+            }
+            result => panic!("expected unsupported schema, got: {result:?}"),
+        }
+
+        let cache = BitlinkCache::new("test-migrate", Some(cache_dir.path())).await;
+        assert!(cache.is_none(), "newer database must not be opened");
+    }
+
+    #[rstest]
+    #[tokio::test]
+    async fn set_find_groups(#[future(awt)] cache: BitlinkCache) {
+        let group = |guid: &str, name: &str| Group {
+            guid: guid.to_string(),
+            name: name.to_string(),
+            organization_guid: Some("test-org-guid".to_string()),
+            is_active: true,
+            role: None,
+        };
+
+        let groups = [group("g1", "a"), group("g2", "b"), group("g3", "b")];
+        assert!(cache.set_groups(&groups).await);
+
+        // renamed group replaces the original entry
+        assert!(cache.set_groups(&[group("g1", "b")]).await);
+
+        assert!(cache.find_groups("a").await.is_empty());
+        assert_eq!(
+            vec![group("g1", "b"), group("g2", "b"), group("g3", "b")],
+            cache.find_groups("b").await
+        );
+
+        // replacing drops the groups that are gone
+        assert!(
+            cache
+                .replace_groups(&[group("g2", "b"), group("g4", "a")])
+                .await
+        );
+
+        assert_eq!(vec![group("g4", "a")], cache.find_groups("a").await);
+        assert_eq!(vec![group("g2", "b")], cache.find_groups("b").await);
+    }
+
+    #[rstest]
+    #[tokio::test]
+    async fn get_set_value(#[future(awt)] cache: BitlinkCache) {
+        assert!(cache.get_value("key", None).await.is_none());
+
+        assert!(cache.set_value("key", "old").await);
+        assert!(cache.set_value("key", "new").await);
+
+        let hour = Duration::from_secs(60 * 60);
+        assert_eq!(
+            Some("new"),
+            cache.get_value("key", Some(hour)).await.as_deref()
+        );
+
+        sqlx::query("UPDATE kv SET updated_at = updated_at - 7200 WHERE key = 'key'")
+            .execute(&cache.pool)
+            .await
+            .expect("backdate value");
+
+        assert!(cache.replace_value("key", "newer").await);
+        assert!(
+            cache.get_value("key", Some(hour)).await.is_none(),
+            "expired"
+        );
+        assert_eq!(Some("newer"), cache.get_value("key", None).await.as_deref());
+
+        assert!(!cache.replace_value("other", "value").await, "missing key");
+    }
+
+    #[rstest]
+    #[tokio::test]
+    async fn manage_entries(
+        #[future(awt)] cache: BitlinkCache,
+        shorten: Shorten<'static>,
+        link: Bitlink,
+    ) {
+        let other = Shorten {
+            long_url: "https://example.com/other".parse().unwrap(),
+            domain: None,
+            group_guid: shorten.group_guid.clone(),
+            title: None,
+            tags: Cow::Borrowed(&[]),
+            deeplinks: Cow::Borrowed(&[]),
+            keyword: None,
+        };
+
+        let other_link = Bitlink {
+            link: "https://bit.ly/other".parse().unwrap(),
+            id: "other-bitlink-id".to_string(),
+            long_url: other.long_url.clone(),
+        };
+
+        assert!(cache.set(&shorten, &link).await);
+        assert!(cache.set(&other, &other_link).await);
+        assert!(cache.set_value("key", "value").await);
+
+        let entry = |link: &Bitlink, domain: Option<&str>| CacheEntry {
+            id: link.id.clone(),
+            link: link.link.clone(),
+            long_url: link.long_url.clone(),
+            domain: domain.map(str::to_string),
+            group_guid: "test-group-guid".to_string(),
+        };
+
+        let all = cache.entries(&CacheFilter::default()).await.unwrap();
+        assert_eq!(
+            vec![entry(&link, Some("bit.ly")), entry(&other_link, None)],
+            all
+        );
+
+        let filter = CacheFilter {
+            pattern: Some("*/other".to_string()),
+            ..CacheFilter::default()
+        };
+        let found = cache.entries(&filter).await.unwrap();
+        assert_eq!(vec![entry(&other_link, None)], found);
+
+        let filter = CacheFilter {
+            domain: Some("bit.ly".to_string()),
+            group_guid: Some("test-group-guid".to_string()),
+            ..CacheFilter::default()
+        };
+        let found = cache.entries(&filter).await.unwrap();
+        assert_eq!(vec![entry(&link, Some("bit.ly"))], found);
+
+        let filter = CacheFilter {
+            group: Some("unknown".to_string()),
+            ..CacheFilter::default()
+        };
+        assert!(cache.entries(&filter).await.unwrap().is_empty());
+
+        let stats = cache.stats().await.unwrap();
+        assert_eq!(2, stats.bitlinks);
+        assert_eq!(1, stats.values);
+        assert!(stats.size > 0, "empty database file");
+        assert_eq!(2, stats.domains.len());
+
+        // by link
+        let removed = cache.remove(&link.link).await.unwrap();
+        assert_eq!(vec![entry(&link, Some("bit.ly"))], removed);
+        assert!(cache.get(&shorten).await.is_none());
+
+        // by long URL
+        let removed = cache.remove(&other.long_url).await.unwrap();
+        assert_eq!(vec![entry(&other_link, None)], removed);
+        assert!(cache.remove(&other.long_url).await.unwrap().is_empty());
+
+        assert!(cache.set(&shorten, &link).await);
+        assert_eq!(1, cache.clear().await.unwrap());
+        assert!(cache.get_value("key", None).await.is_none());
+        assert_eq!(0, cache.stats().await.unwrap().bitlinks);
+
+        cache.vacuum().await.expect("vacuumed database");
+    }
+
+    #[rstest]
+    #[case::skip(ConflictPolicy::Skip, Some(ImportSummary { imported: 1, replaced: 0, skipped: 2 }))]
+    #[case::overwrite(
+        ConflictPolicy::Overwrite,
+        Some(ImportSummary { imported: 1, replaced: 1, skipped: 1 })
+    )]
+    #[case::fail(ConflictPolicy::Fail, None)]
+    #[tokio::test]
+    async fn export_import_entries(
+        #[future(awt)] cache: BitlinkCache,
+        shorten: Shorten<'static>,
+        link: Bitlink,
+        #[case] policy: ConflictPolicy,
+        #[case] expected: Option<ImportSummary>,
+    ) {
+        assert!(cache.set(&shorten, &link).await);
+
+        let tags = ["campaign".to_string()];
+        let tagged = Shorten {
+            tags: Cow::Borrowed(&tags),
+            ..shorten
+        };
+        assert!(cache.set(&tagged, &link).await);
+
+        // NOTE: only plain bitlinks are exported
+        let [cached] = cache
+            .export()
+            .await
+            .unwrap()
+            .try_into()
+            .expect("single entry");
+        assert_eq!(link.id, cached.id);
+
+        let entry = |id: &str, link: &str, long_url: &str| CacheEntry {
+            id: id.to_string(),
+            link: link.parse().unwrap(),
+            long_url: long_url.parse().unwrap(),
+            ..cached.clone()
+        };
+
+        let entries = [
+            // unchanged
+            cached.clone(),
+            // new
+            entry(
+                "bit.ly/new",
+                "https://bit.ly/new",
+                "https://example.com/new",
+            ),
+            // conflict on the key (a different bitlink for the same long URL)
+            entry("bit.ly/dup", "https://bit.ly/dup", cached.long_url.as_str()),
+        ];
+
+        match (cache.import(&entries, policy).await, expected) {
+            (Ok(actual), Some(expected)) => assert_eq!(expected, actual),
+            (
+                Err(Error::ImportConflict {
+                    cached: conflict, ..
+                }),
+                None,
+            ) => {
+                assert_eq!(cached.link.as_str(), conflict);
+                let entries = cache.export().await.unwrap();
+                assert_eq!(vec![cached], entries, "failed import must be rolled back");
+                return;
+            }
+            (result, _) => panic!("unexpected import result: {result:?}"),
+        }
+
+        let expected = match policy {
+            ConflictPolicy::Overwrite => "https://bit.ly/dup",
+            _ => "https://bit.ly/4ePsyXN",
+        };
+
+        let link = cache
+            .get(&shorten_of(&cached))
+            .await
+            .expect("cached bitlink");
+        assert_eq!(expected, link.link.as_str());
+    }
+
+    fn shorten_of(entry: &CacheEntry) -> Shorten<'_> {
+        Shorten {
+            long_url: entry.long_url.clone(),
+            domain: entry.domain.as_deref().map(Cow::Borrowed),
+            group_guid: Cow::Borrowed(&entry.group_guid),
+            title: None,
+            tags: Cow::Borrowed(&[]),
+            deeplinks: Cow::Borrowed(&[]),
+            keyword: None,
+        }
+    }
+
     #[rstest]
     #[tokio::test]
     async fn disable_cache() {
@@ -0,0 +1,340 @@
+use std::collections::{HashMap, VecDeque};
+use std::path::{Path, PathBuf};
+use std::sync::Mutex;
+use std::sync::atomic::{AtomicUsize, Ordering};
+
+use reqwest::StatusCode;
+use reqwest::header::{AUTHORIZATION, HeaderMap, HeaderName, HeaderValue};
+use serde::{Deserialize, Serialize};
+use tokio::sync::OnceCell;
+use tracing::{debug, instrument};
+use url::Url;
+
+use crate::error::{Error, Result};
+
+/// Value of recorded headers with credentials
+const REDACTED: &str = "[REDACTED]";
+
+/// Whether API interactions are recorded into or replayed from a cassette directory
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub enum CassetteMode {
+    /// Save each request and its response to the directory (see `--record`)
+    Record(PathBuf),
+    /// Serve responses recorded in the directory instead of using the network (see `--replay`)
+    Replay(PathBuf),
+}
+
+/// Recorded API interactions (request/response pairs), each stored as a JSON file in a directory
+///
+/// Recorded requests are matched by their method, URL and body. Repeated requests are served the
+/// recorded responses in the order they were recorded (e.g., a `429` followed by a retry), the last
+/// one being served to any further repetitions.
+#[derive(Debug)]
+pub struct Cassette {
+    dir: PathBuf,
+    replay: bool,
+    /// Number of interactions recorded so far (including those found in the directory)
+    recorded: AtomicUsize,
+    /// Interactions to replay, loaded from the directory on first use
+    interactions: OnceCell<Mutex<HashMap<Key, VecDeque<Response>>>>,
+}
+
+/// Key matching replayed requests: method, URL and body
+type Key = (String, String, Option<String>);
+
+#[derive(Debug, Deserialize, Serialize)]
+struct Interaction {
+    request: Request,
+    response: Response,
+}
+
+#[derive(Debug, Deserialize, Serialize)]
+struct Request {
+    method: String,
+    url: Url,
+    headers: Vec<(String, String)>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    body: Option<String>,
+}
+
+#[derive(Clone, Debug, Deserialize, Serialize)]
+struct Response {
+    status: u16,
+    headers: Vec<(String, String)>,
+    body: String,
+}
+
+impl Request {
+    fn new(req: &reqwest::Request) -> Self {
+        let body = req
+            .body()
+            .and_then(reqwest::Body::as_bytes)
+            .map(|body| String::from_utf8_lossy(body).into_owned());
+
+        Self {
+            method: req.method().to_string(),
+            url: req.url().clone(),
+            headers: headers(req.headers()),
+            body,
+        }
+    }
+
+    #[inline]
+    fn key(&self) -> Key {
+        (self.method.clone(), self.url.to_string(), self.body.clone())
+    }
+}
+
+impl Response {
+    fn into_response(self) -> Result<reqwest::Response> {
+        let status = StatusCode::from_u16(self.status)
+            .map_err(|error| Error::Cassette(format!("invalid recorded status: {error}")))?;
+
+        let mut resp = http::Response::new(self.body);
+        *resp.status_mut() = status;
+
+        for (name, value) in self.headers {
+            let (Ok(name), Ok(value)) = (HeaderName::try_from(name), HeaderValue::try_from(value))
+            else {
+                continue;
+            };
+            resp.headers_mut().append(name, value);
+        }
+
+        Ok(reqwest::Response::from(resp))
+    }
+}
+
+/// Collect headers as name/value pairs, redacting credentials
+fn headers(headers: &HeaderMap) -> Vec<(String, String)> {
+    headers
+        .iter()
+        .map(|(name, value)| {
+            let value = if name == AUTHORIZATION {
+                REDACTED.to_string()
+            } else {
+                String::from_utf8_lossy(value.as_bytes()).into_owned()
+            };
+            (name.to_string(), value)
+        })
+        .collect()
+}
+
+impl Cassette {
+    pub fn new(mode: CassetteMode) -> Self {
+        let (dir, replay) = match mode {
+            CassetteMode::Record(dir) => (dir, false),
+            CassetteMode::Replay(dir) => (dir, true),
+        };
+
+        // NOTE: recording into an existing cassette continues its numbering
+        let recorded = interaction_files(&dir).map_or(0, |files| files.len());
+
+        Self {
+            dir,
+            replay,
+            recorded: AtomicUsize::new(recorded),
+            interactions: OnceCell::new(),
+        }
+    }
+
+    /// Execute given request, either replaying it or sending it and recording the interaction
+    pub async fn execute(
+        &self,
+        http: &reqwest::Client,
+        req: reqwest::Request,
+    ) -> Result<reqwest::Response> {
+        if self.replay {
+            self.replay(&req).await
+        } else {
+            self.record(http, req).await
+        }
+    }
+
+    #[instrument(level = "debug", skip_all, fields(method = %req.method(), url = %req.url()))]
+    async fn replay(&self, req: &reqwest::Request) -> Result<reqwest::Response> {
+        let interactions = self
+            .interactions
+            .get_or_try_init(|| async { self.load().map(Mutex::new) })
+            .await?;
+
+        let request = Request::new(req);
+
+        let response = {
+            let mut interactions = interactions.lock().expect("cassette lock");
+            interactions
+                .get_mut(&request.key())
+                .and_then(|responses| match responses.len() {
+                    0 => None,
+                    1 => responses.front().cloned(),
+                    _ => responses.pop_front(),
+                })
+        };
+
+        let Some(response) = response else {
+            return Err(Error::NotRecorded {
+                method: request.method,
+                url: request.url,
+            });
+        };
+
+        debug!(status = response.status, "replaying response");
+
+        response.into_response()
+    }
+
+    #[instrument(level = "debug", skip_all, fields(method = %req.method(), url = %req.url()))]
+    async fn record(
+        &self,
+        http: &reqwest::Client,
+        req: reqwest::Request,
+    ) -> Result<reqwest::Response> {
+        let request = Request::new(&req);
+
+        let resp = http.execute(req).await?;
+
+        let status = resp.status();
+        let headers = headers(resp.headers());
+        let body = resp.bytes().await?;
+
+        let response = Response {
+            status: status.as_u16(),
+            headers,
+            body: String::from_utf8_lossy(&body).into_owned(),
+        };
+
+        let interaction = Interaction { request, response };
+
+        let seq = self.recorded.fetch_add(1, Ordering::AcqRel);
+        let path = self.dir.join(format!("{seq:05}.json"));
+
+        debug!(?path, %status, "recording interaction");
+
+        std::fs::create_dir_all(&self.dir)?;
+        std::fs::write(&path, serde_json::to_vec_pretty(&interaction)?)?;
+
+        interaction.response.into_response()
+    }
+
+    /// Load all the recorded interactions in the order they were recorded
+    fn load(&self) -> Result<HashMap<Key, VecDeque<Response>>> {
+        let mut interactions = HashMap::<_, VecDeque<_>>::new();
+
+        for path in interaction_files(&self.dir)? {
+            let Interaction { request, response } = serde_json::from_slice(&std::fs::read(&path)?)?;
+            interactions
+                .entry(request.key())
+                .or_default()
+                .push_back(response);
+        }
+
+        debug!(dir = ?self.dir, "loaded cassette");
+
+        Ok(interactions)
+    }
+}
+
+/// Paths of the interactions recorded in given directory (sorted in the recording order)
+fn interaction_files(dir: &Path) -> std::io::Result<Vec<PathBuf>> {
+    let mut files = std::fs::read_dir(dir)?
+        .map(|entry| entry.map(|entry| entry.path()))
+        .filter(|path| {
+            path.as_ref().map_or(true, |path| {
+                path.extension().is_some_and(|ext| ext == "json")
+            })
+        })
+        .collect::<std::io::Result<Vec<_>>>()?;
+
+    files.sort();
+
+    Ok(files)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use rstest::*;
+
+    use tempfile::TempDir;
+    use wiremock::matchers::{method, path};
+    use wiremock::{Mock, MockServer, ResponseTemplate};
+
+    fn request(http: &reqwest::Client, server: &str) -> reqwest::Request {
+        http.post(format!("{server}/v4/shorten"))
+            .bearer_auth("secret-token")
+            .json(&serde_json::json!({ "long_url": "https://example.com" }))
+            .build()
+            .expect("valid request")
+    }
+
+    #[rstest]
+    #[tokio::test]
+    async fn record_and_replay() {
+        let server = MockServer::start().await;
+        let dir = TempDir::new().expect("cassette directory");
+
+        Mock::given(method("POST"))
+            .and(path("v4/shorten"))
+            .respond_with(ResponseTemplate::new(StatusCode::TOO_MANY_REQUESTS))
+            .up_to_n_times(1)
+            .with_priority(1)
+            .mount(&server)
+            .await;
+
+        Mock::given(method("POST"))
+            .and(path("v4/shorten"))
+            .respond_with(
+                ResponseTemplate::new(StatusCode::OK)
+                    .set_body_raw(r#"{"link": "https://bit.ly/abc"}"#, "application/json"),
+            )
+            .mount(&server)
+            .await;
+
+        let http = reqwest::Client::new();
+
+        let recorder = Cassette::new(CassetteMode::Record(dir.path().to_path_buf()));
+        for expected in [StatusCode::TOO_MANY_REQUESTS, StatusCode::OK] {
+            let resp = recorder
+                .execute(&http, request(&http, &server.uri()))
+                .await
+                .expect("recorded response");
+            assert_eq!(expected, resp.status());
+        }
+
+        let recorded = std::fs::read_to_string(dir.path().join("00000.json")).unwrap();
+        assert!(!recorded.contains("secret-token"), "{recorded}");
+        assert!(recorded.contains(REDACTED), "{recorded}");
+
+        // NOTE: nothing is sent to the server, so it can go away
+        let server_uri = server.uri();
+        drop(server);
+
+        let player = Cassette::new(CassetteMode::Replay(dir.path().to_path_buf()));
+        for expected in [
+            StatusCode::TOO_MANY_REQUESTS,
+            StatusCode::OK,
+            StatusCode::OK,
+        ] {
+            let resp = player
+                .execute(&http, request(&http, &server_uri))
+                .await
+                .expect("replayed response");
+            assert_eq!(expected, resp.status());
+        }
+
+        let resp = player
+            .execute(&http, request(&http, &server_uri))
+            .await
+            .unwrap();
+        assert_eq!(
+            r#"{"link": "https://bit.ly/abc"}"#,
+            resp.text().await.unwrap()
+        );
+
+        let other = http.get(format!("{server_uri}/v4/user")).build().unwrap();
+        match player.execute(&http, other).await {
+            Err(Error::NotRecorded { method, .. }) => assert_eq!("GET", method),
+            result => panic!("expected unrecorded request, got: {result:?}"),
+        }
+    }
+}
@@ -1,11 +1,19 @@
 use std::borrow::Cow;
-use std::num::NonZeroUsize;
+use std::num::{NonZeroU32, NonZeroUsize};
 use std::path::{Path, PathBuf};
+use std::time::Duration;
 
 use clap::builder::ArgPredicate;
-use clap::{Args, Parser, Subcommand, ValueEnum, ValueHint};
+use clap::error::ErrorKind;
+use clap::{ArgGroup, Args, CommandFactory as _, Parser, Subcommand, ValueEnum, ValueHint};
+use serde::{Deserialize, Serialize};
 use url::Url;
 
+use crate::api::{
+    BitlinkChanges, BitlinkFields, BitlinkRef, Deeplink, ListFilter, Metric, MetricsQuery,
+};
+use crate::cache::CacheFilter;
+use crate::cassette::CassetteMode;
 use crate::config::{APP, ConfigError, Options};
 
 #[derive(Debug, Parser)]
@@ -23,7 +31,7 @@ pub struct Cli {
     /// Alternative path to the cache directory
     ///
     /// If set to an empty path, then caching will be disabled.
-    #[arg(long, env = "BITCLI_CACHE_DIR", value_hint = ValueHint::DirPath)]
+    #[arg(long, global = true, env = "BITCLI_CACHE_DIR", value_hint = ValueHint::DirPath)]
     cache_dir: Option<PathBuf>,
 
     /// Explicitly disable local cache for this command invocation
@@ -37,6 +45,18 @@ pub struct Cli {
     )]
     no_cache: bool,
 
+    /// Age after which cached bitlinks are revalidated with the API, e.g. `24h` (`0` disables it)
+    ///
+    /// Stale bitlinks that have been changed or deleted since are dropped from the cache, while
+    /// those that can't be revalidated (e.g., when the API is unreachable) are still used.
+    #[arg(
+        long,
+        value_name = "DURATION",
+        value_parser = parse_duration,
+        env = "BITCLI_CACHE_MAX_AGE"
+    )]
+    cache_max_age: Option<Duration>,
+
     /// Enabling the offline mode will prevent any API requests
     ///
     /// Under this mode, any command will only rely on the local cache, therefore this flag cannot
@@ -51,12 +71,107 @@ pub struct Cli {
     )]
     offline: bool,
 
+    /// Maximum number of API requests per second, shared by all the requests in flight
+    ///
+    /// If unspecified, the rate is derived from the platform limits of the user (at most 10 and
+    /// lowered once the remaining monthly quota runs low). Setting this to 0 disables the rate
+    /// limiting.
+    #[arg(long, value_name = "N", env = "BITCLI_RATE_LIMIT")]
+    rate_limit: Option<u32>,
+
+    /// Timeout of a single API request attempt, e.g. `30s` or `500ms` (`0` disables the timeout)
+    #[arg(long, value_name = "DURATION", value_parser = parse_duration, env = "BITCLI_TIMEOUT")]
+    timeout: Option<Duration>,
+
+    /// Timeout of establishing a connection to the API (`0` disables the timeout)
+    #[arg(
+        long,
+        value_name = "DURATION",
+        value_parser = parse_duration,
+        env = "BITCLI_CONNECT_TIMEOUT"
+    )]
+    connect_timeout: Option<Duration>,
+
+    /// Overall time limit of the whole command, e.g. `10m`
+    ///
+    /// When the deadline passes, requests in flight are cancelled, results received so far are
+    /// kept (printed and cached) and the program exits with status 124. Similarly, an interrupt
+    /// (Ctrl-C) exits with status 130.
+    #[arg(long, value_name = "DURATION", value_parser = parse_duration, env = "BITCLI_DEADLINE")]
+    deadline: Option<Duration>,
+
+    /// Maximum number of attempts of each API request that failed with a transient error
+    ///
+    /// Transient errors are rate limiting (429), server errors (500, 502, 503, 504) and connection
+    /// failures. Requests that add custom bitlinks or update bitlinks are only retried when rate
+    /// limited or when the connection fails. Setting this to 1 disables retries.
+    #[arg(long, value_name = "N", env = "BITCLI_MAX_ATTEMPTS")]
+    max_attempts: Option<NonZeroU32>,
+
+    /// Delay (in milliseconds) before the first retry, which doubles with each next attempt
+    ///
+    /// Note that a delay requested by the server via the `Retry-After` header takes precedence.
+    #[arg(long, value_name = "MS", env = "BITCLI_RETRY_DELAY")]
+    retry_delay: Option<u64>,
+
+    /// Do not randomize delays between retries
+    #[arg(long, default_value_t = false, env = "BITCLI_NO_RETRY_JITTER")]
+    no_retry_jitter: bool,
+
+    /// Record every API request and its response as a JSON file in given directory
+    ///
+    /// The bearer token is redacted from the recorded requests. Recording into an existing
+    /// directory appends to the interactions recorded there before.
+    #[arg(
+        long,
+        value_name = "DIR",
+        conflicts_with = "replay",
+        env = "BITCLI_RECORD",
+        value_hint = ValueHint::DirPath
+    )]
+    record: Option<PathBuf>,
+
+    /// Serve API responses recorded via `--record` in given directory instead of the network
+    ///
+    /// Requests are matched by their method, URL and body. A request that has not been recorded
+    /// fails.
+    #[arg(long, value_name = "DIR", env = "BITCLI_REPLAY", value_hint = ValueHint::DirPath)]
+    replay: Option<PathBuf>,
+
     // emulate default (sub)command
     #[clap(flatten)]
     shorten: ShortenArgs,
 }
 
 impl Cli {
+    /// Parse and validate program arguments, exiting on error
+    pub fn parse_args() -> Self {
+        let cli = Self::parse();
+
+        let shorten = match cli.command {
+            Some(Command::Shorten(ref args)) => args,
+            Some(_) => return cli,
+            None => &cli.shorten,
+        };
+
+        if shorten.keyword.is_some() && shorten.urls.len() != 1 {
+            Self::command()
+                .error(
+                    ErrorKind::ArgumentConflict,
+                    "a custom '--keyword' can be used with exactly one URL",
+                )
+                .exit();
+        }
+
+        cli
+    }
+
+    /// Overall time limit of the command (if any)
+    #[inline]
+    pub fn deadline(&self) -> Option<Duration> {
+        self.deadline
+    }
+
     /// Get the location of the config file
     ///
     /// Note that if `--config-file` has not been specified, then this will look for `config.toml`
@@ -84,8 +199,26 @@ impl From<&Cli> for Options {
             ops.cache_dir.clone_from(&cli.cache_dir);
         }
 
+        ops.cache_max_age = cli.cache_max_age;
+
         ops.offline = Some(cli.offline);
 
+        ops.rate_limit = cli.rate_limit;
+        ops.request_timeout = cli.timeout;
+        ops.connect_timeout = cli.connect_timeout;
+        ops.max_attempts = cli.max_attempts;
+        ops.retry_delay = cli.retry_delay;
+
+        if cli.no_retry_jitter {
+            ops.retry_jitter = Some(false);
+        }
+
+        ops.cassette = match (&cli.record, &cli.replay) {
+            (Some(dir), _) => Some(CassetteMode::Record(dir.clone())),
+            (None, Some(dir)) => Some(CassetteMode::Replay(dir.clone())),
+            (None, None) => None,
+        };
+
         ops
     }
 }
@@ -94,6 +227,33 @@ impl From<&Cli> for Options {
 pub enum Command {
     #[command(about = "Shorten URL and print the result to the output (default)")]
     Shorten(ShortenArgs),
+
+    #[command(about = "Expand bitlink and print the original long URL to the output")]
+    Expand(ExpandArgs),
+
+    #[command(about = "Fetch and print all the metadata of given bitlinks")]
+    Info(InfoArgs),
+
+    #[command(about = "Update existing bitlinks and print their new metadata")]
+    Update(UpdateArgs),
+
+    #[command(subcommand, about = "Fetch engagement metrics of given bitlinks")]
+    Stats(StatsCommand),
+
+    #[command(about = "List and search bitlinks of a group")]
+    List(ListArgs),
+
+    #[command(subcommand, about = "Manage groups of the authenticated user")]
+    Groups(GroupsCommand),
+
+    #[command(subcommand, about = "Manage organizations of the authenticated user")]
+    Orgs(OrgsCommand),
+
+    #[command(about = "Resume a shortening job started with '--job' from where it stopped")]
+    Resume(ResumeArgs),
+
+    #[command(subcommand, about = "Inspect and manage the local cache")]
+    Cache(CacheCommand),
 }
 
 impl From<Cli> for Command {
@@ -111,13 +271,38 @@ impl From<&Command> for Options {
             Command::Shorten(ShortenArgs {
                 domain,
                 group_guid,
-                max_concurrent,
+                group,
+                batch,
                 ..
             }) => {
-                ops.max_concurrent = NonZeroUsize::new(*max_concurrent as usize);
+                batch.apply(&mut ops);
                 ops.domain.clone_from(domain);
-                ops.group_guid.clone_from(group_guid);
+                ops.set_group(group_guid, group);
+            }
+
+            Command::Expand(ExpandArgs { batch, .. })
+            | Command::Info(InfoArgs { batch, .. })
+            | Command::Update(UpdateArgs { batch, .. }) => {
+                batch.apply(&mut ops);
+            }
+
+            Command::Stats(stats) => {
+                stats.metrics().batch.apply(&mut ops);
+            }
+
+            Command::List(ListArgs {
+                group_guid, group, ..
+            }) => {
+                ops.set_group(group_guid, group);
+            }
+
+            Command::Resume(ResumeArgs { max_concurrent, .. }) => {
+                if let Some(max_concurrent) = max_concurrent {
+                    max_concurrent.apply(&mut ops);
+                }
             }
+
+            Command::Groups(_) | Command::Orgs(_) | Command::Cache(_) => {}
         }
 
         ops
@@ -132,23 +317,8 @@ pub struct ShortenArgs {
     #[arg(num_args(1..))]
     pub urls: Vec<Url>,
 
-    /// Maximum number of API requests in flight
-    #[arg(
-        long,
-        default_value_t = 16,
-        value_parser = clap::value_parser!(u64).range(1..),
-        env = "BITCLI_MAX_CONCURRENT",
-    )]
-    pub max_concurrent: u64,
-
-    /// The type of the output ordering
-    ///
-    ///  - ordered: individual outputs follow the input order
-    ///
-    ///  - unordered: outputs follow an arbitrary order, but are printed together with
-    ///    corresponding input URL
-    #[arg(long, default_value_t, value_enum, env = "BITCLI_ORDERING")]
-    pub ordering: Ordering,
+    #[command(flatten)]
+    pub batch: BatchArgs,
 
     /// The domain to create bitlinks under
     #[arg(short, long, env = "BITCLI_DOMAIN")]
@@ -165,11 +335,795 @@ pub struct ShortenArgs {
     ///  3. If still unknown, fetch current default group GUID for the authenticated user
     #[arg(short, long, env = "BITCLI_GROUP_GUID")]
     pub group_guid: Option<String>,
+
+    /// The name of the group to create bitlinks under (takes precedence over `--group-guid`)
+    ///
+    /// Group names are resolved to GUIDs through a locally cached list of user's groups.
+    #[arg(short = 'G', long, env = "BITCLI_GROUP")]
+    pub group: Option<String>,
+
+    /// The title of created bitlinks
+    #[arg(long)]
+    pub title: Option<String>,
+
+    /// Tag to attach to created bitlinks (can be repeated)
+    #[arg(long = "tag", value_name = "TAG")]
+    pub tags: Vec<String>,
+
+    #[command(flatten)]
+    pub deeplink: DeeplinkArgs,
+
+    /// Custom back-half of the bitlink (e.g., `launch` for `go.acme.co/launch`)
+    ///
+    /// The bitlink is created first and then the custom bitlink is attached to it under the same
+    /// domain, which usually has to be a branded one. Requires exactly one URL argument.
+    #[arg(short, long, requires = "urls")]
+    pub keyword: Option<String>,
+
+    /// Run as a named job, which journals processed inputs so that it can be resumed
+    ///
+    /// Inputs are acknowledged in the local database as their results are printed. If the job
+    /// stops for any reason, continue it with `bitcli resume NAME` given the same inputs.
+    #[arg(long, value_name = "NAME", conflicts_with = "keyword")]
+    pub job: Option<String>,
+
+    /// Print each distinct output just once, dropping repeated ones (e.g., of duplicate URLs)
+    #[arg(long, default_value_t = false, env = "BITCLI_UNIQUE")]
+    pub unique: bool,
+
+    /// Print whether each URL would be served from the cache or the requests that would be sent
+    /// to the API (including their exact payloads), without creating any bitlinks
+    #[arg(long, default_value_t = false, conflicts_with = "job")]
+    pub dry_run: bool,
 }
 
-#[derive(Clone, Copy, Debug, Default, ValueEnum)]
+impl From<&ShortenArgs> for BitlinkFields {
+    fn from(args: &ShortenArgs) -> Self {
+        Self {
+            title: args.title.clone(),
+            tags: args.tags.clone(),
+            deeplinks: Option::<Deeplink>::from(&args.deeplink)
+                .into_iter()
+                .collect(),
+            keyword: args.keyword.clone(),
+        }
+    }
+}
+
+#[derive(Args, Debug)]
+pub struct ResumeArgs {
+    /// The name of the job (see `shorten --job`)
+    pub name: String,
+
+    /// The same URLs the job was started with
+    ///
+    /// If none given as program arguments, then the application will try to read them from stdin.
+    /// Inputs that have been processed already are skipped, all the other settings are restored
+    /// from the job.
+    pub urls: Vec<Url>,
+
+    /// Maximum number of API requests in flight, or `auto` to adapt it to the API responses
+    #[arg(
+        long,
+        value_name = "N|auto",
+        value_parser = parse_max_concurrent,
+        env = "BITCLI_MAX_CONCURRENT",
+    )]
+    pub max_concurrent: Option<MaxConcurrent>,
+
+    /// Print how the remaining inputs would be processed, without creating any bitlinks
+    ///
+    /// Nothing is recorded in the job journal either.
+    #[arg(long, default_value_t = false)]
+    pub dry_run: bool,
+}
+
+/// Mobile app deep link to attach to created bitlinks
+#[derive(Args, Debug)]
+pub struct DeeplinkArgs {
+    /// The app ID of the deep link (e.g., `com.example.app`)
+    #[arg(long, requires = "deeplink_app_uri_path")]
+    pub deeplink_app_id: Option<String>,
+
+    /// The in-app path the deep link should open (e.g., `/products/42`)
+    #[arg(long, requires = "deeplink_app_id")]
+    pub deeplink_app_uri_path: Option<String>,
+
+    /// Where to send users who don't have the app installed
+    #[arg(long, requires = "deeplink_app_id")]
+    pub deeplink_install_url: Option<String>,
+
+    /// How to treat users who don't have the app installed
+    #[arg(
+        long,
+        requires = "deeplink_app_id",
+        value_parser = ["no_install", "auto_install", "promote_install"],
+    )]
+    pub deeplink_install_type: Option<String>,
+}
+
+impl From<&DeeplinkArgs> for Option<Deeplink> {
+    fn from(args: &DeeplinkArgs) -> Self {
+        args.deeplink_app_id.as_ref().map(|app_id| Deeplink {
+            app_id: Some(app_id.clone()),
+            app_uri_path: args.deeplink_app_uri_path.clone(),
+            install_url: args.deeplink_install_url.clone(),
+            install_type: args.deeplink_install_type.clone(),
+            ..Default::default()
+        })
+    }
+}
+
+#[derive(Args, Debug)]
+pub struct ExpandArgs {
+    /// Bitlinks to expand (either full URLs or bitlink IDs such as `bit.ly/4ePsyXN`)
+    ///
+    /// If none given as program arguments, then the application will try to read them from stdin.
+    #[arg(num_args(1..))]
+    pub bitlinks: Vec<BitlinkRef>,
+
+    #[command(flatten)]
+    pub batch: BatchArgs,
+}
+
+#[derive(Args, Debug)]
+pub struct InfoArgs {
+    /// Bitlinks to inspect (either full URLs or bitlink IDs such as `bit.ly/4ePsyXN`)
+    ///
+    /// If none given as program arguments, then the application will try to read them from stdin.
+    #[arg(num_args(1..))]
+    pub bitlinks: Vec<BitlinkRef>,
+
+    #[command(flatten)]
+    pub batch: BatchArgs,
+
+    /// The format of the output
+    #[arg(short, long, default_value_t, value_enum, env = "BITCLI_FORMAT")]
+    pub format: Format,
+}
+
+#[derive(Args, Debug)]
+#[command(group(
+    ArgGroup::new("changes")
+        .required(true)
+        .multiple(true)
+        .args(["title", "archive", "unarchive", "add_tags", "remove_tags", "long_url"]),
+))]
+pub struct UpdateArgs {
+    /// Bitlinks to update (either full URLs or bitlink IDs such as `bit.ly/4ePsyXN`)
+    ///
+    /// If none given as program arguments, then the application will try to read them from stdin.
+    #[arg(num_args(1..))]
+    pub bitlinks: Vec<BitlinkRef>,
+
+    /// Set new title
+    #[arg(long)]
+    pub title: Option<String>,
+
+    /// Archive the bitlinks
+    #[arg(long, conflicts_with = "unarchive")]
+    pub archive: bool,
+
+    /// Restore archived bitlinks
+    #[arg(long)]
+    pub unarchive: bool,
+
+    /// Tag to add (can be repeated)
+    #[arg(long = "add-tag", value_name = "TAG")]
+    pub add_tags: Vec<String>,
+
+    /// Tag to remove (can be repeated)
+    #[arg(long = "remove-tag", value_name = "TAG")]
+    pub remove_tags: Vec<String>,
+
+    /// Change the destination URL (not available on all Bitly plans)
+    #[arg(long)]
+    pub long_url: Option<Url>,
+
+    /// Print the update requests that would be sent to the API (including their exact payloads),
+    /// without changing any bitlinks
+    #[arg(long, default_value_t = false)]
+    pub dry_run: bool,
+
+    #[command(flatten)]
+    pub batch: BatchArgs,
+
+    /// The format of the output
+    #[arg(short, long, default_value_t, value_enum, env = "BITCLI_FORMAT")]
+    pub format: Format,
+}
+
+impl From<&UpdateArgs> for BitlinkChanges {
+    fn from(args: &UpdateArgs) -> Self {
+        let archived = match (args.archive, args.unarchive) {
+            (true, _) => Some(true),
+            (_, true) => Some(false),
+            _ => None,
+        };
+
+        Self {
+            title: args.title.clone(),
+            archived,
+            add_tags: args.add_tags.clone(),
+            remove_tags: args.remove_tags.clone(),
+            long_url: args.long_url.clone(),
+        }
+    }
+}
+
+#[derive(Debug, Subcommand)]
+pub enum StatsCommand {
+    #[command(about = "Print the number of clicks per time unit (or in total with '--summary')")]
+    Clicks(ClicksArgs),
+
+    #[command(about = "Print the number of clicks per country")]
+    Countries(MetricsArgs),
+
+    #[command(about = "Print the number of clicks per city")]
+    Cities(MetricsArgs),
+
+    #[command(about = "Print the number of clicks per referrer")]
+    Referrers(MetricsArgs),
+
+    #[command(about = "Print the number of clicks per referring domain")]
+    ReferringDomains(MetricsArgs),
+
+    #[command(about = "Print the number of clicks per device type")]
+    Devices(MetricsArgs),
+}
+
+impl StatsCommand {
+    /// The metric (endpoint) requested by this command
+    pub fn metric(&self) -> Metric {
+        match self {
+            Self::Clicks(ClicksArgs { summary: false, .. }) => Metric::Clicks,
+            Self::Clicks(ClicksArgs { summary: true, .. }) => Metric::ClicksSummary,
+            Self::Countries(_) => Metric::Countries,
+            Self::Cities(_) => Metric::Cities,
+            Self::Referrers(_) => Metric::Referrers,
+            Self::ReferringDomains(_) => Metric::ReferringDomains,
+            Self::Devices(_) => Metric::Devices,
+        }
+    }
+
+    /// Options shared by all the metrics subcommands
+    pub fn metrics(&self) -> &MetricsArgs {
+        match self {
+            Self::Clicks(ClicksArgs { metrics, .. })
+            | Self::Countries(metrics)
+            | Self::Cities(metrics)
+            | Self::Referrers(metrics)
+            | Self::ReferringDomains(metrics)
+            | Self::Devices(metrics) => metrics,
+        }
+    }
+
+    #[inline]
+    pub fn into_metrics(self) -> MetricsArgs {
+        match self {
+            Self::Clicks(ClicksArgs { metrics, .. })
+            | Self::Countries(metrics)
+            | Self::Cities(metrics)
+            | Self::Referrers(metrics)
+            | Self::ReferringDomains(metrics)
+            | Self::Devices(metrics) => metrics,
+        }
+    }
+}
+
+#[derive(Args, Debug)]
+pub struct ClicksArgs {
+    /// Print just the total number of clicks over the whole period
+    #[arg(short, long)]
+    pub summary: bool,
+
+    #[command(flatten)]
+    pub metrics: MetricsArgs,
+}
+
+/// Options shared by all the metrics (sub)commands
+#[derive(Args, Debug)]
+pub struct MetricsArgs {
+    /// Bitlinks to inspect (either full URLs or bitlink IDs such as `bit.ly/4ePsyXN`)
+    ///
+    /// If none given as program arguments, then the application will try to read them from stdin.
+    #[arg(num_args(1..))]
+    pub bitlinks: Vec<BitlinkRef>,
+
+    /// The unit of time to aggregate metrics by
+    #[arg(long, default_value_t, value_enum)]
+    pub unit: TimeUnit,
+
+    /// The number of time units to query data for (-1 for all the available data)
+    #[arg(long, default_value_t = -1, allow_negative_numbers = true)]
+    pub units: i64,
+
+    /// ISO-8601 timestamp the time units are counted back from (defaults to now)
+    ///
+    /// For example: `2024-08-07T08:48:48+0000`
+    #[arg(long)]
+    pub unit_reference: Option<String>,
+
+    #[command(flatten)]
+    pub batch: BatchArgs,
+
+    /// The format of the output
+    #[arg(short, long, default_value_t, value_enum, env = "BITCLI_FORMAT")]
+    pub format: Format,
+}
+
+impl From<&MetricsArgs> for MetricsQuery {
+    #[inline]
+    fn from(args: &MetricsArgs) -> Self {
+        Self {
+            unit: args.unit,
+            units: args.units,
+            unit_reference: args.unit_reference.clone(),
+        }
+    }
+}
+
+#[derive(Args, Debug)]
+pub struct ListArgs {
+    /// Full-text search query (e.g., matching titles or URLs)
+    #[arg(short, long)]
+    pub query: Option<String>,
+
+    /// Only list bitlinks with this tag (can be repeated)
+    #[arg(long = "tag", value_name = "TAG")]
+    pub tags: Vec<String>,
+
+    /// Only list bitlinks created after this Unix timestamp
+    #[arg(long, value_name = "TIMESTAMP")]
+    pub created_after: Option<u64>,
+
+    /// Only list bitlinks created before this Unix timestamp
+    #[arg(long, value_name = "TIMESTAMP")]
+    pub created_before: Option<u64>,
+
+    /// Filter by the archived state
+    #[arg(long, default_value_t = Toggle::Off, value_enum)]
+    pub archived: Toggle,
+
+    /// Filter by the presence of custom bitlinks (keywords)
+    #[arg(long, default_value_t, value_enum)]
+    pub custom_bitlink: Toggle,
+
+    /// Only list bitlinks under this domain
+    #[arg(short, long)]
+    pub domain: Option<String>,
+
+    /// The group GUID to list bitlinks of
+    ///
+    /// Resolved the same way as for the `shorten` command if unspecified.
+    #[arg(short, long, env = "BITCLI_GROUP_GUID")]
+    pub group_guid: Option<String>,
+
+    /// The name of the group to list bitlinks of (takes precedence over `--group-guid`)
+    #[arg(short = 'G', long, env = "BITCLI_GROUP")]
+    pub group: Option<String>,
+
+    /// Stop after listing this many bitlinks
+    #[arg(short = 'n', long)]
+    pub limit: Option<usize>,
+
+    /// The number of bitlinks fetched per API request
+    #[arg(long, value_parser = clap::value_parser!(u32).range(1..))]
+    pub page_size: Option<u32>,
+
+    /// The format of the output
+    #[arg(short, long, default_value_t, value_enum, env = "BITCLI_FORMAT")]
+    pub format: Format,
+}
+
+impl From<&ListArgs> for ListFilter {
+    fn from(args: &ListArgs) -> Self {
+        Self {
+            query: args.query.clone(),
+            tags: args.tags.clone(),
+            created_after: args.created_after,
+            created_before: args.created_before,
+            archived: args.archived,
+            custom_bitlink: args.custom_bitlink,
+            domain: args.domain.clone(),
+            size: args.page_size,
+        }
+    }
+}
+
+#[derive(Debug, Subcommand)]
+pub enum GroupsCommand {
+    #[command(about = "List groups (and refresh the locally cached group names)")]
+    List(GroupsListArgs),
+}
+
+#[derive(Args, Debug)]
+pub struct GroupsListArgs {
+    /// Only list groups of this organization
+    #[arg(short, long)]
+    pub organization_guid: Option<String>,
+
+    /// The format of the output
+    #[arg(short, long, default_value_t, value_enum, env = "BITCLI_FORMAT")]
+    pub format: Format,
+}
+
+#[derive(Debug, Subcommand)]
+pub enum OrgsCommand {
+    #[command(about = "List organizations")]
+    List(OrgsListArgs),
+}
+
+#[derive(Args, Debug)]
+pub struct OrgsListArgs {
+    /// The format of the output
+    #[arg(short, long, default_value_t, value_enum, env = "BITCLI_FORMAT")]
+    pub format: Format,
+}
+
+#[derive(Debug, Subcommand)]
+pub enum CacheCommand {
+    #[command(about = "List cached bitlinks (as link and long URL pairs)")]
+    List(CacheListArgs),
+
+    #[command(about = "Show the number of cached entries and the size of the database")]
+    Stats(CacheStatsArgs),
+
+    #[command(about = "Remove cached bitlinks by their links or long URLs")]
+    Rm(CacheRmArgs),
+
+    #[command(about = "Remove all cached bitlinks, groups and values (jobs are kept)")]
+    Clear,
+
+    #[command(about = "Rebuild the database file to reclaim unused space")]
+    Vacuum,
+
+    #[command(about = "Export cached bitlinks, e.g. to share them with the team")]
+    Export(CacheExportArgs),
+
+    #[command(about = "Import bitlinks exported by 'bitcli cache export' into the cache")]
+    Import(CacheImportArgs),
+
+    #[command(about = "Apply pending migrations of the database schema")]
+    Migrate(CacheMigrateArgs),
+}
+
+#[derive(Args, Debug)]
+pub struct CacheListArgs {
+    /// Only list bitlinks under this domain
+    #[arg(short, long)]
+    pub domain: Option<String>,
+
+    /// Only list bitlinks of this group
+    #[arg(short, long)]
+    pub group_guid: Option<String>,
+
+    /// Only list bitlinks of groups with this name (see `bitcli groups list`)
+    #[arg(short = 'G', long, conflicts_with = "group_guid")]
+    pub group: Option<String>,
+
+    /// Only list bitlinks whose long URL or link matches this glob pattern (e.g., `*example.com*`)
+    #[arg(short, long, value_name = "PATTERN")]
+    pub url: Option<String>,
+
+    /// Stop after listing this many bitlinks
+    #[arg(short = 'n', long)]
+    pub limit: Option<usize>,
+
+    /// The format of the output
+    #[arg(short, long, default_value_t, value_enum, env = "BITCLI_FORMAT")]
+    pub format: Format,
+}
+
+impl From<&CacheListArgs> for CacheFilter {
+    fn from(args: &CacheListArgs) -> Self {
+        Self {
+            domain: args.domain.clone(),
+            group_guid: args.group_guid.clone(),
+            group: args.group.clone(),
+            pattern: args.url.clone(),
+            limit: args.limit,
+        }
+    }
+}
+
+#[derive(Args, Debug)]
+pub struct CacheStatsArgs {
+    /// The format of the output
+    #[arg(short, long, default_value_t, value_enum, env = "BITCLI_FORMAT")]
+    pub format: Format,
+}
+
+#[derive(Args, Debug)]
+pub struct CacheExportArgs {
+    /// File to write the bitlinks to (stdout if unspecified)
+    #[arg(short, long, value_hint = ValueHint::FilePath)]
+    pub output: Option<PathBuf>,
+
+    /// The format of the exported bitlinks
+    #[arg(short, long, default_value_t, value_enum)]
+    pub format: ExchangeFormat,
+}
+
+#[derive(Args, Debug)]
+pub struct CacheImportArgs {
+    /// File to read the bitlinks from (stdin if unspecified)
+    #[arg(value_hint = ValueHint::FilePath)]
+    pub input: Option<PathBuf>,
+
+    /// The format of the imported bitlinks
+    #[arg(short, long, default_value_t, value_enum)]
+    pub format: ExchangeFormat,
+
+    /// How to resolve bitlinks that conflict with the cached ones
+    ///
+    /// A bitlink conflicts with a cached one if it has the same ID, or if it shortens the same
+    /// long URL under the same domain and group.
+    #[arg(long, default_value_t, value_enum)]
+    pub on_conflict: ConflictPolicy,
+}
+
+#[derive(Args, Debug)]
+pub struct CacheMigrateArgs {
+    /// Only list the pending migrations, exiting with status 1 if there are any
+    #[arg(long)]
+    pub check: bool,
+}
+
+#[derive(Args, Debug)]
+pub struct CacheRmArgs {
+    /// Bitlinks (full URLs or IDs such as `bit.ly/4ePsyXN`) or long URLs to remove
+    ///
+    /// If none given as program arguments, then the application will try to read them from stdin.
+    #[arg(num_args(1..))]
+    pub urls: Vec<BitlinkRef>,
+}
+
+/// Options controlling how a batch of inputs is processed
+#[derive(Args, Debug)]
+pub struct BatchArgs {
+    /// Maximum number of API requests in flight, or `auto` to adapt it to the API responses
+    ///
+    /// The `auto` concurrency grows while requests succeed and backs off when the API is
+    /// overloaded (`429` or `503`), staying within the configured `max_concurrent_ceiling`.
+    /// If unspecified, the configured `max_concurrent` and `adaptive_concurrency` apply (by
+    /// default, 16 requests in flight).
+    #[arg(
+        long,
+        value_name = "N|auto",
+        value_parser = parse_max_concurrent,
+        env = "BITCLI_MAX_CONCURRENT",
+    )]
+    pub max_concurrent: Option<MaxConcurrent>,
+
+    /// The type of the output ordering
+    ///
+    ///  - ordered: individual outputs follow the input order
+    ///
+    ///  - unordered: outputs follow an arbitrary order, but are printed together with
+    ///    corresponding input
+    #[arg(long, default_value_t, value_enum, env = "BITCLI_ORDERING")]
+    pub ordering: Ordering,
+
+    /// Keep processing remaining inputs when some of them fail
+    ///
+    /// Failures are reported to stderr (or to `--errors-file`) and the exit status tells apart
+    /// whether all (0), just some (3) or none (1) of the inputs succeeded.
+    #[arg(long, default_value_t = false, env = "BITCLI_KEEP_GOING")]
+    pub keep_going: bool,
+
+    /// Write failures to given file (JSON lines with the input and error kind) instead of stderr
+    #[arg(long, requires = "keep_going", value_hint = ValueHint::FilePath)]
+    pub errors_file: Option<PathBuf>,
+}
+
+impl BatchArgs {
+    /// Override the configured concurrency, but only if it's been given explicitly
+    #[inline]
+    fn apply(&self, ops: &mut Options) {
+        if let Some(max_concurrent) = self.max_concurrent {
+            max_concurrent.apply(ops);
+        }
+    }
+}
+
+/// Limit of API requests in flight
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum MaxConcurrent {
+    /// Fixed number of requests in flight
+    Fixed(NonZeroUsize),
+    /// Number of requests in flight adapted to the API responses
+    Auto,
+}
+
+impl MaxConcurrent {
+    fn apply(self, ops: &mut Options) {
+        match self {
+            Self::Fixed(max_concurrent) => {
+                ops.max_concurrent = Some(max_concurrent);
+                ops.adaptive_concurrency = Some(false);
+            }
+            Self::Auto => ops.adaptive_concurrency = Some(true),
+        }
+    }
+}
+
+#[derive(Clone, Copy, Debug, Default, ValueEnum, Deserialize, Serialize)]
+#[serde(rename_all = "lowercase")]
 pub enum Ordering {
     #[default]
     Ordered,
     Unordered,
 }
+
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq, ValueEnum, Deserialize, Serialize)]
+#[serde(rename_all = "lowercase")]
+pub enum TimeUnit {
+    Minute,
+    Hour,
+    #[default]
+    Day,
+    Week,
+    Month,
+}
+
+/// Filter matching items with (on), without (off) or regardless of (both) some property
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq, ValueEnum)]
+pub enum Toggle {
+    #[default]
+    Both,
+    On,
+    Off,
+}
+
+impl std::fmt::Display for Toggle {
+    #[inline]
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            Self::Both => write!(f, "both"),
+            Self::On => write!(f, "on"),
+            Self::Off => write!(f, "off"),
+        }
+    }
+}
+
+#[derive(Clone, Copy, Debug, Default, ValueEnum)]
+pub enum Format {
+    /// Human-readable text
+    #[default]
+    Text,
+    /// One JSON object per line
+    Json,
+}
+
+/// Format of exported (and imported) cache entries
+#[derive(Clone, Copy, Debug, Default, ValueEnum)]
+pub enum ExchangeFormat {
+    /// One JSON object per line
+    #[default]
+    Jsonl,
+    /// Comma-separated values with a header
+    Csv,
+}
+
+/// Resolution of an imported cache entry that conflicts with a cached one
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq, ValueEnum)]
+pub enum ConflictPolicy {
+    /// Keep the cached entry
+    #[default]
+    Skip,
+    /// Replace the cached entry with the imported one
+    Overwrite,
+    /// Abort the whole import
+    Fail,
+}
+
+/// Parse a duration given as a number with an optional unit (`ms`, `s`, `m` or `h`)
+///
+/// Numbers without a unit are interpreted as seconds.
+fn parse_duration(value: &str) -> Result<Duration, String> {
+    let value = value.trim();
+    let split = value
+        .find(|c: char| !c.is_ascii_digit())
+        .unwrap_or(value.len());
+
+    let (num, unit) = value.split_at(split);
+
+    let num = num
+        .parse::<u64>()
+        .map_err(|_| format!("invalid duration '{value}'"))?;
+
+    match unit.trim() {
+        "ms" => Ok(Duration::from_millis(num)),
+        "" | "s" => Ok(Duration::from_secs(num)),
+        "m" => Ok(Duration::from_secs(num.saturating_mul(60))),
+        "h" => Ok(Duration::from_secs(num.saturating_mul(60 * 60))),
+        unit => Err(format!(
+            "unknown duration unit '{unit}' (use ms, s, m or h)"
+        )),
+    }
+}
+
+/// Parse the limit of requests in flight, which is either a positive number or `auto`
+fn parse_max_concurrent(value: &str) -> Result<MaxConcurrent, String> {
+    match value.trim() {
+        "auto" => Ok(MaxConcurrent::Auto),
+        value => value
+            .parse::<NonZeroUsize>()
+            .map(MaxConcurrent::Fixed)
+            .map_err(|_| format!("expected a positive number or 'auto', got '{value}'")),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use rstest::*;
+
+    use crate::config::Config;
+
+    #[rstest]
+    #[case("250ms", Some(Duration::from_millis(250)))]
+    #[case("30", Some(Duration::from_secs(30)))]
+    #[case("30s", Some(Duration::from_secs(30)))]
+    #[case(" 10m ", Some(Duration::from_secs(600)))]
+    #[case("2h", Some(Duration::from_secs(7200)))]
+    #[case("0", Some(Duration::ZERO))]
+    #[case("1d", None)]
+    #[case("ms", None)]
+    #[case("-1s", None)]
+    fn parse_durations(#[case] value: &str, #[case] expected: Option<Duration>) {
+        assert_eq!(expected, parse_duration(value).ok());
+    }
+
+    #[rstest]
+    #[case("16", Some(MaxConcurrent::Fixed(NonZeroUsize::new(16).unwrap())))]
+    #[case("auto", Some(MaxConcurrent::Auto))]
+    #[case("0", None)]
+    #[case("-1", None)]
+    #[case("fast", None)]
+    fn parse_max_concurrency(#[case] value: &str, #[case] expected: Option<MaxConcurrent>) {
+        assert_eq!(expected, parse_max_concurrent(value).ok());
+    }
+
+    #[rstest]
+    #[case::configured(&[], 8, true)]
+    #[case::fixed(&["--max-concurrent", "4"], 4, false)]
+    #[case::auto(&["--max-concurrent", "auto"], 8, true)]
+    fn max_concurrent_overrides_config(
+        #[case] args: &[&str],
+        #[case] max_concurrent: usize,
+        #[case] adaptive_concurrency: bool,
+    ) {
+        use std::io::Write as _;
+
+        let mut config_file = tempfile::Builder::new()
+            .suffix(".toml")
+            .tempfile()
+            .expect("temp config file");
+
+        write!(
+            config_file,
+            r#"
+            import = []
+            api_token = "test-api-token"
+            max_concurrent = 8
+            adaptive_concurrency = true
+            "#,
+        )
+        .expect("write temp config file");
+
+        let mut cfg = Config::load(&config_file).expect("valid config");
+
+        let args = ["bitcli"]
+            .iter()
+            .chain(args)
+            .chain(&["https://example.com"]);
+        let cli = Cli::try_parse_from(args).expect("valid arguments");
+        cfg.override_with(&cli);
+        cfg.override_with(&Command::from(cli));
+
+        assert_eq!(max_concurrent, cfg.max_concurrent);
+        assert_eq!(adaptive_concurrency, cfg.adaptive_concurrency);
+    }
+}
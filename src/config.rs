@@ -1,12 +1,17 @@
 use std::borrow::Cow;
 use std::io;
-use std::num::NonZeroUsize;
+use std::num::{NonZeroU32, NonZeroUsize};
 use std::path::{Path, PathBuf};
+use std::time::Duration;
 
 use hide::Hide;
 use serde::Deserialize;
+use serde::de::DeserializeOwned;
 use url::Url;
 
+use crate::cassette::CassetteMode;
+use crate::retry::RetryPolicy;
+
 pub const APP: &str = "bitcli";
 
 #[derive(Debug, thiserror::Error)]
@@ -40,43 +45,71 @@ pub struct Config {
     ///  3. Fetch current default group GUID for the logged in user
     pub default_group_guid: Option<String>,
 
+    /// Name of the default group, used if there's no `default_group_guid` (optional)
+    ///
+    /// The name is resolved to a group GUID via a locally cached mapping of user's groups.
+    pub default_group: Option<String>,
+
     /// Path to the cache directory
     ///
     /// If set to an empty path, then caching will be disabled.
     pub cache_dir: Option<PathBuf>,
 
+    /// Age (in seconds) after which cached bitlinks are revalidated (`0` disables revalidation)
+    ///
+    /// Stale bitlinks are checked against the API before being used, and served regardless of
+    /// their age if the API can't be reached.
+    #[serde(default)]
+    pub cache_max_age_s: u64,
+
     /// If set to `true` then no API requests will be issued (disabled by default)
     ///
     /// Any command will only rely on the local cache under the _offline_ mode.
     #[serde(default = "default::offline")]
     pub offline: bool,
 
-    /// Maximum number of API requests in flight
+    /// Maximum number of API requests in flight (the initial one if the concurrency is adaptive)
     #[serde(default = "default::max_concurrent")]
     pub max_concurrent: usize,
-}
 
-impl Config {
-    pub fn load(config: impl AsRef<Path>) -> Result<Self, ConfigError> {
-        let config = config.as_ref();
-        let cfg_dir = get_config_dir(config)?;
+    /// Adapt the number of API requests in flight to the API responses (disabled by default)
+    ///
+    /// The concurrency grows while requests succeed and backs off when the API is overloaded
+    /// (`429` or `503`), staying within `max_concurrent_ceiling`.
+    #[serde(default)]
+    pub adaptive_concurrency: bool,
 
-        let cfg = config::Config::builder()
-            .add_source(config::File::with_name(config.to_string_lossy().as_ref()));
+    /// Upper bound of the number of API requests in flight under the adaptive concurrency
+    #[serde(default = "default::max_concurrent_ceiling")]
+    pub max_concurrent_ceiling: usize,
+
+    /// Maximum number of API requests per second (`0` disables the rate limiting)
+    ///
+    /// If unspecified, the rate is derived from the platform limits of the user (at most 10).
+    #[serde(default)]
+    pub rate_limit: Option<u32>,
 
-        let Imports { import } = cfg
-            .build_cloned()
-            .and_then(config::Config::try_deserialize)?;
+    /// Timeout (in milliseconds) of a single API request attempt (`0` disables the timeout)
+    #[serde(default = "default::request_timeout_ms")]
+    pub request_timeout_ms: u64,
 
-        let cfg = import
-            .into_iter()
-            .filter_map(|path| resolve_import_path(&cfg_dir, path))
-            .fold(cfg, |builder, path| {
-                builder.add_source(config::File::with_name(path.to_string_lossy().as_ref()))
-            })
-            .build()?;
+    /// Timeout (in milliseconds) of establishing a connection (`0` disables the timeout)
+    #[serde(default = "default::connect_timeout_ms")]
+    pub connect_timeout_ms: u64,
+
+    /// Policy of retrying requests that failed with a transient error (see [`RetryPolicy`])
+    #[serde(default)]
+    pub retry: RetryPolicy,
+
+    /// Record API interactions into a cassette, or replay them from one (see [`CassetteMode`])
+    #[serde(skip)]
+    pub cassette: Option<CassetteMode>,
+}
 
-        cfg.try_deserialize().map_err(ConfigError::Load)
+impl Config {
+    #[inline]
+    pub fn load(config: impl AsRef<Path>) -> Result<Self, ConfigError> {
+        load(config.as_ref())
     }
 
     /// Update current configs with _some_ of the given options (only those that are `Some`)
@@ -87,14 +120,23 @@ impl Config {
             self.domain = ops.domain;
         }
 
+        // NOTE: an explicit group (by name or GUID) overrides both the configured values
         if ops.group_guid.is_some() {
             self.default_group_guid = ops.group_guid;
+            self.default_group = None;
+        } else if ops.group.is_some() {
+            self.default_group_guid = None;
+            self.default_group = ops.group;
         }
 
         if ops.cache_dir.is_some() {
             self.cache_dir = ops.cache_dir;
         }
 
+        if let Some(max_age) = ops.cache_max_age {
+            self.cache_max_age_s = max_age.as_secs();
+        }
+
         if let Some(offline) = ops.offline {
             self.offline = offline;
         }
@@ -102,6 +144,38 @@ impl Config {
         if let Some(max_concurrent) = ops.max_concurrent {
             self.max_concurrent = max_concurrent.into();
         }
+
+        if let Some(adaptive_concurrency) = ops.adaptive_concurrency {
+            self.adaptive_concurrency = adaptive_concurrency;
+        }
+
+        if ops.rate_limit.is_some() {
+            self.rate_limit = ops.rate_limit;
+        }
+
+        if let Some(timeout) = ops.request_timeout {
+            self.request_timeout_ms = millis(timeout);
+        }
+
+        if let Some(timeout) = ops.connect_timeout {
+            self.connect_timeout_ms = millis(timeout);
+        }
+
+        if let Some(max_attempts) = ops.max_attempts {
+            self.retry.max_attempts = max_attempts;
+        }
+
+        if let Some(retry_delay) = ops.retry_delay {
+            self.retry.base_delay_ms = retry_delay;
+        }
+
+        if let Some(retry_jitter) = ops.retry_jitter {
+            self.retry.jitter = retry_jitter;
+        }
+
+        if ops.cassette.is_some() {
+            self.cassette = ops.cassette;
+        }
     }
 
     #[cfg(test)]
@@ -114,6 +188,74 @@ impl Config {
     pub(crate) fn api_token(&self) -> &str {
         self.api_token.as_ref()
     }
+
+    /// Age after which cached bitlinks are revalidated (if enabled)
+    #[inline]
+    pub(crate) fn cache_max_age(&self) -> Option<Duration> {
+        Some(Duration::from_secs(self.cache_max_age_s)).filter(|t| !t.is_zero())
+    }
+
+    /// Timeout of a single API request attempt (if enabled)
+    #[inline]
+    pub(crate) fn request_timeout(&self) -> Option<Duration> {
+        Some(Duration::from_millis(self.request_timeout_ms)).filter(|t| !t.is_zero())
+    }
+
+    /// Timeout of establishing a connection (if enabled)
+    #[inline]
+    pub(crate) fn connect_timeout(&self) -> Option<Duration> {
+        Some(Duration::from_millis(self.connect_timeout_ms)).filter(|t| !t.is_zero())
+    }
+}
+
+/// Part of the [`Config`] needed to manage the local cache, which doesn't require API credentials
+#[derive(Debug, Default, Deserialize, PartialEq, Eq)]
+pub struct CacheConfig {
+    /// Path to the cache directory (see [`Config::cache_dir`])
+    pub cache_dir: Option<PathBuf>,
+}
+
+impl CacheConfig {
+    #[inline]
+    pub fn load(config: impl AsRef<Path>) -> Result<Self, ConfigError> {
+        load(config.as_ref())
+    }
+
+    /// Update the cache directory if it's been given in the options
+    pub fn override_with(&mut self, ops: impl Into<Options>) {
+        let ops = ops.into();
+
+        if ops.cache_dir.is_some() {
+            self.cache_dir = ops.cache_dir;
+        }
+    }
+}
+
+/// Load a config file (together with its imports) into given type
+fn load<T: DeserializeOwned>(config: &Path) -> Result<T, ConfigError> {
+    let cfg_dir = get_config_dir(config)?;
+
+    let cfg = config::Config::builder()
+        .add_source(config::File::with_name(config.to_string_lossy().as_ref()));
+
+    let Imports { import } = cfg
+        .build_cloned()
+        .and_then(config::Config::try_deserialize)?;
+
+    let cfg = import
+        .into_iter()
+        .filter_map(|path| resolve_import_path(&cfg_dir, path))
+        .fold(cfg, |builder, path| {
+            builder.add_source(config::File::with_name(path.to_string_lossy().as_ref()))
+        })
+        .build()?;
+
+    cfg.try_deserialize().map_err(ConfigError::Load)
+}
+
+#[inline]
+fn millis(duration: Duration) -> u64 {
+    u64::try_from(duration.as_millis()).unwrap_or(u64::MAX)
 }
 
 mod default {
@@ -133,6 +275,21 @@ mod default {
     pub(super) fn max_concurrent() -> usize {
         16
     }
+
+    #[inline]
+    pub(super) fn max_concurrent_ceiling() -> usize {
+        64
+    }
+
+    #[inline]
+    pub(super) fn request_timeout_ms() -> u64 {
+        30_000
+    }
+
+    #[inline]
+    pub(super) fn connect_timeout_ms() -> u64 {
+        10_000
+    }
 }
 
 #[derive(Debug, Default)]
@@ -143,14 +300,56 @@ pub struct Options {
     /// Default group GUID used in shorten requests (optional)
     pub group_guid: Option<String>,
 
+    /// Name of the default group used in shorten requests (optional)
+    pub group: Option<String>,
+
     /// Alternative path to the cache directory
     pub cache_dir: Option<PathBuf>,
 
+    /// Age after which cached bitlinks are revalidated (zero disables revalidation)
+    pub cache_max_age: Option<Duration>,
+
     /// Controls whether issuing API requests is allowed
     pub offline: Option<bool>,
 
     /// Maximum number of API requests in flight
     pub max_concurrent: Option<NonZeroUsize>,
+
+    /// Controls whether the number of API requests in flight adapts to the API responses
+    pub adaptive_concurrency: Option<bool>,
+
+    /// Maximum number of API requests per second (`0` disables the rate limiting)
+    pub rate_limit: Option<u32>,
+
+    /// Timeout of a single API request attempt (zero disables the timeout)
+    pub request_timeout: Option<Duration>,
+
+    /// Timeout of establishing a connection (zero disables the timeout)
+    pub connect_timeout: Option<Duration>,
+
+    /// Maximum number of attempts of each API request
+    pub max_attempts: Option<NonZeroU32>,
+
+    /// Delay (in milliseconds) before the first retry of a failed API request
+    pub retry_delay: Option<u64>,
+
+    /// Controls whether retry delays are randomized
+    pub retry_jitter: Option<bool>,
+
+    /// Cassette to record API interactions into or to replay them from
+    pub cassette: Option<CassetteMode>,
+}
+
+impl Options {
+    /// Set the group either by name or by GUID, with the name taking precedence
+    pub fn set_group(&mut self, group_guid: &Option<String>, group: &Option<String>) {
+        if group.is_some() {
+            self.group.clone_from(group);
+            self.group_guid = None;
+        } else {
+            self.group_guid.clone_from(group_guid);
+        }
+    }
 }
 
 #[derive(Debug, Deserialize)]
@@ -234,9 +433,18 @@ mod tests {
             api_token: "test-api-token".into(),
             domain: None,
             default_group_guid: None,
+            default_group: None,
             cache_dir: None,
+            cache_max_age_s: 0,
             offline: default::offline(),
             max_concurrent: default::max_concurrent(),
+            adaptive_concurrency: false,
+            max_concurrent_ceiling: default::max_concurrent_ceiling(),
+            rate_limit: None,
+            request_timeout_ms: default::request_timeout_ms(),
+            connect_timeout_ms: default::connect_timeout_ms(),
+            retry: RetryPolicy::default(),
+            cassette: None,
         }
     }
 
@@ -262,8 +470,20 @@ mod tests {
             # Cache directory (optional, empty path disables caching)
             cache_dir = ""
 
+            # Revalidate cached bitlinks older than a day (optional, 0 disables it)
+            cache_max_age_s = 86400
+
             # Maximum number of API requests in flight (default: 16)
             max_concurrent = 8
+
+            # Adapt the number of requests in flight up to a ceiling (default: false, 64)
+            adaptive_concurrency = true
+            max_concurrent_ceiling = 32
+
+            # Retry policy of transient API errors (optional)
+            [retry]
+            max_attempts = 2
+            jitter = false
             "#,
             import_file.path()
         )
@@ -274,9 +494,22 @@ mod tests {
             api_token: "test-api-token".into(),
             domain: None,
             default_group_guid: Some("test-group-guid".to_string()),
+            default_group: None,
             cache_dir: Some(PathBuf::new()),
+            cache_max_age_s: 86_400,
             offline: false,
             max_concurrent: 8,
+            adaptive_concurrency: true,
+            max_concurrent_ceiling: 32,
+            rate_limit: None,
+            request_timeout_ms: default::request_timeout_ms(),
+            connect_timeout_ms: default::connect_timeout_ms(),
+            retry: RetryPolicy {
+                max_attempts: NonZeroU32::new(2).unwrap(),
+                jitter: false,
+                ..RetryPolicy::default()
+            },
+            cassette: None,
         };
 
         match Config::load(config_file) {
@@ -335,22 +568,70 @@ mod tests {
         }
     }
 
+    #[rstest]
+    fn load_cache_config_without_api_token(mut config_file: NamedTempFile) {
+        write!(
+            config_file,
+            r#"
+            import = ["non-existent"]
+            cache_dir = "/path/to/cache"
+            "#,
+        )
+        .expect("write temp config file");
+
+        let mut cfg = match CacheConfig::load(&config_file) {
+            Ok(cfg) => cfg,
+            Err(error) => panic!("expected to read cache config, got: {error:?}"),
+        };
+
+        assert_eq!(Some(PathBuf::from("/path/to/cache")), cfg.cache_dir);
+
+        cfg.override_with(Options::default());
+        assert_eq!(Some(PathBuf::from("/path/to/cache")), cfg.cache_dir);
+
+        cfg.override_with(Options {
+            cache_dir: Some(PathBuf::new()),
+            ..Options::default()
+        });
+        assert_eq!(Some(PathBuf::new()), cfg.cache_dir);
+    }
+
     #[rstest]
     fn override_options(mut config: Config) {
         config.override_with(Options {
             domain: Some("my-domain".to_string()),
             group_guid: None,
+            group: None,
             cache_dir: None,
+            cache_max_age: None,
             offline: None,
             max_concurrent: None,
+            adaptive_concurrency: None,
+            rate_limit: None,
+            request_timeout: None,
+            connect_timeout: None,
+            max_attempts: None,
+            retry_delay: None,
+            retry_jitter: None,
+            cassette: None,
         });
 
         config.override_with(Options {
             domain: None,
             group_guid: None,
+            group: None,
             cache_dir: None,
+            cache_max_age: Some(Duration::from_secs(60 * 60)),
             offline: Some(true),
             max_concurrent: None,
+            adaptive_concurrency: None,
+            rate_limit: None,
+            request_timeout: Some(Duration::from_secs(5)),
+            connect_timeout: Some(Duration::ZERO),
+            max_attempts: NonZeroU32::new(1),
+            retry_delay: Some(10),
+            retry_jitter: None,
+            cassette: None,
         });
 
         let expected = Config {
@@ -358,11 +639,69 @@ mod tests {
             api_token: "test-api-token".into(),
             domain: Some("my-domain".to_string()),
             default_group_guid: None,
+            default_group: None,
             cache_dir: None,
+            cache_max_age_s: 3_600,
             offline: true,
             max_concurrent: default::max_concurrent(),
+            adaptive_concurrency: false,
+            max_concurrent_ceiling: default::max_concurrent_ceiling(),
+            rate_limit: None,
+            request_timeout_ms: 5_000,
+            connect_timeout_ms: 0,
+            retry: RetryPolicy {
+                max_attempts: NonZeroU32::new(1).unwrap(),
+                base_delay_ms: 10,
+                ..RetryPolicy::default()
+            },
+            cassette: None,
         };
 
         assert_eq!(expected, config);
     }
+
+    #[rstest]
+    #[case::group_overrides_guid(
+        (Some("test-group-guid"), None),
+        (None, Some("my-group")),
+        (None, Some("my-group"))
+    )]
+    #[case::guid_overrides_group(
+        (None, Some("test-group")),
+        (Some("my-group-guid"), None),
+        (Some("my-group-guid"), None)
+    )]
+    #[case::guid_takes_precedence(
+        (None, Some("test-group")),
+        (Some("my-group-guid"), Some("my-group")),
+        (Some("my-group-guid"), None)
+    )]
+    #[case::keep_configured(
+        (Some("test-group-guid"), Some("test-group")),
+        (None, None),
+        (Some("test-group-guid"), Some("test-group"))
+    )]
+    fn override_group(
+        mut config: Config,
+        #[case] configured: (Option<&str>, Option<&str>),
+        #[case] options: (Option<&str>, Option<&str>),
+        #[case] expected: (Option<&str>, Option<&str>),
+    ) {
+        config.default_group_guid = configured.0.map(String::from);
+        config.default_group = configured.1.map(String::from);
+
+        config.override_with(Options {
+            group_guid: options.0.map(String::from),
+            group: options.1.map(String::from),
+            ..Options::default()
+        });
+
+        assert_eq!(
+            expected,
+            (
+                config.default_group_guid.as_deref(),
+                config.default_group.as_deref()
+            )
+        );
+    }
 }
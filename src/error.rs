@@ -1,3 +1,5 @@
+use reqwest::StatusCode;
+use reqwest::header::{CONTENT_TYPE, HeaderMap};
 use serde::Deserialize;
 
 pub type Result<T> = std::result::Result<T, Error>;
@@ -10,35 +12,471 @@ pub enum Error {
     #[error("cannot determine group GOUID: {0}")]
     UnknownGroupGUID(&'static str),
 
+    #[error("group '{0}' not found")]
+    UnknownGroup(String),
+
+    #[error(
+        "group name '{name}' is ambiguous, use one of the GUIDs instead:\n{}",
+        candidates.join("\n"),
+    )]
+    AmbiguousGroup {
+        name: String,
+        candidates: Vec<String>,
+    },
+
+    #[error("custom bitlink '{0}' is already taken, try a different keyword")]
+    KeywordTaken(String),
+
+    #[error("monthly shorten quota exceeded: used {used} of {limit} ({remaining} remaining)")]
+    QuotaExceeded {
+        limit: u64,
+        used: u64,
+        remaining: u64,
+    },
+
+    #[error("job '{0}' already exists, continue it with 'bitcli resume {0}' or pick another name")]
+    JobExists(String),
+
+    #[error("job '{0}' not found")]
+    UnknownJob(String),
+
+    #[error(
+        "input #{seq} of job '{job}' does not match its journal (expected '{expected}', got \
+         '{actual}'), resume the job with the same inputs"
+    )]
+    JobInputMismatch {
+        job: String,
+        seq: u64,
+        expected: String,
+        actual: String,
+    },
+
+    #[error("job journal is unavailable (the local database could not be opened)")]
+    JournalUnavailable,
+
+    #[error("local cache is unavailable (caching is disabled or the database could not be opened)")]
+    CacheUnavailable,
+
+    #[error(
+        "database schema version {version} is newer than the supported {supported}, upgrade {}",
+        crate::config::APP
+    )]
+    UnsupportedSchema { version: u32, supported: u32 },
+
+    #[error("invalid record on line {line}: {reason}")]
+    InvalidRecord { line: u64, reason: String },
+
+    #[error(
+        "imported bitlink {link} ({long_url}) conflicts with the cached {cached}, choose how to \
+         resolve conflicts via '--on-conflict'"
+    )]
+    ImportConflict {
+        link: String,
+        long_url: String,
+        cached: String,
+    },
+
+    #[error(transparent)]
+    Database(#[from] sqlx::Error),
+
+    #[error("request {method} {url} has not been recorded in the replayed cassette")]
+    NotRecorded { method: String, url: url::Url },
+
+    #[error("invalid cassette: {0}")]
+    Cassette(String),
+
     #[error(transparent)]
     Io(#[from] std::io::Error),
 
     #[error(transparent)]
     Http(#[from] reqwest::Error),
 
+    #[error(transparent)]
+    Json(#[from] serde_json::Error),
+
     #[error(transparent)]
     Bitly(#[from] ErrorResponse),
+
+    #[error("{input}: {diagnostic}\n  hint: {}\n  caused by: {source}", diagnostic.hint())]
+    Rejected {
+        input: String,
+        diagnostic: Diagnostic,
+        source: ErrorResponse,
+    },
+
+    #[error("unexpected API response {0}")]
+    UnexpectedStatus(Box<RawResponse>),
+
+    #[error("invalid API response {response}: {source}")]
+    InvalidResponse {
+        response: Box<RawResponse>,
+        source: serde_json::Error,
+    },
+
+    #[error("{input}: {source}")]
+    Input { input: String, source: Box<Error> },
+
+    #[error(transparent)]
+    Shared(std::sync::Arc<Error>),
+}
+
+impl Error {
+    /// Attach the input of the failed request to this error
+    ///
+    /// API rejections with a well-known [`Diagnostic`] are turned into [`Error::Rejected`].
+    pub(crate) fn with_input(self, input: impl std::fmt::Display) -> Self {
+        match self {
+            Self::Bitly(source) => match source.diagnose() {
+                Some(diagnostic) => Self::Rejected {
+                    input: input.to_string(),
+                    diagnostic,
+                    source,
+                },
+                None => Self::Input {
+                    input: input.to_string(),
+                    source: Box::new(Self::Bitly(source)),
+                },
+            },
+            error @ (Self::Rejected { .. } | Self::Input { .. }) => error,
+            Self::Shared(error) => match *error {
+                Self::Bitly(ref source) => Self::Bitly(source.clone()).with_input(input),
+                _ => Self::Input {
+                    input: input.to_string(),
+                    source: Box::new(Self::Shared(error)),
+                },
+            },
+            error => Self::Input {
+                input: input.to_string(),
+                source: Box::new(error),
+            },
+        }
+    }
+
+    /// The input of the failed request (if known)
+    pub fn input(&self) -> Option<&str> {
+        match self {
+            Self::Rejected { input, .. } | Self::Input { input, .. } => Some(input),
+            _ => None,
+        }
+    }
+
+    /// The error without the attached input (see [`Error::with_input`])
+    #[cfg(test)]
+    pub(crate) fn without_input(self) -> Self {
+        match self {
+            Self::Input { source, .. } => *source,
+            error => error,
+        }
+    }
+
+    /// Short machine-readable classification of the error
+    pub fn kind(&self) -> &'static str {
+        match self {
+            Self::Offline(_) => "offline",
+            Self::UnknownGroupGUID(_) | Self::UnknownGroup(_) => "unknown_group",
+            Self::AmbiguousGroup { .. } => "ambiguous_group",
+            Self::KeywordTaken(_) => "keyword_taken",
+            Self::QuotaExceeded { .. } => "quota_exceeded",
+            Self::JobExists(_) | Self::UnknownJob(_) | Self::JobInputMismatch { .. } => "job",
+            Self::JournalUnavailable
+            | Self::CacheUnavailable
+            | Self::UnsupportedSchema { .. }
+            | Self::Database(_) => "database",
+            Self::NotRecorded { .. } | Self::Cassette(_) => "cassette",
+            Self::InvalidRecord { .. } => "invalid_input",
+            Self::ImportConflict { .. } => "conflict",
+            Self::Io(error) if error.kind() == std::io::ErrorKind::InvalidInput => "invalid_input",
+            Self::Io(_) => "io",
+            Self::Http(error) if error.is_timeout() => "timeout",
+            Self::Http(_) => "http",
+            Self::Json(_) => "json",
+            Self::Bitly(_) => "api",
+            Self::Rejected { diagnostic, .. } => diagnostic.kind(),
+            Self::UnexpectedStatus(_) => "unexpected_status",
+            Self::InvalidResponse { .. } => "invalid_response",
+            Self::Input { source, .. } => source.kind(),
+            Self::Shared(error) => error.kind(),
+        }
+    }
+}
+
+/// Summary of an API response that could not be handled
+#[derive(Debug)]
+pub struct RawResponse {
+    pub status: StatusCode,
+    pub headers: HeaderMap,
+    /// Response body (lossy UTF-8, truncated to [`RawResponse::MAX_BODY_LEN`] characters)
+    pub body: String,
 }
 
-#[derive(Debug, Deserialize, thiserror::Error)]
-#[error(
-    "Bitly request failed with {message} ({}): {} | {:?}",
-    resource.as_deref().unwrap_or("?"),
-    description.as_deref().unwrap_or("?"),
-    errors.as_deref().unwrap_or_default(),
-)]
+impl RawResponse {
+    pub const MAX_BODY_LEN: usize = 512;
+
+    pub fn new(status: StatusCode, headers: HeaderMap, body: &[u8]) -> Self {
+        let body = String::from_utf8_lossy(body);
+
+        let body = match body.char_indices().nth(Self::MAX_BODY_LEN) {
+            Some((end, _)) => format!("{}...", &body[..end]),
+            None => body.into_owned(),
+        };
+
+        Self {
+            status,
+            headers,
+            body,
+        }
+    }
+}
+
+impl std::fmt::Display for RawResponse {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        let content_type = self
+            .headers
+            .get(CONTENT_TYPE)
+            .and_then(|value| value.to_str().ok())
+            .unwrap_or("?");
+
+        write!(f, "({}, {content_type})", self.status)?;
+
+        if !self.body.is_empty() {
+            write!(f, " {}", self.body)?;
+        }
+
+        Ok(())
+    }
+}
+
+/// Error codes of custom bitlink requests rejected because the keyword is already in use
+const KEYWORD_TAKEN: &[&str] = &["ALREADY_EXISTS", "CUSTOM_BITLINK_ALREADY_EXISTS"];
+
+#[derive(Clone, Debug, Deserialize, thiserror::Error)]
 pub struct ErrorResponse {
     pub(crate) message: String,
     pub(crate) description: Option<String>,
     pub(crate) resource: Option<String>,
-    #[serde(skip_deserializing)]
+    #[serde(default)]
     pub(crate) errors: Option<Vec<FieldError>>,
 }
 
-#[allow(dead_code)]
-#[derive(Debug, Deserialize)]
+impl ErrorResponse {
+    /// Check whether this is a rejection of a custom bitlink keyword that's already in use
+    ///
+    /// NOTE: Other `ALREADY_*` codes mean something else (e.g., `ALREADY_A_BITLY_LINK` rejects
+    /// shortening a URL that's a bitlink already).
+    pub(crate) fn is_keyword_taken(&self) -> bool {
+        KEYWORD_TAKEN.contains(&self.message.as_str())
+            || self.field_errors().any(|error| {
+                matches!(error.field.as_str(), "keyword" | "custom_bitlink")
+                    && error.error_code == "already_exists"
+            })
+    }
+
+    /// Check whether the requested resource does not exist (anymore)
+    pub(crate) fn is_not_found(&self) -> bool {
+        matches!(self.message.as_str(), "NOT_FOUND" | "GONE")
+    }
+
+    /// Map well-known rejections of request inputs to an actionable [`Diagnostic`]
+    pub(crate) fn diagnose(&self) -> Option<Diagnostic> {
+        let fields = self
+            .field_errors()
+            .map(|error| error.field.as_str())
+            .chain(self.message.strip_prefix("INVALID_ARG_"));
+
+        for field in fields {
+            match field.to_ascii_lowercase().as_str() {
+                "long_url" => return Some(Diagnostic::InvalidLongUrl),
+                "domain" => return Some(Diagnostic::DomainNotAllowed),
+                "group_guid" | "group" => return Some(Diagnostic::GroupForbidden),
+                _ => {}
+            }
+        }
+
+        match self.message.as_str() {
+            "FORBIDDEN" if self.resource.as_deref() == Some("groups") => {
+                Some(Diagnostic::GroupForbidden)
+            }
+            "BRANDED_DOMAIN_NOT_ALLOWED" | "INVALID_DOMAIN" => Some(Diagnostic::DomainNotAllowed),
+            _ => None,
+        }
+    }
+
+    #[inline]
+    fn field_errors(&self) -> impl Iterator<Item = &FieldError> {
+        self.errors.iter().flatten()
+    }
+}
+
+impl std::fmt::Display for ErrorResponse {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(f, "Bitly request failed with {}", self.message)?;
+
+        if let Some(ref resource) = self.resource {
+            write!(f, " ({resource})")?;
+        }
+
+        if let Some(ref description) = self.description {
+            write!(f, ": {description}")?;
+        }
+
+        for error in self.field_errors() {
+            write!(f, "\n  - {error}")?;
+        }
+
+        Ok(())
+    }
+}
+
+#[derive(Clone, Debug, Deserialize)]
 pub struct FieldError {
+    #[serde(default)]
     pub(crate) field: String,
+    #[serde(default)]
     pub(crate) error_code: String,
-    pub(crate) message: String,
+    #[serde(default)]
+    pub(crate) message: Option<String>,
+}
+
+impl std::fmt::Display for FieldError {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(f, "{}: {}", self.field, self.error_code)?;
+        match self.message {
+            Some(ref message) if !message.is_empty() => write!(f, " ({message})"),
+            _ => Ok(()),
+        }
+    }
+}
+
+/// Well-known reason of a rejected request input, along with a suggested fix
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum Diagnostic {
+    InvalidLongUrl,
+    DomainNotAllowed,
+    GroupForbidden,
+}
+
+impl Diagnostic {
+    /// Short machine-readable classification of the rejection
+    #[inline]
+    pub fn kind(&self) -> &'static str {
+        match self {
+            Self::InvalidLongUrl => "invalid_long_url",
+            Self::DomainNotAllowed => "domain_not_allowed",
+            Self::GroupForbidden => "group_forbidden",
+        }
+    }
+
+    /// Suggestion of how to fix the rejected input
+    pub fn hint(&self) -> &'static str {
+        match self {
+            Self::InvalidLongUrl => {
+                "check that the URL is absolute, uses http(s) and is not a bitlink itself"
+            }
+            Self::DomainNotAllowed => {
+                "use a (branded) domain available to your account via '--domain' or omit it to \
+                 use the default 'bit.ly'"
+            }
+            Self::GroupForbidden => {
+                "use a group you have access to (see 'bitcli groups list') via '--group' or \
+                 '--group-guid'"
+            }
+        }
+    }
+}
+
+impl std::fmt::Display for Diagnostic {
+    #[inline]
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            Self::InvalidLongUrl => write!(f, "invalid long URL"),
+            Self::DomainNotAllowed => write!(f, "domain is not allowed"),
+            Self::GroupForbidden => write!(f, "group is not accessible"),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use rstest::*;
+
+    #[rstest]
+    #[case::long_url(
+        r#"{
+          "message": "INVALID_ARG_LONG_URL",
+          "resource": "bitlinks",
+          "description": "The value provided is invalid.",
+          "errors": [{"field": "long_url", "error_code": "invalid"}]
+        }"#,
+        Some(Diagnostic::InvalidLongUrl)
+    )]
+    #[case::domain(
+        r#"{
+          "message": "INVALID_ARG_DOMAIN",
+          "resource": "bitlinks",
+          "description": "The value provided is invalid."
+        }"#,
+        Some(Diagnostic::DomainNotAllowed)
+    )]
+    #[case::group(
+        r#"{
+          "message": "FORBIDDEN",
+          "resource": "bitlinks",
+          "errors": [{"field": "group_guid", "error_code": "forbidden", "message": "no access"}]
+        }"#,
+        Some(Diagnostic::GroupForbidden)
+    )]
+    #[case::unknown(r#"{"message": "FORBIDDEN"}"#, None)]
+    fn diagnose_error_response(#[case] resp: &str, #[case] expected: Option<Diagnostic>) {
+        let resp = serde_json::from_str::<ErrorResponse>(resp).expect("valid error response");
+        assert_eq!(expected, resp.diagnose());
+    }
+
+    #[rstest]
+    #[case::already_exists(r#"{"message": "ALREADY_EXISTS"}"#, true)]
+    #[case::custom_bitlink(r#"{"message": "CUSTOM_BITLINK_ALREADY_EXISTS"}"#, true)]
+    #[case::bitly_link(r#"{"message": "ALREADY_A_BITLY_LINK"}"#, false)]
+    #[case::group(r#"{"message": "GROUP_ALREADY_EXISTS"}"#, false)]
+    fn keyword_taken_by_message(#[case] resp: &str, #[case] expected: bool) {
+        let resp = serde_json::from_str::<ErrorResponse>(resp).expect("valid error response");
+        assert_eq!(expected, resp.is_keyword_taken());
+    }
+
+    #[rstest]
+    #[case::already_exists("custom_bitlink", "already_exists", true)]
+    #[case::keyword("keyword", "already_exists", true)]
+    #[case::not_exists("custom_bitlink", "not_exists", false)]
+    #[case::other_field("long_url", "already_exists", false)]
+    fn keyword_taken_by_field_error(
+        #[case] field: &str,
+        #[case] error_code: &str,
+        #[case] expected: bool,
+    ) {
+        let resp = serde_json::from_value::<ErrorResponse>(serde_json::json!({
+            "message": "UNPROCESSABLE_ENTITY",
+            "errors": [{"field": field, "error_code": error_code}]
+        }))
+        .expect("valid error response");
+
+        assert_eq!(expected, resp.is_keyword_taken());
+    }
+
+    #[rstest]
+    fn display_field_errors() {
+        let resp = serde_json::from_str::<ErrorResponse>(
+            r#"{
+              "message": "INVALID_ARG_LONG_URL",
+              "resource": "bitlinks",
+              "description": "The value provided is invalid.",
+              "errors": [{"field": "long_url", "error_code": "invalid", "message": "bad URL"}]
+            }"#,
+        )
+        .expect("valid error response");
+
+        assert_eq!(
+            "Bitly request failed with INVALID_ARG_LONG_URL (bitlinks): The value provided is \
+             invalid.\n  - long_url: invalid (bad URL)",
+            resp.to_string()
+        );
+    }
 }
@@ -0,0 +1,138 @@
+use std::io::{BufRead, Write};
+
+use crate::cache::CacheEntry;
+use crate::cli::ExchangeFormat;
+use crate::error::{Error, Result};
+
+/// Write given cache entries in given format (see [`read_entries`])
+pub fn write_entries(
+    writer: impl Write,
+    format: ExchangeFormat,
+    entries: &[CacheEntry],
+) -> Result<()> {
+    match format {
+        ExchangeFormat::Jsonl => {
+            let mut writer = writer;
+            for entry in entries {
+                serde_json::to_writer(&mut writer, entry)?;
+                writeln!(writer)?;
+            }
+            writer.flush()?;
+        }
+
+        ExchangeFormat::Csv => {
+            let mut writer = csv::Writer::from_writer(writer);
+            for entry in entries {
+                writer.serialize(entry).map_err(std::io::Error::from)?;
+            }
+            writer.flush()?;
+        }
+    }
+
+    Ok(())
+}
+
+/// Read all the cache entries written by [`write_entries`] in given format
+///
+/// Both the links and long URLs are validated, and the first invalid entry fails the whole read
+/// (naming its line).
+pub fn read_entries(reader: impl BufRead, format: ExchangeFormat) -> Result<Vec<CacheEntry>> {
+    match format {
+        ExchangeFormat::Jsonl => {
+            let mut entries = Vec::new();
+
+            for (line, text) in (1..).zip(reader.lines()) {
+                let text = text?;
+                if text.trim().is_empty() {
+                    continue;
+                }
+
+                let entry = serde_json::from_str(&text).map_err(|error| Error::InvalidRecord {
+                    line,
+                    reason: error.to_string(),
+                })?;
+
+                entries.push(entry);
+            }
+
+            Ok(entries)
+        }
+
+        ExchangeFormat::Csv => csv::Reader::from_reader(reader)
+            .into_deserialize()
+            .map(|entry| {
+                entry.map_err(|error| match error.kind() {
+                    csv::ErrorKind::Io(_) => Error::Io(error.into()),
+                    _ => Error::InvalidRecord {
+                        line: error.position().map_or(0, csv::Position::line),
+                        reason: match error.into_kind() {
+                            csv::ErrorKind::Deserialize { err, .. } => err.to_string(),
+                            kind => format!("{kind:?}"),
+                        },
+                    },
+                })
+            })
+            .collect(),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use rstest::*;
+
+    #[fixture]
+    fn entries() -> Vec<CacheEntry> {
+        vec![
+            CacheEntry {
+                id: "bit.ly/4ePsyXN".to_string(),
+                link: "https://bit.ly/4ePsyXN".parse().unwrap(),
+                long_url: "https://example.com/?q=a,b".parse().unwrap(),
+                domain: Some("bit.ly".to_string()),
+                group_guid: "test-group-guid".to_string(),
+            },
+            CacheEntry {
+                id: "bit.ly/other".to_string(),
+                link: "https://bit.ly/other".parse().unwrap(),
+                long_url: "https://example.com/other".parse().unwrap(),
+                domain: None,
+                group_guid: "test-group-guid".to_string(),
+            },
+        ]
+    }
+
+    #[rstest]
+    #[case::jsonl(ExchangeFormat::Jsonl)]
+    #[case::csv(ExchangeFormat::Csv)]
+    fn write_read_entries(entries: Vec<CacheEntry>, #[case] format: ExchangeFormat) {
+        let mut buf = Vec::new();
+        write_entries(&mut buf, format, &entries).expect("written entries");
+
+        let actual = read_entries(buf.as_slice(), format).expect("read entries");
+        assert_eq!(entries, actual);
+    }
+
+    #[rstest]
+    #[case::jsonl(
+        ExchangeFormat::Jsonl,
+        concat!(
+            r#"{"id":"bit.ly/a","link":"https://bit.ly/a","long_url":"https://example.com","domain":null,"group_guid":"g"}"#,
+            "\n",
+            r#"{"id":"bit.ly/b","link":"https://bit.ly/b","long_url":"example.com","domain":null,"group_guid":"g"}"#,
+        ),
+        2
+    )]
+    #[case::csv(
+        ExchangeFormat::Csv,
+        "id,link,long_url,domain,group_guid\n\
+         bit.ly/a,https://bit.ly/a,https://example.com,,g\n\
+         bit.ly/b,not a link,https://example.com/b,,g\n",
+        3
+    )]
+    fn reject_invalid_urls(#[case] format: ExchangeFormat, #[case] input: &str, #[case] line: u64) {
+        match read_entries(input.as_bytes(), format) {
+            Err(Error::InvalidRecord { line: actual, .. }) => assert_eq!(line, actual),
+            result => panic!("expected invalid record, got: {result:?}"),
+        }
+    }
+}
@@ -3,7 +3,7 @@ use std::io::IsTerminal as _;
 use std::os::fd::AsFd as _;
 use std::str::FromStr;
 
-use async_stream::try_stream;
+use async_stream::stream;
 use futures_util::TryStream;
 use tokio::io::{self, AsyncBufReadExt as _, AsyncRead, BufReader};
 
@@ -11,6 +11,9 @@ use crate::error::Result;
 
 /// Read standard input as a [`TryStream`] of parsed lines of type `T`.
 ///
+/// Lines that fail to parse yield an error (naming the line), but unlike read errors, they do not
+/// end the stream.
+///
 /// Returns `None` if the stdin handle does not refer to a terminal/tty.
 pub fn read_input<T>() -> Option<impl TryStream<Item = Result<T>>>
 where
@@ -31,18 +34,26 @@ where
     T: FromStr + 'static,
     <T as FromStr>::Err: Error + Send + Sync,
 {
-    try_stream! {
+    stream! {
         let mut reader = BufReader::new(reader);
         let mut buf = String::new();
 
         loop {
-            let n = reader.read_line(&mut buf).await?;
-
-            if n == 0 {
-                break;
+            match reader.read_line(&mut buf).await {
+                Ok(0) => break,
+                Ok(_) => {}
+                Err(error) => {
+                    yield Err(error.into());
+                    break;
+                }
             }
 
-            yield buf.trim().parse::<T>().map_err(invalid_input)?;
+            let line = buf.trim();
+
+            yield line.parse::<T>().map_err(|error| {
+                let error = crate::error::Error::Io(invalid_input(error));
+                error.with_input(line)
+            });
 
             buf.clear();
         }
@@ -104,18 +115,26 @@ mod tests {
         let reader = Builder::new()
             .read(b"https://example.com\n")
             .read(b"some invalid entry\n")
+            .read(b"http://example.com\n")
             .build();
 
         let input = try_read::<Url>(reader).collect::<Vec<_>>().await;
-        let input = input.into_iter().collect::<Result<Vec<_>>>();
 
-        if let Some(err) = input.expect_err("read should fail").source() {
-            assert_eq!(
-                err.downcast_ref::<std::io::Error>()
-                    .expect("expected an std::io::Error")
-                    .kind(),
-                std::io::ErrorKind::InvalidInput
-            );
+        // NOTE: an invalid entry does not end the input
+        assert_eq!(3, input.len());
+
+        let error = input
+            .into_iter()
+            .collect::<Result<Vec<_>>>()
+            .expect_err("read should fail");
+
+        assert_eq!(Some("some invalid entry"), error.input());
+
+        match error.without_input() {
+            crate::error::Error::Io(err) => {
+                assert_eq!(std::io::ErrorKind::InvalidInput, err.kind())
+            }
+            error => panic!("expected an std::io::Error, got: {error:?}"),
         }
     }
 
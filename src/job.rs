@@ -0,0 +1,229 @@
+use std::collections::BTreeMap;
+use std::path::{Path, PathBuf};
+
+use serde::{Deserialize, Serialize};
+use tracing::{debug, instrument};
+
+use crate::api::{BitlinkFields, VERSION};
+use crate::cache::BitlinkCache;
+use crate::cli::{Ordering, ShortenArgs};
+use crate::config::Config;
+use crate::error::{Error, Result};
+
+/// Settings of a job recorded when it's started, so that it can be resumed with the same ones
+#[derive(Debug, Deserialize, Serialize)]
+pub struct JobSpec {
+    pub domain: Option<String>,
+    pub group_guid: Option<String>,
+    pub group: Option<String>,
+    pub fields: BitlinkFields,
+    pub ordering: Ordering,
+    pub keep_going: bool,
+    pub errors_file: Option<PathBuf>,
+    #[serde(default)]
+    pub unique: bool,
+}
+
+impl JobSpec {
+    /// Record the arguments of a shortening job and the effective configs it runs with
+    pub fn new(cfg: &Config, args: &ShortenArgs) -> Result<Self> {
+        // NOTE: the job may be resumed from a different working directory
+        let errors_file = match args.batch.errors_file {
+            Some(ref path) => Some(std::path::absolute(path)?),
+            None => None,
+        };
+
+        Ok(Self {
+            domain: cfg.domain.clone(),
+            group_guid: cfg.default_group_guid.clone(),
+            group: cfg.default_group.clone(),
+            fields: BitlinkFields::from(args),
+            ordering: args.batch.ordering,
+            keep_going: args.batch.keep_going,
+            errors_file,
+            unique: args.unique,
+        })
+    }
+
+    /// Apply the recorded domain and group to given configs (overriding the current ones)
+    pub fn apply(&self, cfg: &mut Config) {
+        cfg.domain.clone_from(&self.domain);
+        cfg.default_group_guid.clone_from(&self.group_guid);
+        cfg.default_group.clone_from(&self.group);
+    }
+}
+
+/// Named batch job, which journals processed inputs in the local database
+///
+/// Inputs are identified by their sequence number (offset) in the input. Once an input has been
+/// processed, it's _acknowledged_ in the journal together with its output (or error), and skipped
+/// when the job is resumed.
+#[derive(Debug)]
+pub struct Job {
+    name: String,
+    spec: JobSpec,
+    db: BitlinkCache,
+    acked: BTreeMap<u64, (String, Option<String>)>,
+}
+
+impl Job {
+    /// Start a new job with given name, failing if there already is one
+    #[instrument(level = "debug", skip(cache_dir, spec))]
+    pub async fn start(cache_dir: Option<&Path>, name: &str, spec: JobSpec) -> Result<Self> {
+        let db = open(cache_dir).await?;
+
+        if !db.create_job(name, &serde_json::to_string(&spec)?).await? {
+            return Err(Error::JobExists(name.to_string()));
+        }
+
+        Ok(Self {
+            name: name.to_string(),
+            spec,
+            db,
+            acked: BTreeMap::new(),
+        })
+    }
+
+    /// Resume an existing job with given name
+    #[instrument(level = "debug", skip(cache_dir))]
+    pub async fn resume(cache_dir: Option<&Path>, name: &str) -> Result<Self> {
+        let db = open(cache_dir).await?;
+
+        let Some(spec) = db.get_job(name).await? else {
+            return Err(Error::UnknownJob(name.to_string()));
+        };
+
+        let spec = serde_json::from_str(&spec)?;
+        let acked = db.acknowledged(name).await?;
+
+        debug!(acked = acked.len(), "resuming job");
+
+        Ok(Self {
+            name: name.to_string(),
+            spec,
+            db,
+            acked,
+        })
+    }
+
+    #[inline]
+    pub fn spec(&self) -> &JobSpec {
+        &self.spec
+    }
+
+    /// Outputs of the inputs processed (and acknowledged) before the job has been resumed
+    pub fn outputs(&self) -> impl Iterator<Item = &str> {
+        self.acked
+            .values()
+            .filter_map(|(_, output)| output.as_deref())
+    }
+
+    /// Returns `true` if the input with given sequence number has been processed already
+    ///
+    /// Fails if the input differs from the one recorded in the journal (i.e., the job has been
+    /// resumed with different inputs).
+    pub fn is_acked(&self, seq: u64, input: &str) -> Result<bool> {
+        match self.acked.get(&seq) {
+            Some((expected, _)) if expected == input => Ok(true),
+            Some((expected, _)) => Err(Error::JobInputMismatch {
+                job: self.name.clone(),
+                seq,
+                expected: expected.clone(),
+                actual: input.to_string(),
+            }),
+            None => Ok(false),
+        }
+    }
+
+    /// Record the output (or error) of the input with given sequence number
+    ///
+    /// Inputs can also be processed without any output (e.g., duplicates under `--unique`).
+    pub async fn acknowledge(
+        &self,
+        seq: u64,
+        input: &str,
+        outcome: std::result::Result<Option<&str>, &str>,
+    ) -> Result<()> {
+        let (output, error) = match outcome {
+            Ok(output) => (output, None),
+            Err(error) => (None, Some(error)),
+        };
+
+        self.db
+            .acknowledge(&self.name, seq, input, output, error)
+            .await
+            .map_err(Error::from)
+    }
+}
+
+/// Open the database of jobs, which lives next to the cache even if caching is disabled
+async fn open(cache_dir: Option<&Path>) -> Result<BitlinkCache> {
+    let cache_dir = cache_dir.filter(|dir| !dir.as_os_str().is_empty());
+    BitlinkCache::new(VERSION, cache_dir)
+        .await
+        .ok_or(Error::JournalUnavailable)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use rstest::*;
+
+    use tempfile::TempDir;
+
+    fn spec() -> JobSpec {
+        JobSpec {
+            domain: Some("bit.ly".to_string()),
+            group_guid: None,
+            group: Some("marketing".to_string()),
+            fields: BitlinkFields::default(),
+            ordering: Ordering::Ordered,
+            keep_going: true,
+            errors_file: None,
+            unique: false,
+        }
+    }
+
+    #[rstest]
+    #[tokio::test]
+    async fn start_and_resume_job() {
+        let dir = TempDir::new().expect("temporary cache directory");
+        let dir = Some(dir.path());
+
+        let job = Job::start(dir, "links", spec()).await.expect("started job");
+        assert!(!job.is_acked(0, "https://example.com/").unwrap());
+
+        job.acknowledge(0, "https://example.com/", Ok(Some("https://bit.ly/a")))
+            .await
+            .expect("acknowledged output");
+        job.acknowledge(1, "https://example.com/", Ok(None))
+            .await
+            .expect("acknowledged without output");
+        job.acknowledge(2, "https://example.com/2", Err("offline"))
+            .await
+            .expect("acknowledged error");
+
+        match Job::start(dir, "links", spec()).await {
+            Err(Error::JobExists(name)) => assert_eq!("links", name),
+            result => panic!("expected existing job, got: {result:?}"),
+        }
+
+        let job = Job::resume(dir, "links").await.expect("resumed job");
+        assert_eq!(Some("marketing"), job.spec().group.as_deref());
+        assert!(job.is_acked(0, "https://example.com/").unwrap());
+        assert!(job.is_acked(1, "https://example.com/").unwrap());
+        assert!(!job.is_acked(3, "https://example.com/3").unwrap());
+        assert!(job.is_acked(2, "https://example.com/2").unwrap());
+        assert_eq!(vec!["https://bit.ly/a"], job.outputs().collect::<Vec<_>>());
+
+        match job.is_acked(0, "https://example.com/other") {
+            Err(Error::JobInputMismatch { seq: 0, .. }) => {}
+            result => panic!("expected input mismatch, got: {result:?}"),
+        }
+
+        match Job::resume(dir, "unknown").await {
+            Err(Error::UnknownJob(name)) => assert_eq!("unknown", name),
+            result => panic!("expected unknown job, got: {result:?}"),
+        }
+    }
+}
@@ -0,0 +1,284 @@
+use std::num::{NonZeroU32, NonZeroUsize};
+use std::sync::Mutex;
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::time::{Duration, Instant};
+
+use tracing::debug;
+
+use crate::error::{Error, Result};
+
+/// Token bucket limiting the rate of API requests, shared by all the requests in flight
+///
+/// The bucket holds up to `rate` tokens (i.e., allows bursts of up to one second worth of
+/// requests) and refills continuously at `rate` tokens per second.
+#[derive(Debug)]
+pub struct RateLimiter {
+    rate: f64,
+    bucket: Mutex<Bucket>,
+}
+
+#[derive(Debug)]
+struct Bucket {
+    tokens: f64,
+    updated: Instant,
+}
+
+impl RateLimiter {
+    pub fn new(rate: NonZeroU32) -> Self {
+        let rate = f64::from(rate.get());
+        Self {
+            rate,
+            bucket: Mutex::new(Bucket {
+                tokens: rate,
+                updated: Instant::now(),
+            }),
+        }
+    }
+
+    /// Wait until a request can be issued without exceeding the rate limit
+    ///
+    /// Each call takes a token right away (possibly going into debt), so concurrent callers are
+    /// scheduled in the order they arrived.
+    pub async fn acquire(&self) {
+        let wait = {
+            let mut bucket = self.bucket.lock().expect("rate limiter lock");
+
+            let now = Instant::now();
+            let elapsed = now.duration_since(bucket.updated).as_secs_f64();
+
+            bucket.tokens = (bucket.tokens + elapsed * self.rate).min(self.rate) - 1.0;
+            bucket.updated = now;
+
+            if bucket.tokens >= 0.0 {
+                return;
+            }
+
+            Duration::from_secs_f64(-bucket.tokens / self.rate)
+        };
+
+        tokio::time::sleep(wait).await;
+    }
+}
+
+/// Limit of API requests in flight, which is either fixed or adapted to the API responses
+///
+/// The adaptive limit follows AIMD (additive increase, multiplicative decrease): it grows by one
+/// after each window of successful requests (i.e., as many as the current limit) and it's halved
+/// when the API is overloaded (`429` or `503`), but never beyond the `[1, ceiling]` range.
+#[derive(Debug)]
+pub struct Concurrency {
+    ceiling: usize,
+    adaptive: bool,
+    window: Mutex<Window>,
+}
+
+#[derive(Debug)]
+struct Window {
+    limit: f64,
+    /// Number of decreases so far, which tells apart requests issued before the last one
+    epoch: u64,
+}
+
+impl Concurrency {
+    /// Fixed limit of requests in flight
+    pub fn fixed(limit: NonZeroUsize) -> Self {
+        Self::new(limit, limit, false)
+    }
+
+    /// Adaptive limit of requests in flight, starting at `initial` and growing up to `ceiling`
+    pub fn adaptive(initial: NonZeroUsize, ceiling: NonZeroUsize) -> Self {
+        Self::new(initial.min(ceiling), ceiling, true)
+    }
+
+    fn new(limit: NonZeroUsize, ceiling: NonZeroUsize, adaptive: bool) -> Self {
+        Self {
+            ceiling: ceiling.get(),
+            adaptive,
+            window: Mutex::new(Window {
+                limit: limit.get() as f64,
+                epoch: 0,
+            }),
+        }
+    }
+
+    /// Current limit of requests in flight
+    pub fn limit(&self) -> usize {
+        let window = self.window.lock().expect("concurrency lock");
+        (window.limit as usize).clamp(1, self.ceiling)
+    }
+
+    /// Current epoch, which should be recorded before issuing a request (see [`Self::overloaded`])
+    pub fn epoch(&self) -> u64 {
+        self.window.lock().expect("concurrency lock").epoch
+    }
+
+    /// Additively increase the limit after a successful request
+    pub fn succeeded(&self) {
+        if !self.adaptive {
+            return;
+        }
+
+        let mut window = self.window.lock().expect("concurrency lock");
+
+        let before = window.limit as usize;
+        window.limit = (window.limit + 1.0 / window.limit).min(self.ceiling as f64);
+
+        if window.limit as usize > before {
+            debug!(
+                concurrency = window.limit as usize,
+                "increasing concurrency"
+            );
+        }
+    }
+
+    /// Multiplicatively decrease the limit after a request issued in given `epoch` was rejected
+    /// due to an overload
+    ///
+    /// Requests issued before the last decrease are ignored, so that a burst of rejections (of
+    /// requests that were in flight together) backs off just once.
+    pub fn overloaded(&self, epoch: u64) {
+        if !self.adaptive {
+            return;
+        }
+
+        let mut window = self.window.lock().expect("concurrency lock");
+
+        if window.epoch != epoch {
+            return;
+        }
+
+        window.limit = (window.limit / 2.0).max(1.0);
+        window.epoch += 1;
+
+        debug!(
+            concurrency = window.limit as usize,
+            "backing off concurrency"
+        );
+    }
+}
+
+/// Remaining part of a monthly API quota (e.g., of shortened links)
+#[derive(Debug)]
+pub struct Quota {
+    limit: u64,
+    used: AtomicU64,
+}
+
+impl Quota {
+    #[inline]
+    pub fn new(limit: u64, used: u64) -> Self {
+        Self {
+            limit,
+            used: AtomicU64::new(used),
+        }
+    }
+
+    #[inline]
+    pub fn limit(&self) -> u64 {
+        self.limit
+    }
+
+    /// Number of units used so far (including those reserved by this process)
+    #[inline]
+    pub fn used(&self) -> u64 {
+        self.used.load(Ordering::Acquire)
+    }
+
+    /// Reserve a single unit of the quota, failing if it has been exhausted already
+    pub fn reserve(&self) -> Result<()> {
+        let reserved = self
+            .used
+            .fetch_update(Ordering::AcqRel, Ordering::Acquire, |used| {
+                (used < self.limit).then_some(used + 1)
+            });
+
+        match reserved {
+            Ok(_) => Ok(()),
+            Err(used) => Err(Error::QuotaExceeded {
+                limit: self.limit,
+                used,
+                remaining: self.limit.saturating_sub(used),
+            }),
+        }
+    }
+
+    /// Give back a unit reserved before (e.g., if the request it's been reserved for failed)
+    pub fn release(&self) {
+        let _ = self
+            .used
+            .fetch_update(Ordering::AcqRel, Ordering::Acquire, |used| {
+                used.checked_sub(1)
+            });
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use rstest::*;
+
+    #[rstest]
+    #[tokio::test]
+    async fn rate_limit_requests() {
+        let limiter = RateLimiter::new(NonZeroU32::new(100).unwrap());
+
+        // NOTE: the first 100 requests fit into the initial burst
+        let start = Instant::now();
+        for _ in 0..105 {
+            limiter.acquire().await;
+        }
+
+        let elapsed = start.elapsed();
+        assert!(
+            elapsed >= Duration::from_millis(45),
+            "5 requests over the burst took just {elapsed:?}"
+        );
+    }
+
+    #[rstest]
+    fn adapt_concurrency() {
+        let nz = |n| NonZeroUsize::new(n).unwrap();
+        let concurrency = Concurrency::adaptive(nz(4), nz(6));
+        assert_eq!(4, concurrency.limit());
+
+        // a window of (slightly more than) 4 successful requests increases the limit by one
+        let epoch = concurrency.epoch();
+        (0..5).for_each(|_| concurrency.succeeded());
+        assert_eq!(5, concurrency.limit());
+
+        // the limit never exceeds the ceiling
+        (0..100).for_each(|_| concurrency.succeeded());
+        assert_eq!(6, concurrency.limit());
+
+        // overloaded requests of the same epoch back off just once
+        concurrency.overloaded(epoch);
+        concurrency.overloaded(epoch);
+        assert_eq!(3, concurrency.limit());
+
+        // and the limit never drops below one
+        (0..4).for_each(|_| concurrency.overloaded(concurrency.epoch()));
+        assert_eq!(1, concurrency.limit());
+
+        let fixed = Concurrency::fixed(nz(4));
+        fixed.overloaded(fixed.epoch());
+        fixed.succeeded();
+        assert_eq!(4, fixed.limit());
+    }
+
+    #[rstest]
+    fn reserve_quota() {
+        let quota = Quota::new(3, 1);
+
+        assert!(quota.reserve().is_ok());
+        assert!(quota.reserve().is_ok());
+
+        match quota.reserve() {
+            Err(Error::QuotaExceeded {
+                limit: 3,
+                used: 3,
+                remaining: 0,
+            }) => {}
+            result => panic!("expected quota to be exceeded, got: {result:?}"),
+        }
+    }
+}
@@ -1,20 +1,42 @@
+use std::collections::HashSet;
+use std::fmt::Display;
+use std::io::{BufReader, Write as _};
+use std::path::Path;
 use std::pin::pin;
+use std::str::FromStr;
+use std::sync::Arc;
 
-use clap::Parser as _;
-use futures_util::stream::{self, StreamExt as _};
+use futures_util::future;
+use futures_util::stream::{self, Stream, StreamExt as _};
+use serde::Serialize;
+use tokio::time::Instant;
 use tracing_subscriber::layer::SubscriberExt as _;
 use tracing_subscriber::util::SubscriberInitExt as _;
+use url::Url;
 
 mod api;
 mod cache;
+mod cassette;
 mod cli;
 mod config;
 mod error;
+mod exchange;
 mod io;
+mod job;
+mod limits;
+mod migrations;
+mod output;
+mod retry;
 
-use api::Client;
-use cli::{Cli, Command, Ordering};
-use config::{APP, Config};
+use api::{
+    BitlinkChanges, BitlinkFields, Breakdown, Clicks, ClicksSummary, Client, ListFilter, Metric,
+    MetricsQuery, VERSION,
+};
+use cache::{BitlinkCache, CacheFilter};
+use cli::{BatchArgs, CacheCommand, Cli, Command, Format, GroupsCommand, Ordering, OrgsCommand};
+use config::{APP, CacheConfig, Config, Options};
+use job::{Job, JobSpec};
+use output::{Compact, Failures, Printer};
 
 macro_rules! crash_if_err {
     ($exp:expr) => {
@@ -28,6 +50,406 @@ macro_rules! crash_if_err {
     };
 }
 
+/// Settings of how the results of a command are processed
+#[derive(Debug)]
+struct Run {
+    /// Point in time when the command is interrupted (see `--deadline`)
+    deadline: Option<Instant>,
+    /// Report of failed inputs under the `--keep-going` mode (otherwise the first failure exits)
+    failures: Option<Arc<Failures>>,
+    /// Job that journals processed inputs (see `--job`)
+    job: Option<Arc<Job>>,
+}
+
+/// Input of a batch identified by its sequence number (see [`Job`])
+type Entry = (u64, String);
+
+impl Run {
+    #[inline]
+    fn new(deadline: Option<Instant>) -> Self {
+        Self {
+            deadline,
+            failures: None,
+            job: None,
+        }
+    }
+
+    /// Apply the options of a batch command
+    fn batch(self, args: &BatchArgs) -> Self {
+        if args.keep_going {
+            self.keep_going(args.errors_file.as_deref(), false)
+        } else {
+            self
+        }
+    }
+
+    /// Report failures instead of exiting on the first one, optionally into an errors file
+    fn keep_going(mut self, errors_file: Option<&Path>, append: bool) -> Self {
+        let failures = crash_if_err! { Failures::new(errors_file, append) };
+        self.failures = Some(Arc::new(failures));
+        self
+    }
+
+    /// Journal the processed inputs of given job (if any)
+    fn job(mut self, job: Option<Job>) -> Self {
+        self.job = job.map(Arc::new);
+        self
+    }
+
+    /// Acknowledge a processed input in the job journal (if running as a job)
+    async fn acknowledge(&self, (seq, input): Entry, outcome: Result<Option<&str>, &str>) {
+        if let Some(ref job) = self.job {
+            crash_if_err! { job.acknowledge(seq, &input, outcome).await };
+        }
+    }
+
+    /// Report a failure, exiting unless in the `--keep-going` mode
+    fn fail(failures: Option<&Failures>, error: error::Error) {
+        match failures {
+            Some(failures) => crash_if_err! { failures.report(&error) },
+            None => {
+                eprintln!("{APP}: {error}");
+                std::process::exit(1);
+            }
+        }
+    }
+
+    /// Exit with a status code that tells apart partial (3) and total (1) failure (if any)
+    fn exit(&self, succeeded: usize) {
+        let failed = self
+            .failures
+            .as_ref()
+            .map_or(0, |failures| failures.count());
+
+        if failed > 0 {
+            eprintln!("{APP}: {failed} input(s) failed, {succeeded} succeeded");
+            std::process::exit(if succeeded == 0 { 1 } else { 3 });
+        }
+    }
+}
+
+/// Use inputs given as program arguments or, if there are none, try to read them from stdin
+///
+/// Invalid inputs read from stdin are reported as failures of the `run`.
+#[inline]
+fn input<T>(args: Vec<T>, run: &Run) -> Option<impl Stream<Item = T> + use<T>>
+where
+    T: FromStr + 'static,
+    <T as FromStr>::Err: std::error::Error + Send + Sync,
+{
+    Some(indexed_input(args, run)?.map(|(_, input)| input))
+}
+
+/// Use inputs like [`input`], each with its sequence number (offset) in the input
+///
+/// Note that the offsets count the invalid inputs as well, so that these don't shift the rest.
+fn indexed_input<T>(args: Vec<T>, run: &Run) -> Option<impl Stream<Item = (u64, T)> + use<T>>
+where
+    T: FromStr + 'static,
+    <T as FromStr>::Err: std::error::Error + Send + Sync,
+{
+    if args.is_empty() {
+        let failures = run.failures.clone();
+        let inputs = io::read_input::<T>()?;
+        Some(
+            stream::iter(0..)
+                .zip(inputs)
+                .filter_map(move |(seq, input)| {
+                    let input = input.map_err(|error| Run::fail(failures.as_deref(), error));
+                    future::ready(input.ok().map(|input| (seq, input)))
+                })
+                .left_stream(),
+        )
+    } else {
+        Some(stream::iter((0..).zip(args)).right_stream())
+    }
+}
+
+/// Reason of a premature end of the program
+#[derive(Clone, Copy, Debug)]
+enum Interrupt {
+    /// Interrupted by the user (Ctrl-C)
+    Signal,
+    /// The `--deadline` has passed
+    Deadline,
+}
+
+impl Interrupt {
+    /// Wait for an interrupt signal or until given deadline (if any) passes
+    async fn wait(deadline: Option<Instant>) -> Self {
+        let deadline = async {
+            match deadline {
+                Some(deadline) => tokio::time::sleep_until(deadline).await,
+                None => std::future::pending().await,
+            }
+        };
+
+        let signal = async {
+            if tokio::signal::ctrl_c().await.is_err() {
+                std::future::pending::<()>().await;
+            }
+        };
+
+        tokio::select! {
+            _ = signal => Self::Signal,
+            _ = deadline => Self::Deadline,
+        }
+    }
+
+    /// Exit with a status code specific to this interrupt
+    fn exit(self) -> ! {
+        let _ = std::io::stdout().flush();
+
+        match self {
+            Self::Signal => {
+                eprintln!("{APP}: interrupted, pending requests were cancelled");
+                std::process::exit(130);
+            }
+            Self::Deadline => {
+                eprintln!("{APP}: deadline exceeded, pending requests were cancelled");
+                std::process::exit(124);
+            }
+        }
+    }
+}
+
+/// Print all the results with given printer, exiting on the first error (unless `--keep-going`)
+///
+/// On an interrupt (Ctrl-C or `--deadline`), all the requests in flight are cancelled and the
+/// program exits after printing the results received so far.
+#[inline]
+async fn print_all<T>(results: impl Stream<Item = error::Result<T>>, printer: Printer, run: &Run)
+where
+    T: Display + Serialize,
+{
+    print_entries(results.map(|result| (None, result.map(Some))), printer, run).await;
+}
+
+/// Print all the results like [`print_all`], acknowledging their inputs in the job journal
+///
+/// Inputs are acknowledged right after their results have been printed, so in the ordered mode
+/// the acknowledged inputs always form a prefix of the input. Failed inputs are acknowledged only
+/// under the `--keep-going` mode, otherwise they're retried when the job is resumed. Successful
+/// results without any output (`None`) are acknowledged, but not printed.
+async fn print_entries<T>(
+    results: impl Stream<Item = (Option<Entry>, error::Result<Option<T>>)>,
+    mut printer: Printer,
+    run: &Run,
+) where
+    T: Display + Serialize,
+{
+    let mut succeeded = 0;
+
+    let interrupt = {
+        let mut results = pin!(results);
+        let mut interrupt = pin!(Interrupt::wait(run.deadline));
+
+        loop {
+            tokio::select! {
+                interrupt = &mut interrupt => break Some(interrupt),
+                result = results.next() => match result {
+                    Some((entry, Ok(item))) => {
+                        if let Some(ref item) = item {
+                            crash_if_err! { printer.print(item) };
+                        }
+                        succeeded += 1;
+                        if let Some(entry) = entry {
+                            let output = item.as_ref().map(ToString::to_string);
+                            run.acknowledge(entry, Ok(output.as_deref())).await;
+                        }
+                    }
+                    Some((entry, Err(error))) => {
+                        let entry = entry.map(|entry| (entry, error.to_string()));
+                        Run::fail(run.failures.as_deref(), error);
+                        if let Some((entry, error)) = entry {
+                            run.acknowledge(entry, Err(&error)).await;
+                        }
+                    }
+                    None => break None,
+                },
+            }
+        }
+    };
+
+    // NOTE: the results stream (with all the requests in flight) has been dropped at this point
+    if let Some(interrupt) = interrupt {
+        interrupt.exit();
+    }
+
+    run.exit(succeeded);
+}
+
+/// Shorten given URLs and print the results, skipping the inputs already processed by the job
+///
+/// With `unique`, repeated outputs are dropped, i.e. not printed (but their inputs still count as
+/// processed). With `dry_run`, just the plans of how the inputs would be processed
+/// are printed (and nothing is acknowledged).
+async fn shorten(
+    client: &Client,
+    urls: Vec<Url>,
+    fields: BitlinkFields,
+    ordering: Ordering,
+    unique: bool,
+    dry_run: bool,
+    run: &Run,
+) {
+    let Some(urls) = indexed_input(urls, run) else {
+        return;
+    };
+
+    let job = run.job.clone();
+    let urls = urls.filter_map(move |(seq, url)| {
+        let input = url.to_string();
+        let acked = match job {
+            Some(ref job) => crash_if_err! { job.is_acked(seq, &input) },
+            None => false,
+        };
+        future::ready((!acked).then_some(((seq, input), url)))
+    });
+
+    if dry_run {
+        let plans = client.plan_shorten(urls, fields, ordering);
+        let plans = plans.map(|(_, plan)| plan);
+        print_all(plans, Printer::new(Format::Text), run).await;
+        return;
+    }
+
+    // NOTE: outputs printed before a job has been resumed count as well
+    let mut printed = HashSet::new();
+    if unique && let Some(ref job) = run.job {
+        printed.extend(job.outputs().map(ToString::to_string));
+    }
+
+    let results = client
+        .shorten_keyed(urls, fields, ordering)
+        .map(move |(entry, r)| {
+            let r = r.map(|bitlink| match ordering {
+                Ordering::Ordered => bitlink.link.to_string(),
+                Ordering::Unordered => format!("{}\t{}", bitlink.link, bitlink.long_url),
+            });
+            let r = match r {
+                Ok(output) if unique && !printed.insert(output.clone()) => Ok(None),
+                r => r.map(Some),
+            };
+            (Some(entry), r)
+        });
+
+    print_entries(results, Printer::new(Format::Text), run).await;
+}
+
+/// Run a management command of the local cache in given directory
+async fn manage_cache(cmd: CacheCommand, cache_dir: Option<&Path>, run: &Run) {
+    // NOTE: opening the cache applies pending migrations, which `migrate` must control itself
+    let cache = match cmd {
+        CacheCommand::Migrate(_) => BitlinkCache::connect(VERSION, cache_dir).await,
+        _ => BitlinkCache::new(VERSION, cache_dir).await,
+    };
+
+    let cache = &crash_if_err! { cache.ok_or(error::Error::CacheUnavailable) };
+
+    match cmd {
+        CacheCommand::Migrate(args) => migrate_cache(cache, args.check).await,
+
+        CacheCommand::List(args) => {
+            let entries = crash_if_err! { cache.entries(&CacheFilter::from(&args)).await };
+            let entries = stream::iter(entries.into_iter().map(Ok));
+            print_all(entries, Printer::new(args.format), run).await;
+        }
+
+        CacheCommand::Stats(args) => {
+            let stats = crash_if_err! { cache.stats().await };
+            print_all(stream::iter([Ok(stats)]), Printer::new(args.format), run).await;
+        }
+
+        CacheCommand::Rm(args) => {
+            let Some(urls) = input(args.urls, run) else {
+                return;
+            };
+
+            let removed = urls
+                .then(|url| async move {
+                    cache
+                        .remove(url.url())
+                        .await
+                        .map_err(|error| error::Error::from(error).with_input(url))
+                })
+                .flat_map(|removed| match removed {
+                    Ok(entries) => stream::iter(entries.into_iter().map(Ok)).left_stream(),
+                    Err(error) => stream::iter([Err(error)]).right_stream(),
+                });
+
+            print_all(removed, Printer::new(Format::Text), run).await;
+        }
+
+        CacheCommand::Clear => {
+            let removed = crash_if_err! { cache.clear().await };
+            eprintln!("{APP}: removed {removed} cached bitlink(s)");
+        }
+
+        CacheCommand::Vacuum => {
+            let (before, after) = crash_if_err! { cache.vacuum().await };
+            eprintln!("{APP}: vacuumed database ({before} B before, {after} B after)");
+        }
+
+        CacheCommand::Export(args) => {
+            let entries = crash_if_err! { cache.export().await };
+
+            let written = match args.output {
+                Some(path) => std::fs::File::create(path)
+                    .map_err(error::Error::from)
+                    .and_then(|file| exchange::write_entries(file, args.format, &entries)),
+                None => exchange::write_entries(std::io::stdout().lock(), args.format, &entries),
+            };
+
+            crash_if_err! { written };
+            eprintln!("{APP}: exported {} cached bitlink(s)", entries.len());
+        }
+
+        CacheCommand::Import(args) => {
+            let entries = match args.input {
+                Some(path) => std::fs::File::open(path)
+                    .map_err(error::Error::from)
+                    .and_then(|file| exchange::read_entries(BufReader::new(file), args.format)),
+                None => exchange::read_entries(std::io::stdin().lock(), args.format),
+            };
+
+            let entries = crash_if_err! { entries };
+            let summary = crash_if_err! { cache.import(&entries, args.on_conflict).await };
+
+            eprintln!(
+                "{APP}: imported {} new bitlink(s), replaced {} and skipped {}",
+                summary.imported, summary.replaced, summary.skipped
+            );
+        }
+    }
+}
+
+/// Apply pending migrations of the database schema, or with `check`, just list them
+async fn migrate_cache(cache: &BitlinkCache, check: bool) {
+    let version = crash_if_err! { cache.schema_version().await };
+
+    let migrations = if check {
+        crash_if_err! { cache.pending_migrations().await }
+    } else {
+        crash_if_err! { cache.migrate().await }
+    };
+
+    for migration in migrations {
+        println!("{migration}");
+    }
+
+    let (n, latest) = (migrations.len(), migrations::LATEST);
+
+    if check {
+        eprintln!("{APP}: schema version {version} (latest {latest}), {n} pending migration(s)");
+        if n > 0 {
+            std::process::exit(1);
+        }
+    } else {
+        eprintln!("{APP}: migrated schema from version {version} to {latest}, applied {n}");
+    }
+}
+
 fn setup_tracing() {
     let stderr_layer = tracing_subscriber::fmt::layer()
         .with_writer(std::io::stderr)
@@ -35,7 +457,10 @@ fn setup_tracing() {
         .with_line_number(true)
         .compact();
 
-    let env_filter = tracing_subscriber::EnvFilter::from_default_env();
+    // NOTE: warnings (e.g., about stale cached bitlinks) are shown unless `RUST_LOG` says otherwise
+    let env_filter = tracing_subscriber::EnvFilter::builder()
+        .with_default_directive(tracing_subscriber::filter::LevelFilter::WARN.into())
+        .from_env_lossy();
 
     tracing_subscriber::registry()
         .with(stderr_layer)
@@ -47,44 +472,182 @@ fn setup_tracing() {
 async fn main() {
     setup_tracing();
 
-    let cli = Cli::parse();
+    let cli = Cli::parse_args();
+
+    let run = Run::new(cli.deadline().map(|deadline| Instant::now() + deadline));
 
-    let mut cfg = crash_if_err! { cli.config_file().and_then(Config::load) };
-    cfg.override_with(&cli);
+    let config_file = crash_if_err! { cli.config_file().map(|path| path.into_owned()) };
+    let ops = Options::from(&cli);
+    let cmd = Command::from(cli);
+
+    // NOTE: the client would open (and migrate) the cache, which is managed directly instead, and
+    //       so cache commands work without any API credentials
+    if let Command::Cache(cmd) = cmd {
+        let mut cfg = crash_if_err! { CacheConfig::load(&config_file) };
+        cfg.override_with(ops);
+        manage_cache(cmd, cfg.cache_dir.as_deref(), &run).await;
+        return;
+    }
 
-    let cmd = cli.into();
+    let mut cfg = crash_if_err! { Config::load(&config_file) };
+    cfg.override_with(ops);
     cfg.override_with(&cmd);
 
-    let client = Client::new(cfg).await;
+    // NOTE: a resumed job restores the domain and group it has been started with
+    let job = match cmd {
+        Command::Shorten(ref args) => match args.job {
+            Some(ref name) => {
+                let spec = crash_if_err! { JobSpec::new(&cfg, args) };
+                Some(crash_if_err! { Job::start(cfg.cache_dir.as_deref(), name, spec).await })
+            }
+            None => None,
+        },
+        Command::Resume(ref args) => {
+            let job = crash_if_err! { Job::resume(cfg.cache_dir.as_deref(), &args.name).await };
+            job.spec().apply(&mut cfg);
+            Some(job)
+        }
+        _ => None,
+    };
+
+    let client = crash_if_err! { Client::new(cfg).await };
 
     match cmd {
         Command::Shorten(args) => {
-            let urls = if args.urls.is_empty() {
-                let Some(urls) = io::read_input::<url::Url>() else {
-                    return;
-                };
-                urls.map(|url| crash_if_err!(url)).left_stream()
+            let fields = BitlinkFields::from(&args);
+            let run = run.batch(&args.batch).job(job);
+            let ordering = args.batch.ordering;
+            let (unique, dry_run) = (args.unique, args.dry_run);
+            shorten(&client, args.urls, fields, ordering, unique, dry_run, &run).await;
+        }
+
+        Command::Resume(args) => {
+            let Some(job) = job else {
+                return;
+            };
+
+            let spec = job.spec();
+            let fields = spec.fields.clone();
+            let ordering = spec.ordering;
+            let unique = spec.unique;
+
+            let run = if spec.keep_going {
+                run.keep_going(spec.errors_file.as_deref(), true)
             } else {
-                stream::iter(args.urls).right_stream()
+                run
+            };
+
+            let run = run.job(Some(job));
+            let dry_run = args.dry_run;
+            shorten(&client, args.urls, fields, ordering, unique, dry_run, &run).await;
+        }
+
+        Command::Expand(args) => {
+            let run = run.batch(&args.batch);
+            let Some(bitlinks) = input(args.bitlinks, &run) else {
+                return;
             };
 
-            let mut results = pin!(client.shorten(urls, args.ordering));
+            let ordering = args.batch.ordering;
+            let results = client.expand(bitlinks, ordering);
 
-            match args.ordering {
-                Ordering::Ordered => {
-                    while let Some(result) = results.next().await {
-                        let bitlink = crash_if_err! { result };
-                        println!("{}", bitlink.link);
-                    }
+            let results = match ordering {
+                Ordering::Ordered => results
+                    .map(|r| r.map(|bitlink| bitlink.long_url.to_string()))
+                    .left_stream(),
+                Ordering::Unordered => results
+                    .map(|r| r.map(|bitlink| format!("{}\t{}", bitlink.long_url, bitlink.link)))
+                    .right_stream(),
+            };
+
+            print_all(results, Printer::new(Format::Text), &run).await;
+        }
+
+        Command::Info(args) => {
+            let run = run.batch(&args.batch);
+            let Some(bitlinks) = input(args.bitlinks, &run) else {
+                return;
+            };
+
+            let results = client.info(bitlinks, args.batch.ordering);
+            print_all(results, Printer::blocks(args.format), &run).await;
+        }
+
+        Command::Update(args) => {
+            let changes = BitlinkChanges::from(&args);
+            let run = run.batch(&args.batch);
+
+            let Some(bitlinks) = input(args.bitlinks, &run) else {
+                return;
+            };
+
+            let ordering = args.batch.ordering;
+
+            if args.dry_run {
+                let plans = client.plan_update(bitlinks, changes, ordering);
+                print_all(plans, Printer::blocks(args.format), &run).await;
+                return;
+            }
+
+            let results = client.update(bitlinks, changes, ordering);
+            print_all(results, Printer::blocks(args.format), &run).await;
+        }
+
+        Command::Stats(stats) => {
+            let metric = stats.metric();
+            let metrics = stats.into_metrics();
+
+            let run = run.batch(&metrics.batch);
+            let query = MetricsQuery::from(&metrics);
+            let ordering = metrics.batch.ordering;
+            let printer = Printer::new(metrics.format);
+
+            let Some(bitlinks) = input(metrics.bitlinks, &run) else {
+                return;
+            };
+
+            match metric {
+                Metric::Clicks => {
+                    let results = client.metrics::<_, Clicks>(bitlinks, metric, query, ordering);
+                    print_all(results, printer, &run).await;
                 }
 
-                Ordering::Unordered => {
-                    while let Some(result) = results.next().await {
-                        let bitlink = crash_if_err! { result };
-                        println!("{}\t{}", bitlink.link, bitlink.long_url);
-                    }
+                Metric::ClicksSummary => {
+                    let results =
+                        client.metrics::<_, ClicksSummary>(bitlinks, metric, query, ordering);
+                    print_all(results, printer, &run).await;
+                }
+
+                _ => {
+                    let results = client.metrics::<_, Breakdown>(bitlinks, metric, query, ordering);
+                    print_all(results, printer, &run).await;
                 }
             }
         }
+
+        Command::List(args) => {
+            let bitlinks = client
+                .list(ListFilter::from(&args))
+                .map(|info| info.map(Compact));
+            let bitlinks = match args.limit {
+                Some(limit) => bitlinks.take(limit).left_stream(),
+                None => bitlinks.right_stream(),
+            };
+            print_all(bitlinks, Printer::new(args.format), &run).await;
+        }
+
+        Command::Groups(GroupsCommand::List(args)) => {
+            let groups = crash_if_err! { client.groups(args.organization_guid.as_deref()).await };
+            let groups = stream::iter(groups.into_iter().map(Ok));
+            print_all(groups, Printer::new(args.format), &run).await;
+        }
+
+        Command::Orgs(OrgsCommand::List(args)) => {
+            let orgs = crash_if_err! { client.organizations().await };
+            let orgs = stream::iter(orgs.into_iter().map(Ok));
+            print_all(orgs, Printer::new(args.format), &run).await;
+        }
+
+        Command::Cache(_) => unreachable!("cache commands run before the client is initialized"),
     }
 }
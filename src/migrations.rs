@@ -0,0 +1,262 @@
+use sqlx::sqlite::SqlitePool;
+use tracing::{debug, instrument};
+
+use crate::error::{Error, Result};
+
+/// Versioned change of the database schema
+#[derive(Debug)]
+pub struct Migration {
+    /// Schema version after applying this migration
+    pub version: u32,
+    pub description: &'static str,
+    sql: &'static str,
+}
+
+impl std::fmt::Display for Migration {
+    #[inline]
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(f, "{}\t{}", self.version, self.description)
+    }
+}
+
+/// All the migrations of the database schema, ordered by their versions
+///
+/// NOTE: Migrations must never change once released, new ones must be appended with the next
+/// version instead.
+pub const MIGRATIONS: &[Migration] = &[
+    Migration {
+        version: 1,
+        description: "create shorten table",
+        sql: r#"
+            CREATE TABLE IF NOT EXISTS shorten (
+              id TEXT NOT NULL UNIQUE,
+              link TEXT NOT NULL,
+              long_url TEXT NOT NULL,
+              domain TEXT,
+              group_guid TEXT NOT NULL
+            );
+
+            CREATE UNIQUE INDEX IF NOT EXISTS ix_shorten
+            ON shorten (group_guid, domain, long_url);
+        "#,
+    },
+    Migration {
+        version: 2,
+        description: "add options to the shorten key",
+        sql: r#"
+            ALTER TABLE shorten ADD COLUMN options TEXT NOT NULL DEFAULT '';
+
+            DROP INDEX IF EXISTS ix_shorten;
+
+            CREATE UNIQUE INDEX ix_shorten
+            ON shorten (group_guid, domain, long_url, options);
+        "#,
+    },
+    Migration {
+        version: 3,
+        description: "create groups table",
+        sql: r#"
+            CREATE TABLE IF NOT EXISTS groups (
+              guid TEXT NOT NULL PRIMARY KEY,
+              name TEXT NOT NULL,
+              organization_guid TEXT,
+              is_active BOOLEAN NOT NULL,
+              role TEXT
+            );
+
+            CREATE INDEX IF NOT EXISTS ix_groups_name ON groups (name);
+        "#,
+    },
+    Migration {
+        version: 4,
+        description: "create kv table",
+        sql: r#"
+            CREATE TABLE IF NOT EXISTS kv (
+              key TEXT NOT NULL PRIMARY KEY,
+              value TEXT NOT NULL,
+              updated_at INTEGER NOT NULL
+            );
+        "#,
+    },
+    Migration {
+        version: 5,
+        description: "create jobs and journal tables",
+        sql: r#"
+            CREATE TABLE IF NOT EXISTS jobs (
+              name TEXT NOT NULL PRIMARY KEY,
+              spec TEXT NOT NULL,
+              created_at INTEGER NOT NULL
+            );
+
+            CREATE TABLE IF NOT EXISTS journal (
+              job TEXT NOT NULL REFERENCES jobs (name) ON DELETE CASCADE,
+              seq INTEGER NOT NULL,
+              input TEXT NOT NULL,
+              output TEXT,
+              error TEXT,
+              PRIMARY KEY (job, seq)
+            );
+        "#,
+    },
+    Migration {
+        version: 6,
+        description: "add cached_at to shorten",
+        sql: r#"
+            ALTER TABLE shorten ADD COLUMN cached_at INTEGER NOT NULL DEFAULT 0;
+
+            -- NOTE: the age of existing entries is unknown, so it starts with the migration
+            UPDATE shorten SET cached_at = CAST(strftime('%s', 'now') AS INTEGER);
+        "#,
+    },
+    Migration {
+        version: 7,
+        description: "drop unique constraint on shorten id",
+        sql: r#"
+            -- NOTE: the same bitlink is cached for each set of options it's been requested with
+            CREATE TABLE shorten_v7 (
+              id TEXT NOT NULL,
+              link TEXT NOT NULL,
+              long_url TEXT NOT NULL,
+              domain TEXT,
+              group_guid TEXT NOT NULL,
+              options TEXT NOT NULL DEFAULT '',
+              cached_at INTEGER NOT NULL DEFAULT 0
+            );
+
+            INSERT INTO shorten_v7 (id, link, long_url, domain, group_guid, options, cached_at)
+            SELECT id, link, long_url, domain, group_guid, options, cached_at
+            FROM shorten
+            ORDER BY rowid;
+
+            DROP TABLE shorten;
+
+            ALTER TABLE shorten_v7 RENAME TO shorten;
+
+            CREATE UNIQUE INDEX ix_shorten
+            ON shorten (group_guid, domain, long_url, options);
+
+            CREATE INDEX ix_shorten_id ON shorten (id);
+        "#,
+    },
+];
+
+/// Schema version of the database after applying all the [`MIGRATIONS`]
+pub const LATEST: u32 = MIGRATIONS[MIGRATIONS.len() - 1].version;
+
+/// Current schema version of the database
+///
+/// Databases created before the schema has been versioned lack the `metadata` table, so their
+/// version is derived from the `shorten` table (all later migrations are idempotent).
+#[instrument(level = "debug", skip_all, ret)]
+pub async fn schema_version(pool: &SqlitePool) -> sqlx::Result<u32> {
+    let (versioned,): (bool,) = sqlx::query_as(
+        "SELECT COUNT(*) > 0 FROM sqlite_master WHERE type = 'table' AND name = 'metadata'",
+    )
+    .fetch_one(pool)
+    .await?;
+
+    if versioned {
+        let version: Option<String> =
+            sqlx::query_scalar("SELECT value FROM metadata WHERE key = 'schema_version'")
+                .fetch_optional(pool)
+                .await?;
+
+        return Ok(version.and_then(|v| v.parse().ok()).unwrap_or_default());
+    }
+
+    let columns: Vec<String> = sqlx::query_scalar("SELECT name FROM pragma_table_info('shorten')")
+        .fetch_all(pool)
+        .await?;
+
+    Ok(match columns.iter().any(|column| column == "options") {
+        true => 2,
+        false if !columns.is_empty() => 1,
+        false => 0,
+    })
+}
+
+/// Migrations that have not been applied to the database yet
+///
+/// Fails if the database has been created by a newer version of this program.
+pub async fn pending(pool: &SqlitePool) -> Result<&'static [Migration]> {
+    let version = schema_version(pool).await?;
+
+    if version > LATEST {
+        return Err(Error::UnsupportedSchema {
+            version,
+            supported: LATEST,
+        });
+    }
+
+    let applied = MIGRATIONS.partition_point(|migration| migration.version <= version);
+    Ok(&MIGRATIONS[applied..])
+}
+
+/// Apply all the pending migrations in order, returning the applied ones
+///
+/// Each migration runs in its own transaction together with the update of the schema version.
+#[instrument(level = "debug", skip_all)]
+pub async fn migrate(pool: &SqlitePool) -> Result<&'static [Migration]> {
+    let pending = pending(pool).await?;
+
+    for migration in pending {
+        // NOTE: take the write lock up front, so that concurrent processes migrate one by one
+        let mut tx = pool.begin_with("BEGIN IMMEDIATE").await?;
+
+        sqlx::query(
+            r#"
+            CREATE TABLE IF NOT EXISTS metadata (
+              key TEXT NOT NULL PRIMARY KEY,
+              value TEXT NOT NULL
+            )
+            "#,
+        )
+        .execute(&mut *tx)
+        .await?;
+
+        let version: Option<String> =
+            sqlx::query_scalar("SELECT value FROM metadata WHERE key = 'schema_version'")
+                .fetch_optional(&mut *tx)
+                .await?;
+
+        let version = version.and_then(|v| v.parse::<u32>().ok());
+
+        if version.is_some_and(|version| version >= migration.version) {
+            debug!(
+                version = migration.version,
+                "migration applied by another process"
+            );
+            continue;
+        }
+
+        debug!(
+            version = migration.version,
+            description = migration.description,
+            "applying migration"
+        );
+
+        sqlx::query(migration.sql).execute(&mut *tx).await?;
+
+        sqlx::query("INSERT OR REPLACE INTO metadata (key, value) VALUES ('schema_version', $1)")
+            .bind(migration.version.to_string())
+            .execute(&mut *tx)
+            .await?;
+
+        tx.commit().await?;
+    }
+
+    Ok(pending)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use rstest::*;
+
+    #[rstest]
+    fn migrations_are_ordered() {
+        let versions = MIGRATIONS.iter().map(|m| m.version).collect::<Vec<_>>();
+        let expected = (1..=LATEST).collect::<Vec<_>>();
+        assert_eq!(expected, versions);
+    }
+}
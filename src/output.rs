@@ -0,0 +1,198 @@
+use std::fmt::Display;
+use std::fs::File;
+use std::io::Write as _;
+use std::path::Path;
+use std::sync::Mutex;
+use std::sync::atomic::{AtomicUsize, Ordering};
+
+use serde::Serialize;
+
+use crate::api::BitlinkInfo;
+use crate::cli::Format;
+use crate::config::APP;
+use crate::error::{Error, Result};
+
+/// Print individual output items in given format
+///
+/// Text items are rendered with their [`Display`] implementation (items that render as an empty
+/// string are skipped), JSON items are printed one per line (JSONL).
+#[derive(Debug)]
+pub struct Printer {
+    format: Format,
+    blocks: bool,
+    empty: bool,
+}
+
+impl Printer {
+    /// Printer of items that render as individual lines (e.g., tab-separated records)
+    #[inline]
+    pub fn new(format: Format) -> Self {
+        Self {
+            format,
+            blocks: false,
+            empty: true,
+        }
+    }
+
+    /// Printer of items that render as multi-line blocks, which are separated by an empty line
+    #[inline]
+    pub fn blocks(format: Format) -> Self {
+        Self {
+            format,
+            blocks: true,
+            empty: true,
+        }
+    }
+
+    pub fn print<T>(&mut self, item: &T) -> Result<()>
+    where
+        T: Display + Serialize,
+    {
+        match self.format {
+            Format::Text => {
+                let item = item.to_string();
+                if item.is_empty() {
+                    return Ok(());
+                }
+                if self.blocks && !self.empty {
+                    println!();
+                }
+                println!("{item}");
+            }
+            Format::Json => println!("{}", serde_json::to_string(item)?),
+        }
+
+        self.empty = false;
+
+        Ok(())
+    }
+}
+
+/// Compact single-line text rendering of an item (the JSON output remains complete)
+#[derive(Debug, Serialize)]
+#[serde(transparent)]
+pub struct Compact<T>(pub T);
+
+impl Display for Compact<BitlinkInfo> {
+    #[inline]
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        let Self(info) = self;
+        let title = info.title.as_deref().unwrap_or_default();
+        write!(f, "{}\t{}\t{title}", info.link, info.long_url)
+    }
+}
+
+/// Report of inputs that failed under the `--keep-going` mode
+///
+/// Failures are printed to stderr or, if there's an errors file, written to it as JSON lines.
+#[derive(Debug)]
+pub struct Failures {
+    file: Option<Mutex<File>>,
+    count: AtomicUsize,
+}
+
+#[derive(Debug, Serialize)]
+struct Failure<'a> {
+    input: Option<&'a str>,
+    kind: &'static str,
+    error: String,
+}
+
+impl Failures {
+    /// Create new report, optionally appending to an existing errors file (e.g., of a resumed job)
+    pub fn new(errors_file: Option<&Path>, append: bool) -> Result<Self> {
+        let file = match errors_file {
+            Some(path) => {
+                let file = File::options()
+                    .write(true)
+                    .create(true)
+                    .append(append)
+                    .truncate(!append)
+                    .open(path)?;
+                Some(Mutex::new(file))
+            }
+            None => None,
+        };
+
+        Ok(Self {
+            file,
+            count: AtomicUsize::new(0),
+        })
+    }
+
+    /// Number of failures reported so far
+    #[inline]
+    pub fn count(&self) -> usize {
+        self.count.load(Ordering::Acquire)
+    }
+
+    pub fn report(&self, error: &Error) -> Result<()> {
+        self.count.fetch_add(1, Ordering::AcqRel);
+
+        let Some(ref file) = self.file else {
+            eprintln!("{APP}: {error}");
+            return Ok(());
+        };
+
+        let failure = Failure {
+            input: error.input(),
+            kind: error.kind(),
+            error: match error {
+                Error::Input { source, .. } => source.to_string(),
+                error => error.to_string(),
+            },
+        };
+
+        let mut file = file.lock().expect("errors file lock");
+        serde_json::to_writer(&mut *file, &failure)?;
+        writeln!(file)?;
+
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use rstest::*;
+
+    #[rstest]
+    fn report_failures() {
+        let dir = tempfile::tempdir().expect("temp dir");
+        let path = dir.path().join("errors.jsonl");
+
+        let failures = Failures::new(Some(&path), false).expect("errors file");
+
+        let errors = [
+            Error::KeywordTaken("bit.ly/taken".to_string()).with_input("https://example.com"),
+            Error::Offline("expand"),
+        ];
+
+        for error in &errors {
+            failures.report(error).expect("reported failure");
+        }
+
+        assert_eq!(2, failures.count());
+
+        let actual = std::fs::read_to_string(&path).expect("read errors file");
+        let actual = actual
+            .lines()
+            .map(|line| serde_json::from_str::<serde_json::Value>(line).expect("JSON line"))
+            .collect::<Vec<_>>();
+
+        let expected = vec![
+            serde_json::json!({
+                "input": "https://example.com",
+                "kind": "keyword_taken",
+                "error": "custom bitlink 'bit.ly/taken' is already taken, try a different keyword",
+            }),
+            serde_json::json!({
+                "input": null,
+                "kind": "offline",
+                "error": "operation 'expand' could not complete under offline mode",
+            }),
+        ];
+
+        assert_eq!(expected, actual);
+    }
+}
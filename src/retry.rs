@@ -0,0 +1,219 @@
+use std::hash::{BuildHasher as _, RandomState};
+use std::num::NonZeroU32;
+use std::time::{Duration, SystemTime};
+
+use reqwest::StatusCode;
+use reqwest::header::{HeaderMap, RETRY_AFTER};
+use serde::Deserialize;
+
+/// Policy of retrying API requests that failed with a transient error
+///
+/// Transient errors are rate limiting (`429`), server-side failures (`500`, `502`, `503` and
+/// `504`) and connection errors or timeouts. Delays between attempts grow exponentially from
+/// `base_delay_ms` up to `max_delay_ms`, unless the server asks for a specific delay via the
+/// `Retry-After` header.
+#[derive(Clone, Debug, Deserialize, PartialEq, Eq)]
+#[serde(default)]
+pub struct RetryPolicy {
+    /// Maximum number of attempts of each request, including the first one (`1` disables retries)
+    pub max_attempts: NonZeroU32,
+
+    /// Delay (in milliseconds) before the first retry, which doubles with each next attempt
+    pub base_delay_ms: u64,
+
+    /// Upper bound (in milliseconds) of the exponential backoff delay (and of `Retry-After`)
+    pub max_delay_ms: u64,
+
+    /// Randomize backoff delays to spread out retries of concurrent requests
+    pub jitter: bool,
+}
+
+impl Default for RetryPolicy {
+    fn default() -> Self {
+        Self {
+            max_attempts: NonZeroU32::new(4).expect("non-zero attempts"),
+            base_delay_ms: 500,
+            max_delay_ms: 30_000,
+            jitter: true,
+        }
+    }
+}
+
+impl RetryPolicy {
+    /// Returns `true` if there are any attempts left after given number of `attempts`
+    #[inline]
+    pub fn can_retry(&self, attempts: u32) -> bool {
+        attempts < self.max_attempts.get()
+    }
+
+    /// Returns `true` if a response with given status code is worth retrying
+    #[inline]
+    pub fn is_transient(status: StatusCode) -> bool {
+        matches!(
+            status,
+            StatusCode::TOO_MANY_REQUESTS
+                | StatusCode::INTERNAL_SERVER_ERROR
+                | StatusCode::BAD_GATEWAY
+                | StatusCode::SERVICE_UNAVAILABLE
+                | StatusCode::GATEWAY_TIMEOUT
+        )
+    }
+
+    /// Returns `true` if a response with given status code is worth retrying for a request that is
+    /// either `idempotent` or not
+    ///
+    /// Requests that are not idempotent might have been processed by the server even if it has
+    /// failed, so these are retried only when rate limited (`429`), i.e. when they were rejected
+    /// before being processed.
+    #[inline]
+    pub fn is_retryable(status: StatusCode, idempotent: bool) -> bool {
+        if idempotent {
+            Self::is_transient(status)
+        } else {
+            status == StatusCode::TOO_MANY_REQUESTS
+        }
+    }
+
+    /// Delay before the next attempt given the number of failed `attempts` so far
+    ///
+    /// The `Retry-After` header of the last response (if present) takes precedence over the
+    /// exponential backoff, but it's still capped at `max_delay_ms`.
+    pub fn delay(&self, attempts: u32, headers: Option<&HeaderMap>) -> Duration {
+        match headers.and_then(retry_after) {
+            Some(delay) => delay.min(Duration::from_millis(self.max_delay_ms)),
+            None => self.backoff(attempts),
+        }
+    }
+
+    fn backoff(&self, attempts: u32) -> Duration {
+        let exp = attempts.saturating_sub(1).min(u64::BITS - 1);
+        let delay = self
+            .base_delay_ms
+            .saturating_mul(1 << exp)
+            .min(self.max_delay_ms);
+
+        // NOTE: "equal jitter" keeps at least half of the delay
+        let delay = if self.jitter {
+            let half = delay / 2;
+            half + random() % (half + 1)
+        } else {
+            delay
+        };
+
+        Duration::from_millis(delay)
+    }
+}
+
+/// Parse the `Retry-After` header, which is either a number of seconds or an HTTP date
+fn retry_after(headers: &HeaderMap) -> Option<Duration> {
+    let value = headers.get(RETRY_AFTER)?.to_str().ok()?.trim();
+
+    if let Ok(secs) = value.parse::<u64>() {
+        return Some(Duration::from_secs(secs));
+    }
+
+    let at = httpdate::parse_http_date(value).ok()?;
+
+    // NOTE: a date in the past means that the request can be retried right away
+    Some(
+        at.duration_since(SystemTime::now())
+            .unwrap_or(Duration::ZERO),
+    )
+}
+
+/// Cheap source of randomness (not suitable for anything else than jitter)
+#[inline]
+fn random() -> u64 {
+    RandomState::new().hash_one(SystemTime::now())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use rstest::*;
+
+    use reqwest::header::HeaderValue;
+
+    fn policy(jitter: bool) -> RetryPolicy {
+        RetryPolicy {
+            max_attempts: NonZeroU32::new(3).unwrap(),
+            base_delay_ms: 100,
+            max_delay_ms: 1_000,
+            jitter,
+        }
+    }
+
+    #[rstest]
+    #[case(1, 100)]
+    #[case(2, 200)]
+    #[case(3, 400)]
+    #[case(5, 1_000)]
+    #[case(100, 1_000)]
+    fn exponential_backoff(#[case] attempts: u32, #[case] expected: u64) {
+        let expected = Duration::from_millis(expected);
+        assert_eq!(expected, policy(false).delay(attempts, None));
+
+        let actual = policy(true).delay(attempts, None);
+        assert!(
+            expected / 2 <= actual && actual <= expected,
+            "jittered delay {actual:?} out of bounds of {expected:?}"
+        );
+    }
+
+    #[rstest]
+    #[case("7", Some(Duration::from_secs(7)))]
+    #[case(" 0 ", Some(Duration::ZERO))]
+    #[case("Wed, 21 Oct 2015 07:28:00 GMT", Some(Duration::ZERO))]
+    #[case("soon", None)]
+    fn parse_retry_after(#[case] value: &str, #[case] expected: Option<Duration>) {
+        let mut headers = HeaderMap::new();
+        headers.insert(RETRY_AFTER, HeaderValue::from_str(value).unwrap());
+        assert_eq!(expected, retry_after(&headers));
+    }
+
+    #[rstest]
+    fn retry_after_takes_precedence() {
+        let mut headers = HeaderMap::new();
+        headers.insert(RETRY_AFTER, HeaderValue::from_static("3"));
+
+        let policy = RetryPolicy {
+            max_delay_ms: 10_000,
+            ..policy(false)
+        };
+
+        assert_eq!(Duration::from_secs(3), policy.delay(1, Some(&headers)));
+    }
+
+    #[rstest]
+    fn retry_after_is_capped() {
+        let mut headers = HeaderMap::new();
+        headers.insert(RETRY_AFTER, HeaderValue::from_static("86400"));
+        assert_eq!(
+            Duration::from_millis(1_000),
+            policy(false).delay(1, Some(&headers))
+        );
+    }
+
+    #[rstest]
+    #[case(StatusCode::TOO_MANY_REQUESTS, true, true)]
+    #[case(StatusCode::TOO_MANY_REQUESTS, false, true)]
+    #[case(StatusCode::SERVICE_UNAVAILABLE, true, true)]
+    #[case(StatusCode::SERVICE_UNAVAILABLE, false, false)]
+    #[case(StatusCode::GATEWAY_TIMEOUT, false, false)]
+    #[case(StatusCode::BAD_REQUEST, true, false)]
+    fn retryable_statuses(
+        #[case] status: StatusCode,
+        #[case] idempotent: bool,
+        #[case] expected: bool,
+    ) {
+        assert_eq!(expected, RetryPolicy::is_retryable(status, idempotent));
+    }
+
+    #[rstest]
+    fn retry_attempts() {
+        let policy = policy(false);
+        assert!(policy.can_retry(1));
+        assert!(policy.can_retry(2));
+        assert!(!policy.can_retry(3));
+    }
+}